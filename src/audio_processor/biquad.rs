@@ -0,0 +1,329 @@
+use std::f64::consts::PI;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Q factor of a second-order Butterworth response, ffmpeg's default filter width
+pub const BUTTERWORTH_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// responses of the second-order filters, with coefficients from the RBJ audio EQ cookbook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BiquadKind {
+    Lowpass,
+    Highpass,
+    /// bandpass with a constant 0 dB peak gain
+    Bandpass,
+    Notch,
+    LowShelf,
+    HighShelf,
+    /// peaking EQ boosting or cutting around the center frequency
+    Peaking,
+}
+
+impl BiquadKind {
+    /// whether the response takes a gain in dB
+    pub fn has_gain(self) -> bool {
+        matches!(self, BiquadKind::LowShelf | BiquadKind::HighShelf | BiquadKind::Peaking)
+    }
+
+    /// Q factor used when none is given, matching ffmpeg's filter defaults
+    pub fn default_q(self) -> f64 {
+        match self {
+            BiquadKind::Lowpass | BiquadKind::Highpass => BUTTERWORTH_Q,
+            BiquadKind::Bandpass | BiquadKind::Notch | BiquadKind::LowShelf | BiquadKind::HighShelf => 0.5,
+            BiquadKind::Peaking => 1.0,
+        }
+    }
+
+    /// name of the equivalent ffmpeg filter
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            BiquadKind::Lowpass => "lowpass",
+            BiquadKind::Highpass => "highpass",
+            BiquadKind::Bandpass => "bandpass",
+            BiquadKind::Notch => "bandreject",
+            BiquadKind::LowShelf => "lowshelf",
+            BiquadKind::HighShelf => "highshelf",
+            BiquadKind::Peaking => "equalizer",
+        }
+    }
+}
+
+/// a filter response together with its parameters, independent of the sample rate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BiquadSpec {
+    pub kind: BiquadKind,
+    /// cutoff or center frequency in Hz
+    pub frequency: f64,
+    /// gain in dB, only used by shelves and peaking EQ
+    pub gain_db: f64,
+    pub q: f64,
+}
+
+impl BiquadSpec {
+    /// parses the CLI form `HZ[:Q]`, or `HZ:GAIN[:Q]` for responses taking a gain (e.g. `--eq 3000:+2:1.0`)
+    pub fn parse(kind: BiquadKind, value: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = value.split(':').collect();
+        let expected = if kind.has_gain() { "HZ:GAIN[:Q]" } else { "HZ[:Q]" };
+        let (frequency, gain_db, q) = match (kind.has_gain(), fields.as_slice()) {
+            (false, [frequency]) => (*frequency, None, None),
+            (false, [frequency, q]) => (*frequency, None, Some(*q)),
+            (true, [frequency, gain]) => (*frequency, Some(*gain), None),
+            (true, [frequency, gain, q]) => (*frequency, Some(*gain), Some(*q)),
+            _ => return Err(format!("expected {}, got '{}'", expected, value)),
+        };
+
+        let number = |field: &str| field.trim().parse::<f64>().map_err(|_| format!("'{}' is not a number", field));
+        let spec = Self {
+            kind,
+            frequency: number(frequency)?,
+            gain_db: gain_db.map(number).transpose()?.unwrap_or(0.0),
+            q: q.map(number).transpose()?.unwrap_or(kind.default_q()),
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// checks that frequency and Q are positive and finite
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.frequency.is_finite() && self.frequency > 0.0) {
+            return Err(format!("frequency must be positive, got {}", self.frequency));
+        }
+        if !(self.q.is_finite() && self.q > 0.0) {
+            return Err(format!("Q must be positive, got {}", self.q));
+        }
+        if !self.gain_db.is_finite() {
+            return Err(format!("gain must be finite, got {}", self.gain_db));
+        }
+        Ok(())
+    }
+
+    /// the equivalent ffmpeg filter, used when the job is routed to ffmpeg
+    pub fn ffmpeg_filter(&self) -> String {
+        let mut filter = format!("{}=f={}:t=q:w={}", self.kind.ffmpeg_name(), self.frequency, self.q);
+        if self.kind.has_gain() {
+            filter.push_str(&format!(":g={}", self.gain_db));
+        }
+        filter
+    }
+}
+
+/// a single-channel second-order IIR filter in transposed direct form II
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// the frequency of `spec` must lie below the Nyquist frequency of `sample_rate`
+    pub fn new(spec: &BiquadSpec, sample_rate: u32) -> Self {
+        let w0 = 2.0 * PI * spec.frequency / sample_rate as f64;
+        let (sin_w0, cos_w0) = w0.sin_cos();
+        let alpha = sin_w0 / (2.0 * spec.q);
+        // amplitude of the shelf or peak
+        let a = 10f64.powf(spec.gain_db / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match spec.kind {
+            BiquadKind::Lowpass => (
+                (1.0 - cos_w0) / 2.0, 1.0 - cos_w0, (1.0 - cos_w0) / 2.0,
+                1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+            ),
+            BiquadKind::Highpass => (
+                (1.0 + cos_w0) / 2.0, -(1.0 + cos_w0), (1.0 + cos_w0) / 2.0,
+                1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+            ),
+            BiquadKind::Bandpass => (
+                alpha, 0.0, -alpha,
+                1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+            ),
+            BiquadKind::Notch => (
+                1.0, -2.0 * cos_w0, 1.0,
+                1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+            ),
+            BiquadKind::Peaking => (
+                1.0 + alpha * a, -2.0 * cos_w0, 1.0 - alpha * a,
+                1.0 + alpha / a, -2.0 * cos_w0, 1.0 - alpha / a,
+            ),
+            BiquadKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - shelf),
+                (a + 1.0) + (a - 1.0) * cos_w0 + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - shelf,
+            ),
+            BiquadKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - shelf),
+                (a + 1.0) - (a - 1.0) * cos_w0 + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - shelf,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// filters one sample, carrying the state over to the next call
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let x = sample as f64;
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y as f32
+    }
+}
+
+/// runs one cascade of filters per channel over interleaved samples, processing the channels in parallel
+/// `cascades` holds one cascade per channel, each carrying its own state
+pub fn process_interleaved(cascades: &mut [Vec<Biquad>], samples: &mut [f32]) {
+    let channels = cascades.len();
+    if channels == 0 {
+        return;
+    }
+
+    // deinterleaving so that every channel can be filtered independently
+    let mut planes: Vec<Vec<f32>> = (0..channels)
+        .map(|channel| samples.iter().skip(channel).step_by(channels).copied().collect())
+        .collect();
+
+    planes
+        .par_iter_mut()
+        .zip(cascades.par_iter_mut())
+        .for_each(|(plane, cascade)| {
+            for filter in cascade.iter_mut() {
+                plane.iter_mut().for_each(|sample| *sample = filter.process(*sample));
+            }
+        });
+
+    for (channel, plane) in planes.iter().enumerate() {
+        for (frame, &sample) in plane.iter().enumerate() {
+            samples[frame * channels + channel] = sample;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    // magnitude response of the filter at `frequency` in dB
+    fn response_db(spec: BiquadSpec, frequency: f64) -> f64 {
+        let filter = Biquad::new(&spec, RATE);
+        let w = 2.0 * PI * frequency / RATE as f64;
+        // H(z) evaluated on the unit circle, z^-1 = e^-jw
+        let (c1, s1, c2, s2) = (w.cos(), -w.sin(), (2.0 * w).cos(), -(2.0 * w).sin());
+        let numerator = ((filter.b0 + filter.b1 * c1 + filter.b2 * c2).powi(2) + (filter.b1 * s1 + filter.b2 * s2).powi(2)).sqrt();
+        let denominator = ((1.0 + filter.a1 * c1 + filter.a2 * c2).powi(2) + (filter.a1 * s1 + filter.a2 * s2).powi(2)).sqrt();
+        20.0 * (numerator / denominator).log10()
+    }
+
+    fn spec(kind: BiquadKind, frequency: f64, gain_db: f64) -> BiquadSpec {
+        BiquadSpec { kind, frequency, gain_db, q: kind.default_q() }
+    }
+
+    fn assert_db(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.05, "{} dB instead of {} dB", actual, expected);
+    }
+
+    #[test]
+    fn parses_cli_values() {
+        let spec = BiquadSpec::parse(BiquadKind::Highpass, "80").unwrap();
+        assert_eq!((spec.frequency, spec.gain_db, spec.q), (80.0, 0.0, BUTTERWORTH_Q));
+        let spec = BiquadSpec::parse(BiquadKind::Lowpass, "8000:1.2").unwrap();
+        assert_eq!((spec.frequency, spec.q), (8000.0, 1.2));
+        let spec = BiquadSpec::parse(BiquadKind::Peaking, "3000:+2:1.5").unwrap();
+        assert_eq!((spec.frequency, spec.gain_db, spec.q), (3000.0, 2.0, 1.5));
+        let spec = BiquadSpec::parse(BiquadKind::LowShelf, "100:-3").unwrap();
+        assert_eq!((spec.gain_db, spec.q), (-3.0, 0.5));
+    }
+
+    #[test]
+    fn rejects_invalid_cli_values() {
+        for (kind, value) in [
+            (BiquadKind::Highpass, ""),
+            (BiquadKind::Highpass, "80:0.7:1"),
+            (BiquadKind::Peaking, "3000"),
+            (BiquadKind::Lowpass, "fast"),
+            (BiquadKind::Lowpass, "-100"),
+            (BiquadKind::Lowpass, "100:0"),
+            (BiquadKind::Peaking, "3000:inf"),
+            (BiquadKind::Notch, "NaN"),
+        ] {
+            assert!(BiquadSpec::parse(kind, value).is_err(), "{:?} {} was accepted", kind, value);
+        }
+    }
+
+    #[test]
+    fn formats_the_ffmpeg_filter() {
+        assert_eq!(BiquadSpec::parse(BiquadKind::Notch, "50:4").unwrap().ffmpeg_filter(), "bandreject=f=50:t=q:w=4");
+        assert_eq!(BiquadSpec::parse(BiquadKind::Peaking, "3000:2:1").unwrap().ffmpeg_filter(), "equalizer=f=3000:t=q:w=1:g=2");
+    }
+
+    #[test]
+    fn passes_and_stops_bands() {
+        let lowpass = spec(BiquadKind::Lowpass, 1000.0, 0.0);
+        assert_db(response_db(lowpass, 0.0), 0.0);
+        assert_db(response_db(lowpass, 1000.0), -3.01);
+        assert!(response_db(lowpass, 10000.0) < -38.0);
+
+        let highpass = spec(BiquadKind::Highpass, 1000.0, 0.0);
+        assert_db(response_db(highpass, 1000.0), -3.01);
+        assert_db(response_db(highpass, RATE as f64 / 2.0), 0.0);
+        assert!(response_db(highpass, 100.0) < -38.0);
+
+        let bandpass = spec(BiquadKind::Bandpass, 1000.0, 0.0);
+        assert_db(response_db(bandpass, 1000.0), 0.0);
+        assert!(response_db(bandpass, 10.0) < -30.0);
+
+        let notch = spec(BiquadKind::Notch, 1000.0, 0.0);
+        assert!(response_db(notch, 1000.0) < -100.0);
+        assert_db(response_db(notch, 0.0), 0.0);
+    }
+
+    #[test]
+    fn applies_gains() {
+        let peaking = BiquadSpec { q: 2.0, ..spec(BiquadKind::Peaking, 3000.0, 6.0) };
+        assert_db(response_db(peaking, 3000.0), 6.0);
+        assert_db(response_db(peaking, 0.0), 0.0);
+
+        let low_shelf = spec(BiquadKind::LowShelf, 200.0, -4.0);
+        assert_db(response_db(low_shelf, 0.0), -4.0);
+        assert_db(response_db(low_shelf, 200.0), -2.0);
+        assert_db(response_db(low_shelf, RATE as f64 / 2.0), 0.0);
+
+        let high_shelf = spec(BiquadKind::HighShelf, 5000.0, 3.0);
+        assert_db(response_db(high_shelf, 0.0), 0.0);
+        assert_db(response_db(high_shelf, RATE as f64 / 2.0), 3.0);
+    }
+
+    #[test]
+    fn filters_channels_independently() {
+        let lowpass = Biquad::new(&spec(BiquadKind::Lowpass, 100.0, 0.0), RATE);
+        let mut cascades = vec![vec![lowpass.clone()], vec![lowpass]];
+        // a DC level in the left channel and an alternating one at the Nyquist frequency in the right
+        let mut samples: Vec<f32> = (0..RATE as usize).flat_map(|frame| [0.5, if frame % 2 == 0 { 0.5 } else { -0.5 }]).collect();
+        process_interleaved(&mut cascades, &mut samples);
+        let last = &samples[samples.len() - 2..];
+        assert!((last[0] - 0.5).abs() < 1e-4, "{}", last[0]);
+        assert!(last[1].abs() < 1e-4, "{}", last[1]);
+    }
+}
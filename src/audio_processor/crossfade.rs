@@ -0,0 +1,88 @@
+use std::f32::consts::FRAC_PI_2;
+
+/// joins consecutive parts of an interleaved stream, overlapping the end of each part with the start of the next
+/// using equal-power fades; the last `fade_samples` of a part are held back until it is known whether another part follows
+pub struct Crossfader {
+    fade_samples: usize,
+    channels: usize,
+    // the most recent samples of the current part, not written yet
+    held: Vec<f32>,
+    // the end of the previous part fading out under the current one, and how far the fade has progressed
+    fading: Option<(Vec<f32>, usize)>,
+}
+
+impl Crossfader {
+    pub fn new(fade_samples: usize, channels: u8) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            // fades cover whole frames
+            fade_samples: fade_samples - fade_samples % channels,
+            channels,
+            held: Vec::new(),
+            fading: None,
+        }
+    }
+
+    /// marks the start of the next part, which the end of the current part is faded into
+    /// returns samples that became ready for output
+    pub fn start_part(&mut self) -> Vec<f32> {
+        // a part shorter than the fade ends while the previous part is still fading out, the rest of which then continues after it
+        if let Some(rest) = self.take_fading_rest() {
+            self.held.extend(rest);
+        }
+        let excess = self.held.len().saturating_sub(self.fade_samples);
+        let ready: Vec<f32> = self.held.drain(..excess).collect();
+        let tail = std::mem::take(&mut self.held);
+        if !tail.is_empty() {
+            self.fading = Some((tail, 0));
+        }
+        ready
+    }
+
+    /// mixes the samples of the current part into the fade and returns the samples that are ready for output
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut samples = samples.to_vec();
+        if let Some((tail, position)) = &mut self.fading {
+            let frames = (tail.len() / self.channels) as f32;
+            let overlap = (tail.len() - *position).min(samples.len());
+            for (index, sample) in samples.iter_mut().take(overlap).enumerate() {
+                let offset = *position + index;
+                let progress = (offset / self.channels) as f32 / frames;
+                let (rising, falling) = (progress * FRAC_PI_2).sin_cos();
+                *sample = *sample * rising + tail[offset] * falling;
+            }
+            *position += overlap;
+            if *position == tail.len() {
+                self.fading = None;
+            }
+        }
+
+        self.held.extend(samples);
+        let ready = self.held.len().saturating_sub(self.fade_samples);
+        self.held.drain(..ready).collect()
+    }
+
+    /// returns everything still held back at the end of the last part
+    pub fn finish(&mut self) -> Vec<f32> {
+        let mut remaining = std::mem::take(&mut self.held);
+        if let Some(rest) = self.take_fading_rest() {
+            remaining.extend(rest);
+        }
+        remaining
+    }
+
+    // the part of the previous part's end that the current part did not overlap, still faded out
+    fn take_fading_rest(&mut self) -> Option<Vec<f32>> {
+        let (tail, position) = self.fading.take()?;
+        let frames = (tail.len() / self.channels) as f32;
+        let rest = tail[position..]
+            .iter()
+            .enumerate()
+            .map(|(index, &sample)| {
+                let progress = ((position + index) / self.channels) as f32 / frames;
+                sample * (progress * FRAC_PI_2).cos()
+            })
+            .collect();
+        Some(rest)
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+use crate::audio_processor::level;
+
+// how quickly the ducked signal is pulled down once the key signal is heard
+const ATTACK: Duration = Duration::from_millis(10);
+// how long the ducking holds after the key signal last crossed the threshold, bridging the gaps between words
+const HOLD: Duration = Duration::from_millis(200);
+
+/// how a signal is lowered while a key signal (e.g. voice) is present
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckSettings {
+    /// level in dBFS above which the key signal triggers ducking
+    pub threshold_db: f32,
+    /// attenuation in dB applied while ducking
+    pub amount_db: f32,
+    /// time the signal takes to come back up once the key signal stops
+    pub release: Duration,
+}
+
+impl Default for DuckSettings {
+    fn default() -> Self {
+        Self { threshold_db: -40.0, amount_db: -12.0, release: Duration::from_millis(500) }
+    }
+}
+
+/// lowers interleaved audio frame by frame while the key signal is above the threshold, with smoothed gain changes
+pub struct Ducker {
+    threshold: f32,
+    ducked_gain: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    hold_frames: usize,
+    // frames left until the hold expires
+    holding: usize,
+    gain: f32,
+}
+
+impl Ducker {
+    pub fn new(settings: &DuckSettings, sample_rate: u32) -> Self {
+        // one-pole smoothing reaching about 63% of a gain change within the given time
+        let coefficient = |time: Duration| (-1.0 / (time.as_secs_f32() * sample_rate as f32).max(1.0)).exp();
+        Self {
+            threshold: level::db_to_linear(settings.threshold_db),
+            ducked_gain: level::db_to_linear(settings.amount_db),
+            attack_coefficient: coefficient(ATTACK),
+            release_coefficient: coefficient(settings.release),
+            hold_frames: (HOLD.as_secs_f64() * sample_rate as f64) as usize,
+            holding: 0,
+            gain: 1.0,
+        }
+    }
+
+    /// attenuates `samples` according to the level of `key`, both interleaved with `channels` channels and of equal length
+    pub fn process(&mut self, key: &[f32], samples: &mut [f32], channels: u8) {
+        let channels = channels.max(1) as usize;
+        for (key_frame, frame) in key.chunks(channels).zip(samples.chunks_mut(channels)) {
+            if level::peak(key_frame) >= self.threshold {
+                self.holding = self.hold_frames;
+            } else {
+                self.holding = self.holding.saturating_sub(1);
+            }
+
+            let (target, coefficient) = if self.holding > 0 {
+                (self.ducked_gain, self.attack_coefficient)
+            } else {
+                (1.0, self.release_coefficient)
+            };
+            self.gain = target + (self.gain - target) * coefficient;
+            frame.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    // runs a constant signal through the ducker while the key is heard for the first `key_frames` of `frames`,
+    // returning the gain of every frame
+    fn gains(settings: &DuckSettings, key_frames: usize, frames: usize) -> Vec<f32> {
+        let key: Vec<f32> = (0..frames).map(|frame| if frame < key_frames { 0.5 } else { 0.0 }).collect();
+        let mut samples = vec![1.0; frames];
+        Ducker::new(settings, SAMPLE_RATE).process(&key, &mut samples, 1);
+        samples
+    }
+
+    #[test]
+    fn ducks_while_the_key_is_heard() {
+        let settings = DuckSettings::default();
+        let ducked = level::db_to_linear(settings.amount_db);
+        let gains = gains(&settings, 100, 100);
+        // the attack takes a few milliseconds
+        assert!(gains[0] > 0.9);
+        assert!((gains[99] - ducked).abs() < 1e-3, "{}", gains[99]);
+    }
+
+    #[test]
+    fn holds_before_releasing() {
+        let settings = DuckSettings::default();
+        let ducked = level::db_to_linear(settings.amount_db);
+        let hold_frames = (HOLD.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let release_frames = (settings.release.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let gains = gains(&settings, 100, 100 + hold_frames + 10 * release_frames);
+
+        // bridging the gap after the key stops
+        let hold_end = 99 + hold_frames;
+        assert!(gains[100..hold_end].iter().all(|gain| (gain - ducked).abs() < 1e-3));
+        // coming back up by about 63% within the release time
+        let released = gains[hold_end + release_frames];
+        let expected = 1.0 - (1.0 - ducked) * (-1.0f32).exp();
+        assert!((released - expected).abs() < 0.01, "{} instead of {}", released, expected);
+        assert!(gains.windows(2).skip(hold_end).all(|pair| pair[1] >= pair[0]));
+        assert!((gains.last().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ignores_keys_below_the_threshold() {
+        let settings = DuckSettings { threshold_db: 0.0, ..DuckSettings::default() };
+        assert!(gains(&settings, 100, 200).iter().all(|&gain| gain == 1.0));
+    }
+}
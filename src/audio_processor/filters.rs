@@ -0,0 +1,282 @@
+use log::debug;
+use crate::audio_processor::biquad::{self, Biquad, BiquadKind, BiquadSpec};
+use crate::errors::TranscoderError;
+
+/// an ffmpeg audio filter with a native equivalent
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioFilter {
+    /// `volume`, as a linear gain factor
+    Volume(f32),
+    /// `highpass`, `lowpass`, `bandpass`, `bandreject`, `lowshelf`, `highshelf` and `equalizer`
+    Biquad(BiquadSpec),
+    /// `aresample` to the given sample rate in Hz
+    Aresample(u32),
+}
+
+/// parses a linear ffmpeg filter chain (`--af`) into filters the native transcoder can apply
+/// returns the reason as Err if any part of the graph has no native equivalent, in which case the job is left to ffmpeg
+/// the native resampler runs before all other stages, so `aresample` is only accepted as the first filter
+pub fn parse_filtergraph(graph: &str) -> Result<Vec<AudioFilter>, String> {
+    if graph.contains([';', '[', '\\', '\'']) {
+        return Err("labelled, branching or escaped filter graphs are only supported by FFmpeg".to_string());
+    }
+
+    let filters = graph
+        .split(',')
+        .map(str::trim)
+        .map(|filter| {
+            let (name, args) = filter.split_once('=').unwrap_or((filter, ""));
+            let args = FilterArgs::parse(args);
+            match name {
+                "volume" => parse_volume(&args),
+                "highpass" => parse_biquad(&args, BiquadKind::Highpass, Some(3000.0)),
+                "lowpass" => parse_biquad(&args, BiquadKind::Lowpass, Some(500.0)),
+                "bandpass" => parse_biquad(&args, BiquadKind::Bandpass, Some(3000.0)),
+                "bandreject" => parse_biquad(&args, BiquadKind::Notch, Some(3000.0)),
+                "lowshelf" | "bass" => parse_biquad(&args, BiquadKind::LowShelf, Some(100.0)),
+                "highshelf" | "treble" => parse_biquad(&args, BiquadKind::HighShelf, Some(3000.0)),
+                "equalizer" => parse_biquad(&args, BiquadKind::Peaking, None),
+                "aresample" => parse_aresample(&args),
+                "" => Err("empty filter in filter graph".to_string()),
+                _ => Err(format!("filter '{}' has no native equivalent", name)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if filters.iter().skip(1).any(|filter| matches!(filter, AudioFilter::Aresample(_))) {
+        return Err("aresample after other filters cannot be run in order natively".to_string());
+    }
+    Ok(filters)
+}
+
+/// the sample rate requested by the `aresample` filter of the chain, if any
+pub fn resample_rate(filters: &[AudioFilter]) -> Option<u32> {
+    filters.iter().rev().find_map(|filter| match filter {
+        AudioFilter::Aresample(rate) => Some(*rate),
+        _ => None,
+    })
+}
+
+// `name=positional:key=value` arguments of a single filter
+struct FilterArgs<'a> {
+    positional: Vec<&'a str>,
+    named: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> FilterArgs<'a> {
+    fn parse(args: &'a str) -> Self {
+        let mut positional = Vec::new();
+        let mut named = Vec::new();
+        for arg in args.split(':').filter(|arg| !arg.is_empty()) {
+            match arg.split_once('=') {
+                Some((key, value)) => named.push((key, value)),
+                None => positional.push(arg),
+            }
+        }
+        Self { positional, named }
+    }
+
+    // fails on options other than `keys`, since silently ignoring them would change the result
+    fn expect_keys(&self, keys: &[&str], max_positional: usize) -> Result<(), String> {
+        if self.positional.len() > max_positional {
+            return Err(format!("unsupported positional option '{}'", self.positional[max_positional]));
+        }
+        match self.named.iter().find(|(key, _)| !keys.contains(key)) {
+            Some((key, _)) => Err(format!("option '{}' has no native equivalent", key)),
+            None => Ok(()),
+        }
+    }
+
+    // the value given by any of the aliases in `keys`, or at the positional index
+    fn get(&self, keys: &[&str], position: usize) -> Option<&'a str> {
+        self.named
+            .iter()
+            .rev()
+            .find(|(key, _)| keys.contains(key))
+            .map(|(_, value)| *value)
+            .or_else(|| self.positional.get(position).copied())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, option: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a plain number for '{}'", value, option))
+}
+
+// `volume=0.5`, `volume=-6dB` or `volume=volume=3dB`
+fn parse_volume(args: &FilterArgs) -> Result<AudioFilter, String> {
+    args.expect_keys(&["volume"], 1)?;
+    let value = args.get(&["volume"], 0).unwrap_or("1.0");
+    let gain = match value.strip_suffix("dB") {
+        Some(db) => 10f32.powf(parse_number::<f32>(db, "volume")? / 20.0),
+        None => parse_number(value, "volume")?,
+    };
+    Ok(AudioFilter::Volume(gain))
+}
+
+// `highpass=f=80`, `lowpass=8000:p=2:t=q:w=0.5`, `equalizer=f=3000:t=q:w=1:g=2`
+// `default_frequency` is ffmpeg's default when `f` is omitted
+fn parse_biquad(args: &FilterArgs, kind: BiquadKind, default_frequency: Option<f64>) -> Result<AudioFilter, String> {
+    let mut keys = vec!["f", "frequency", "t", "width_type", "w", "width"];
+    if kind.has_gain() {
+        keys.extend(["g", "gain"]);
+    }
+    if matches!(kind, BiquadKind::Lowpass | BiquadKind::Highpass) {
+        keys.extend(["p", "poles"]);
+    }
+    args.expect_keys(&keys, 1)?;
+
+    let frequency = match args.get(&["f", "frequency"], 0) {
+        Some(value) => parse_number(value, "frequency")?,
+        None => default_frequency.ok_or_else(|| format!("{} without a frequency has no native equivalent", kind.ffmpeg_name()))?,
+    };
+    if let Some(poles) = args.get(&["p", "poles"], usize::MAX).filter(|poles| *poles != "2") {
+        return Err(format!("{}-pole filters have no native equivalent", poles));
+    }
+    if let Some(width_type) = args.get(&["t", "width_type"], usize::MAX).filter(|width_type| *width_type != "q") {
+        return Err(format!("width type '{}' has no native equivalent", width_type));
+    }
+    let q = match args.get(&["w", "width"], usize::MAX) {
+        Some(value) => parse_number(value, "width")?,
+        None => kind.default_q(),
+    };
+    let gain_db = match args.get(&["g", "gain"], usize::MAX) {
+        Some(value) => parse_number(value, "gain")?,
+        None => 0.0,
+    };
+
+    let spec = BiquadSpec { kind, frequency, gain_db, q };
+    spec.validate()?;
+    Ok(AudioFilter::Biquad(spec))
+}
+
+// `aresample=48000` or `aresample=osr=48000`
+fn parse_aresample(args: &FilterArgs) -> Result<AudioFilter, String> {
+    args.expect_keys(&["osr", "out_sample_rate"], 1)?;
+    let rate = args
+        .get(&["osr", "out_sample_rate"], 0)
+        .ok_or_else(|| "aresample without a sample rate has no native equivalent".to_string())?;
+    match parse_number(rate, "aresample")? {
+        0 => Err("aresample rate must be positive".to_string()),
+        rate => Ok(AudioFilter::Aresample(rate)),
+    }
+}
+
+enum Stage {
+    Gain(f32),
+    // one cascade of consecutive filters per channel, each carrying its own state
+    Biquads(Vec<Vec<Biquad>>),
+}
+
+/// native processing stages built from a parsed filter chain, applied to interleaved samples at the output sample rate
+/// `aresample` adds no stage since it is carried out by the resampler
+pub struct FilterChain {
+    stages: Vec<Stage>,
+}
+
+impl FilterChain {
+    pub fn new(filters: &[AudioFilter], sample_rate: u32, channels: u8) -> Result<Self, TranscoderError> {
+        let nyquist = sample_rate as f64 / 2.0;
+        let mut stages: Vec<Stage> = Vec::new();
+        for filter in filters {
+            debug!("Adding native filter stage: {:?}", filter);
+            match filter {
+                AudioFilter::Volume(gain) => stages.push(Stage::Gain(*gain)),
+                AudioFilter::Biquad(spec) => {
+                    if spec.frequency >= nyquist {
+                        return Err(TranscoderError::Argument(format!(
+                            "Filter frequency {} Hz must be below the Nyquist frequency of {} Hz", spec.frequency, nyquist
+                        )));
+                    }
+                    // consecutive filters share one pass over the deinterleaved channels
+                    let filter = Biquad::new(spec, sample_rate);
+                    match stages.last_mut() {
+                        Some(Stage::Biquads(cascades)) => cascades.iter_mut().for_each(|cascade| cascade.push(filter.clone())),
+                        _ => stages.push(Stage::Biquads(vec![vec![filter]; channels as usize])),
+                    }
+                }
+                AudioFilter::Aresample(_) => {}
+            }
+        }
+
+        Ok(Self { stages })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// runs all stages in order over a buffer of whole interleaved frames
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for stage in &mut self.stages {
+            match stage {
+                Stage::Gain(gain) => samples.iter_mut().for_each(|sample| *sample *= *gain),
+                Stage::Biquads(cascades) => biquad::process_interleaved(cascades, samples),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn biquad(kind: BiquadKind, frequency: f64, gain_db: f64, q: f64) -> AudioFilter {
+        AudioFilter::Biquad(BiquadSpec { kind, frequency, gain_db, q })
+    }
+
+    #[test]
+    fn parses_native_chains() {
+        let filters = parse_filtergraph("aresample=48000, highpass=f=80, volume=-6dB, equalizer=f=3000:t=q:w=2:g=1.5").unwrap();
+        assert_eq!(filters[0], AudioFilter::Aresample(48000));
+        assert_eq!(filters[1], biquad(BiquadKind::Highpass, 80.0, 0.0, biquad::BUTTERWORTH_Q));
+        assert!(matches!(filters[2], AudioFilter::Volume(gain) if (gain - 0.501_187).abs() < 1e-5));
+        assert_eq!(filters[3], biquad(BiquadKind::Peaking, 3000.0, 1.5, 2.0));
+        assert_eq!(resample_rate(&filters), Some(48000));
+    }
+
+    #[test]
+    fn accepts_ffmpeg_spellings() {
+        assert_eq!(parse_filtergraph("volume=volume=0.5").unwrap(), [AudioFilter::Volume(0.5)]);
+        assert_eq!(parse_filtergraph("aresample=osr=44100").unwrap(), [AudioFilter::Aresample(44100)]);
+        assert_eq!(parse_filtergraph("lowpass=8000:p=2").unwrap(), [biquad(BiquadKind::Lowpass, 8000.0, 0.0, biquad::BUTTERWORTH_Q)]);
+        assert_eq!(parse_filtergraph("bass=g=3").unwrap(), [biquad(BiquadKind::LowShelf, 100.0, 3.0, 0.5)]);
+        assert_eq!(parse_filtergraph("bandreject=frequency=50:width_type=q:width=4").unwrap(), [biquad(BiquadKind::Notch, 50.0, 0.0, 4.0)]);
+    }
+
+    #[test]
+    fn rejects_graphs_without_native_equivalent() {
+        for graph in [
+            "loudnorm",
+            "[0:a]volume=2[out]",
+            "volume=2;volume=3",
+            "highpass=f=80:p=1",
+            "lowpass=f=8000:t=h:w=200",
+            "highpass=f=80:mix=0.5",
+            "volume=loud",
+            "volume=2,,volume=3",
+            "equalizer=t=q:w=1:g=3",
+            "aresample",
+            "aresample=0",
+            "highpass=f=-80",
+        ] {
+            assert!(parse_filtergraph(graph).is_err(), "{} was accepted", graph);
+        }
+    }
+
+    #[test]
+    fn rejects_resampling_after_other_filters() {
+        // the native resampler always runs first, which would reorder these graphs
+        assert!(parse_filtergraph("volume=0.5,aresample=48000").is_err());
+        assert!(parse_filtergraph("aresample=48000,volume=0.5,aresample=44100").is_err());
+    }
+
+    #[test]
+    fn skips_filters_without_stages() {
+        let filters = parse_filtergraph("aresample=48000,volume=1.0").unwrap();
+        assert!(!FilterChain::new(&filters, 48000, 2).unwrap().is_empty());
+        assert!(FilterChain::new(&filters[..1], 48000, 2).unwrap().is_empty());
+        let filters = parse_filtergraph("lowpass=f=30000").unwrap();
+        assert!(FilterChain::new(&filters, 44100, 2).is_err());
+    }
+}
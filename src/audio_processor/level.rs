@@ -0,0 +1,50 @@
+// fraction of the ceiling above which the soft limiter starts bending the signal (about -3 dB)
+const SOFT_LIMIT_KNEE: f32 = 0.7;
+
+/// converts a level in dB to a linear amplitude factor
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// converts a linear amplitude to dB (dBFS for sample peaks)
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * linear.log10()
+}
+
+/// the highest absolute sample value of the buffer
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+}
+
+/// the number of samples outside of [-1.0, 1.0], which are clamped when converting to integer samples
+pub fn count_clipped(samples: &[f32]) -> usize {
+    samples.iter().filter(|sample| sample.abs() > 1.0).count()
+}
+
+/// a memoryless soft limiter passing samples below the knee unchanged and bending louder ones
+/// smoothly towards the ceiling, so that the output never exceeds it
+#[derive(Debug, Clone, Copy)]
+pub struct SoftLimiter {
+    knee: f32,
+    ceiling: f32,
+}
+
+impl SoftLimiter {
+    /// `ceiling_dbfs` is the highest output level, e.g. -1.0 for a dB of headroom
+    pub fn new(ceiling_dbfs: f32) -> Self {
+        let ceiling = db_to_linear(ceiling_dbfs);
+        Self { knee: ceiling * SOFT_LIMIT_KNEE, ceiling }
+    }
+
+    pub fn process(&self, samples: &mut [f32]) {
+        let range = self.ceiling - self.knee;
+        for sample in samples.iter_mut() {
+            let magnitude = sample.abs();
+            if magnitude > self.knee {
+                // tanh keeps the curve continuous with a slope of 1 at the knee
+                let limited = self.knee + range * ((magnitude - self.knee) / range).tanh();
+                *sample = limited.copysign(*sample);
+            }
+        }
+    }
+}
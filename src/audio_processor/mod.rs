@@ -1,13 +1,23 @@
 pub mod resampler;
+pub mod biquad;
+pub mod filters;
+pub mod level;
+pub mod pipeline;
+pub mod silence;
+pub mod crossfade;
+pub mod ducker;
 
 use log::debug;
 use rayon::prelude::*;
 
+// integer samples are scaled by 2^(bits - 1) in both directions, so that decoding and re-encoding at the same bit
+// depth restores every sample exactly
+
 /// converts a slice of i16 samples to f32 samples
 pub fn i16_to_f32(samples: &[i16]) -> Vec<f32> {
     samples
         .par_iter()
-        .map(|&s| s as f32 / i16::MAX as f32)
+        .map(|&s| s as f32 / 32768.0)
         .collect()
 }
 
@@ -16,29 +26,31 @@ pub fn f32_to_i16(samples: &[f32]) -> Vec<i16> {
     samples
         .par_iter()
         .map(|&s| {
-            (s * i16::MAX as f32)
+            (s * 32768.0)
                 .round()
                 .clamp(i16::MIN as f32, i16::MAX as f32) as i16
         })
         .collect()
 }
 
-/// converts a slice of i32 samples to f32 samples (FLAC decoding)
-pub fn i32_to_f32(samples: &[i32]) -> Vec<f32> {
+/// converts a slice of i32 samples holding `bits_per_sample` significant bits to f32 samples (FLAC and 24/32-bit WAV decoding)
+pub fn i32_to_f32(samples: &[i32], bits_per_sample: u16) -> Vec<f32> {
+    let scale = (1u64 << (bits_per_sample.clamp(1, 32) - 1)) as f32;
     samples
         .par_iter()
-        .map(|&s| s as f32 / i32::MAX as f32)
+        .map(|&s| s as f32 / scale)
         .collect()
 }
 
-/// converts a slice of f32 samples to i32 samples (FLAC encoding)
-pub fn f32_to_i32(samples: &[f32]) -> Vec<i32> {
+/// converts a slice of f32 samples to i32 samples holding `bits_per_sample` significant bits (8/24/32-bit WAV encoding)
+pub fn f32_to_i32(samples: &[f32], bits_per_sample: u16) -> Vec<i32> {
+    let scale = (1u64 << (bits_per_sample.clamp(1, 32) - 1)) as f64;
     samples
         .par_iter()
         .map(|&s| {
-            (s * i32::MAX as f32)
+            (s as f64 * scale)
                 .round()
-                .clamp(i32::MIN as f32, i32::MAX as f32) as i32
+                .clamp(-scale, scale - 1.0) as i32
         })
         .collect()
 }
@@ -95,8 +107,8 @@ pub fn mix_channels(
                     for c_out in 0..n_out {
                         let mut sum = 0.0;
                         let mut count = 0;
-                        for c_in_idx in (c_out as usize)..n_in as usize{
-                            sum += input_frame[c_in_idx];
+                        for &sample in &input_frame[c_out as usize..n_in as usize] {
+                            sum += sample;
                             count += 1;
                         }
                         if count > 0 {
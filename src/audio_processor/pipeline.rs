@@ -0,0 +1,94 @@
+use log::debug;
+use crate::audio_processor::{self, filters::{AudioFilter, FilterChain}, level::SoftLimiter, resampler::AudioResampler};
+use crate::audio_processor::silence::{SilenceSettings, SilenceTrimmer};
+use crate::codecs::StreamSpec;
+use crate::errors::TranscoderError;
+
+/// number of frames decoded and processed at a time
+pub const CHUNK_FRAMES: usize = 1024;
+
+/// resampling, channel mixing, filtering, level processing and silence trimming from the input to the output spec
+pub struct Pipeline {
+    resampler: Option<AudioResampler>,
+    input_channels: u8,
+    output_channels: u8,
+    filter_chain: FilterChain,
+    gain: f32,
+    limiter: Option<SoftLimiter>,
+    trimmer: Option<SilenceTrimmer>,
+}
+
+impl Pipeline {
+    /// `gain` is a linear factor applied after the filters, followed by `limiter` and trimming of the edge silence described by `trim`
+    pub fn new(
+        input_spec: &StreamSpec,
+        output_spec: &StreamSpec,
+        filters: &[AudioFilter],
+        gain: f32,
+        limiter: Option<SoftLimiter>,
+        trim: Option<&SilenceSettings>,
+    ) -> Result<Self, TranscoderError> {
+        let resampler = if input_spec.sample_rate != output_spec.sample_rate {
+            Some(AudioResampler::new(input_spec.sample_rate, output_spec.sample_rate, input_spec.channels, CHUNK_FRAMES)?)
+        } else {
+            None
+        };
+
+        let filter_chain = FilterChain::new(filters, output_spec.sample_rate, output_spec.channels)?;
+        if !filter_chain.is_empty() {
+            debug!("Pipeline: applying {} filters", filters.len());
+        }
+
+        Ok(Self {
+            resampler,
+            input_channels: input_spec.channels,
+            output_channels: output_spec.channels,
+            filter_chain,
+            gain,
+            limiter,
+            trimmer: trim.map(|settings| SilenceTrimmer::new(settings, output_spec.sample_rate, output_spec.channels)),
+        })
+    }
+
+    /// processes a chunk of interleaved input samples
+    pub fn process(&mut self, chunk: Vec<f32>) -> Result<Vec<f32>, TranscoderError> {
+        let samples = match &mut self.resampler {
+            Some(resampler) => resampler.process_interleaved(&chunk)?,
+            None => chunk,
+        };
+        Ok(self.post_process(samples))
+    }
+
+    /// drains the samples still buffered in the resampler and the silence trimmer at the end of the input
+    pub fn flush(&mut self) -> Result<Vec<f32>, TranscoderError> {
+        let mut samples = match &mut self.resampler {
+            Some(resampler) => {
+                let samples = resampler.flush()?;
+                self.post_process(samples)
+            }
+            None => Vec::new(),
+        };
+        if let Some(trimmer) = &mut self.trimmer {
+            samples.extend(trimmer.finish());
+        }
+        Ok(samples)
+    }
+
+    fn post_process(&mut self, mut samples: Vec<f32>) -> Vec<f32> {
+        // mixing channels
+        if self.input_channels != self.output_channels {
+            samples = audio_processor::mix_channels(&samples, self.input_channels, self.output_channels);
+        }
+        self.filter_chain.process(&mut samples);
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.process(&mut samples);
+        }
+        match &mut self.trimmer {
+            Some(trimmer) => trimmer.process(&samples),
+            None => samples,
+        }
+    }
+}
@@ -4,9 +4,15 @@ use crate::errors::TranscoderError;
 
 pub struct AudioResampler {
     resampler: SincFixedIn<f32>,
-    input_buffer: Vec<Vec<f32>>,
+    // per-channel input frames waiting for a full chunk
+    pending: Vec<Vec<f32>>,
     output_buffer: Vec<Vec<f32>>,
     input_frame_size: usize,
+    ratio: f64,
+    // delay introduced by the sinc filter that has not been dropped from the output yet
+    delay_frames: usize,
+    frames_in: u64,
+    frames_out: u64,
 }
 
 impl AudioResampler {
@@ -30,58 +36,63 @@ impl AudioResampler {
             window: WindowFunction::BlackmanHarris2, // window function for the filter
         };
 
+        let ratio = output_rate as f64 / input_rate as f64;
         let resampler = SincFixedIn::<f32>::new(
-            output_rate as f64 / input_rate as f64,
-            chunk_size as f64,
+            ratio,
+            1.0, // the ratio is fixed for the whole stream
             parameters,
+            chunk_size,
             channels as usize,
-            0, // resample delay in frames
         ).map_err(
             |e| TranscoderError::Resampler(format!("Failed to intialize Rubato sampler: {:?}", e))
         )?;
 
-        // initializing channel interleaved buffers for `rubato`
-        let input_buffer = vec![vec![0.0f32; chunk_size]; channels as usize];
-        let output_buffer = vec![vec![0.0f32; resampler.output_frames_next()]; channels as usize];
+        // initializing channel separated buffers for `rubato`
+        let pending = vec![Vec::with_capacity(chunk_size * 2); channels as usize];
+        let output_buffer = vec![vec![0.0f32; resampler.output_frames_max()]; channels as usize];
+        let delay_frames = resampler.output_delay();
 
         Ok(Self {
             resampler,
-            input_buffer,
+            pending,
             output_buffer,
             input_frame_size: channels as usize,
+            ratio,
+            delay_frames,
+            frames_in: 0,
+            frames_out: 0,
         })
     }
 
     /// resamples a chunk of interleaved audio samples
+    /// input of any length is accepted; frames that do not fill a whole resampler chunk are held back until the next call or `flush`
     pub fn process_interleaved(&mut self, input_interleaved: &[f32]) -> Result<Vec<f32>, TranscoderError> {
         if input_interleaved.is_empty() {
             return Ok(Vec::new());
         }
 
         // de-interleaving input samples into the channel-separated format offered by `rubato`
-        let num_input_frames = input_interleaved.len() / self.input_frame_size;
-        for c in 0..self.input_buffer.len() {
-            self.input_buffer[c].resize(num_input_frames, 0.0);
-            for i in 0..num_input_frames {
-                self.input_buffer[c][i] = input_interleaved[i * self.input_frame_size + c];
+        for frame in input_interleaved.chunks_exact(self.input_frame_size) {
+            for (c, &sample) in frame.iter().enumerate() {
+                self.pending[c].push(sample);
             }
         }
+        self.frames_in += (input_interleaved.len() / self.input_frame_size) as u64;
 
-        // processing block
-        let (resampled_frames_per_channel, _channels) = self.resampler.process_into_buffer(
-            &self.input_buffer,
-            &mut self.output_buffer,
-            None,
-        ).map_err(
-            |e| TranscoderError::Resampler(format!("Failed to process samples with rubato {:?}", e))
-        )?;
+        let mut output_interleaved = Vec::new();
+        while self.pending[0].len() >= self.resampler.input_frames_next() {
+            let (consumed, produced) = self.resampler.process_into_buffer(
+                &self.pending,
+                &mut self.output_buffer,
+                None,
+            ).map_err(
+                |e| TranscoderError::Resampler(format!("Failed to process samples with rubato {:?}", e))
+            )?;
 
-        // re-interleaving output sampled from the special format in `rubato`
-        let mut output_interleaved = Vec::with_capacity(resampled_frames_per_channel * self.input_buffer.len());
-        for i in 0..resampled_frames_per_channel {
-            for c in 0..self.input_buffer.len() {
-                output_interleaved.push(self.output_buffer[c][i]);
+            for channel in self.pending.iter_mut() {
+                channel.drain(..consumed);
             }
+            self.append_output(produced, &mut output_interleaved);
         }
         Ok(output_interleaved)
     }
@@ -89,21 +100,46 @@ impl AudioResampler {
     /// flushes any remaining buffered samples from the resampler
     pub fn flush(&mut self) -> Result<Vec<f32>, TranscoderError> {
         debug!("Flushing resampler");
-        let empty_input: Vec<Vec<f32>> = vec![Vec::new(); self.input_buffer.len()];
-        let (resampled_frames_per_channel, _channels) = self.resampler.process_into_buffer(
-            &empty_input,
-            &mut self.output_buffer,
-            None,
-        ).map_err(
-            |e| TranscoderError::Resampler(format!("Failed to flush rubato resampler: {:?}", e))
-        )?;
+        let expected_frames = (self.frames_in as f64 * self.ratio).round() as u64;
+        let mut output_interleaved = Vec::new();
 
-        let mut output_interleaved = Vec::with_capacity(resampled_frames_per_channel * self.input_buffer.len());
-        for i in 0..resampled_frames_per_channel {
-            for c in 0..self.input_buffer.len() {
-                output_interleaved.push(self.output_buffer[c][i]);
+        // the remaining partial chunk is zero-padded by `rubato`, later calls without input push out the delayed frames
+        let mut remaining = Some(std::mem::take(&mut self.pending)).filter(|pending| !pending[0].is_empty());
+        while self.frames_out < expected_frames {
+            let (_consumed, produced) = self.resampler.process_partial_into_buffer(
+                remaining.take().as_deref(),
+                &mut self.output_buffer,
+                None,
+            ).map_err(
+                |e| TranscoderError::Resampler(format!("Failed to flush rubato resampler: {:?}", e))
+            )?;
+
+            if produced == 0 {
+                break;
             }
+            self.append_output(produced, &mut output_interleaved);
         }
+
+        // dropping the padding produced past the end of the input
+        let excess_frames = self.frames_out.saturating_sub(expected_frames) as usize;
+        output_interleaved.truncate(output_interleaved.len().saturating_sub(excess_frames * self.input_frame_size));
+        self.frames_out -= excess_frames as u64;
+
+        self.pending = vec![Vec::new(); self.input_frame_size];
         Ok(output_interleaved)
     }
-}
\ No newline at end of file
+
+    // re-interleaving output samples from the special format in `rubato`, skipping the filter delay at the start of the stream
+    fn append_output(&mut self, produced: usize, output_interleaved: &mut Vec<f32>) {
+        let skip = self.delay_frames.min(produced);
+        self.delay_frames -= skip;
+
+        output_interleaved.reserve((produced - skip) * self.input_frame_size);
+        for i in skip..produced {
+            for c in 0..self.input_frame_size {
+                output_interleaved.push(self.output_buffer[c][i]);
+            }
+        }
+        self.frames_out += (produced - skip) as u64;
+    }
+}
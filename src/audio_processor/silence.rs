@@ -0,0 +1,130 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::audio_processor::level;
+
+// length of the windows audio is classified in, short enough to cut close to the sound
+const WINDOW_DURATION: Duration = Duration::from_millis(10);
+
+/// what counts as silence: a stretch of at least `min_duration` whose peak stays below `threshold_db`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SilenceSettings {
+    /// level in dBFS below which audio is considered silent
+    pub threshold_db: f32,
+    /// shortest stretch of silence that is trimmed or split at
+    pub min_duration: Duration,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        Self { threshold_db: -50.0, min_duration: Duration::from_millis(500) }
+    }
+}
+
+impl SilenceSettings {
+    /// `min_duration` as a number of interleaved samples
+    pub fn min_samples(&self, sample_rate: u32, channels: u8) -> usize {
+        (self.min_duration.as_secs_f64() * sample_rate as f64) as usize * channels as usize
+    }
+}
+
+/// classifies interleaved audio in windows of 10 ms as silent or not
+pub struct SilenceDetector {
+    threshold: f32,
+    window_samples: usize,
+    // samples of the window that is not complete yet
+    pending: Vec<f32>,
+}
+
+impl SilenceDetector {
+    pub fn new(settings: &SilenceSettings, sample_rate: u32, channels: u8) -> Self {
+        let window_frames = (WINDOW_DURATION.as_secs_f64() * sample_rate as f64).max(1.0) as usize;
+        Self {
+            threshold: level::db_to_linear(settings.threshold_db),
+            window_samples: window_frames * channels as usize,
+            pending: Vec::new(),
+        }
+    }
+
+    /// splits the samples into complete windows, each paired with whether it is silent
+    /// samples not filling a window are kept for the next call
+    pub fn push(&mut self, samples: &[f32]) -> Vec<(Vec<f32>, bool)> {
+        self.pending.extend_from_slice(samples);
+        let complete = self.pending.len() - self.pending.len() % self.window_samples;
+        let complete: Vec<f32> = self.pending.drain(..complete).collect();
+        complete
+            .chunks(self.window_samples)
+            .map(|window| (window.to_vec(), self.is_silent(window)))
+            .collect()
+    }
+
+    /// returns the final, partial window at the end of the stream
+    pub fn finish(&mut self) -> Option<(Vec<f32>, bool)> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let window = std::mem::take(&mut self.pending);
+        let silent = self.is_silent(&window);
+        Some((window, silent))
+    }
+
+    fn is_silent(&self, window: &[f32]) -> bool {
+        level::peak(window) < self.threshold
+    }
+}
+
+/// strips leading and trailing silence lasting at least the minimum duration
+/// trailing silence is held back until sound follows it or the stream ends
+pub struct SilenceTrimmer {
+    detector: SilenceDetector,
+    min_samples: usize,
+    heard_sound: bool,
+    // silent windows since the last sound
+    held: Vec<f32>,
+}
+
+impl SilenceTrimmer {
+    pub fn new(settings: &SilenceSettings, sample_rate: u32, channels: u8) -> Self {
+        Self {
+            detector: SilenceDetector::new(settings, sample_rate, channels),
+            min_samples: settings.min_samples(sample_rate, channels),
+            heard_sound: false,
+            held: Vec::new(),
+        }
+    }
+
+    /// returns the samples that are known not to belong to trimmed silence
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(samples.len());
+        for (window, silent) in self.detector.push(samples) {
+            self.push_window(window, silent, &mut output);
+        }
+        output
+    }
+
+    /// returns the samples held back at the end of the stream, dropping them if they form trailing silence
+    pub fn finish(&mut self) -> Vec<f32> {
+        let mut output = Vec::new();
+        if let Some((window, silent)) = self.detector.finish() {
+            self.push_window(window, silent, &mut output);
+        }
+        if self.held.len() < self.min_samples {
+            output.append(&mut self.held);
+        }
+        self.held.clear();
+        output
+    }
+
+    fn push_window(&mut self, window: Vec<f32>, silent: bool, output: &mut Vec<f32>) {
+        if silent {
+            self.held.extend(window);
+            return;
+        }
+        // silence between sounds is always kept, leading silence only when it is too short to trim
+        if self.heard_sound || self.held.len() < self.min_samples {
+            output.append(&mut self.held);
+        }
+        self.held.clear();
+        self.heard_sound = true;
+        output.extend(window);
+    }
+}
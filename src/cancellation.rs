@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::errors::TranscoderError;
+
+/// a shareable token aborting a transcode when cancelled or once its deadline has passed
+/// clones share the cancellation flag, so a token can be cancelled from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// returns a token sharing the cancellation flag that additionally expires `timeout` from now
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            cancelled: Arc::clone(&self.cancelled),
+            deadline: Some((Instant::now() + timeout, timeout)),
+        }
+    }
+
+    /// returns a token sharing the cancellation flag whose timeout, if any, starts over from now
+    pub fn restarted(&self) -> Self {
+        match self.deadline {
+            Some((_, timeout)) => self.with_timeout(timeout),
+            None => self.clone(),
+        }
+    }
+
+    /// requests cancellation of every transcode observing this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// fails with `TranscoderError::Cancelled` once the token was cancelled or timed out
+    pub fn check(&self) -> Result<(), TranscoderError> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(TranscoderError::Cancelled("cancelled by request".to_string()));
+        }
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(TranscoderError::Cancelled(format!("timed out after {:?}", timeout)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_the_timeout_and_shares_the_flag() {
+        let token = CancellationToken::new().with_timeout(Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(token.check(), Err(TranscoderError::Cancelled(_))));
+        let restarted = token.restarted();
+        assert!(restarted.check().is_ok());
+        token.cancel();
+        assert!(matches!(restarted.check(), Err(TranscoderError::Cancelled(reason)) if reason.contains("request")));
+        assert!(CancellationToken::new().restarted().check().is_ok());
+    }
+}
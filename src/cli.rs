@@ -0,0 +1,620 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand};
+use crate::audio_processor::biquad::{BiquadKind, BiquadSpec};
+use crate::audio_processor::ducker::DuckSettings;
+use crate::audio_processor::silence::SilenceSettings;
+use crate::cancellation::CancellationToken;
+use crate::codecs::{self, channel_layout};
+use crate::errors::TranscoderError;
+use crate::format_detection;
+use crate::jobs::OutputArg;
+use crate::operations::mix::MixTrack;
+use crate::operations::verify::VerifySettings;
+use crate::presets;
+use crate::progress::ProgressCallback;
+use crate::transcoders::{self, TranscodeOptions};
+use crate::utils;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "An audio transcoder written in Rust", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct CliArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// input audio file path, or `-` to read from stdin (requires --input-format)
+    #[arg(short, long, value_name = "FILE", required = true)]
+    pub input: Option<PathBuf>,
+
+    /// output audio file path determined by the output file extension, or `-` to write to stdout (requires --output-format)
+    /// may be repeated to write several outputs from a single decode of the input, each optionally followed by options
+    /// of its own named as in job files, e.g. `out.mp3,bitrate=320,lowpass=16000`
+    #[arg(short, long = "output", value_name = "FILE[,KEY=VALUE...]", required = true, value_parser = OutputArg::parse)]
+    pub outputs: Vec<OutputArg>,
+
+    /// skip the transcode if every output was written by this version from the same input content with the same
+    /// options, as recorded in a hidden `.<output name>.rewav.json` sidecar next to it
+    #[arg(long)]
+    pub if_changed: bool,
+
+    /// decode every output once it is written and compare it with the input, as the verify subcommand does
+    #[arg(long)]
+    pub verify: bool,
+
+    #[command(flatten)]
+    pub verify_thresholds: VerifyThresholdArgs,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+
+    /// increasing verbosity of logging
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+}
+
+/// operations beyond transcoding a single file, the options of which apply to every output
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// cut the input into one output per section separated by silence
+    Split(SplitArgs),
+    /// join several inputs into one output, optionally crossfading between them
+    Concat(ConcatArgs),
+    /// sum several inputs into one output with per-track gain, offset and ducking
+    Mix(MixArgs),
+    /// write every channel of the input to its own mono output
+    SplitChannels(SplitChannelsArgs),
+    /// interleave mono inputs into one multichannel output
+    MergeChannels(MergeChannelsArgs),
+    /// run the transcodes described by a TOML or JSON job file
+    Run(RunArgs),
+    /// transcode every file dropped into a folder until interrupted
+    Watch(WatchArgs),
+    /// serve transcodes over HTTP until interrupted
+    Serve(ServeArgs),
+    /// check that an output holds the audio of its input
+    Verify(VerifyArgs),
+}
+
+impl Command {
+    pub fn transcode_args(&self) -> &TranscodeArgs {
+        match self {
+            Command::Split(args) => &args.transcode,
+            Command::Concat(args) => &args.transcode,
+            Command::Mix(args) => &args.transcode,
+            Command::SplitChannels(args) => &args.transcode,
+            Command::MergeChannels(args) => &args.transcode,
+            Command::Run(args) => &args.transcode,
+            Command::Watch(args) => &args.transcode,
+            Command::Serve(args) => &args.transcode,
+            Command::Verify(args) => &args.transcode,
+        }
+    }
+
+    /// whether the audio the subcommand produces is written to stdout, which then has to be kept free of logs
+    pub fn writes_stdout(&self) -> bool {
+        match self {
+            Command::Concat(args) => utils::is_stdio(&args.output),
+            Command::Mix(args) => utils::is_stdio(&args.output),
+            Command::MergeChannels(args) => utils::is_stdio(&args.output),
+            // templates and reports never name stdout
+            Command::Split(_) | Command::SplitChannels(_) | Command::Run(_) | Command::Watch(_) | Command::Serve(_) | Command::Verify(_) => false,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SplitArgs {
+    /// input audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub input: PathBuf,
+
+    /// output path template; `{index}` is replaced by the 1-based part number, `{index:03}` pads it with zeros
+    #[arg(short, long, value_name = "TEMPLATE")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ConcatArgs {
+    /// input audio file paths in playback order (may be repeated)
+    #[arg(short, long = "input", value_name = "FILE", required = true, num_args = 1..)]
+    pub inputs: Vec<PathBuf>,
+
+    /// output audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// length in seconds of the equal-power crossfade between consecutive inputs
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.0)]
+    pub crossfade: f64,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+impl ConcatArgs {
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if !(self.crossfade.is_finite() && self.crossfade >= 0.0) {
+            return Err(TranscoderError::Argument("Crossfade must be a non-negative number of seconds".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct MixArgs {
+    /// input track as FILE[,gain=DB][,offset=SECONDS][,duck] (may be repeated)
+    /// `duck` lowers the track while the tracks without it are heard
+    #[arg(short = 'i', long = "input", value_name = "TRACK", required = true, num_args = 1.., value_parser = MixTrack::parse)]
+    pub tracks: Vec<MixTrack>,
+
+    /// output audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// level in dBFS above which the other tracks trigger ducking
+    #[arg(long, value_name = "DB", default_value_t = -40.0, allow_hyphen_values = true)]
+    pub duck_threshold: f32,
+
+    /// attenuation in dB of ducked tracks
+    #[arg(long, value_name = "DB", default_value_t = -12.0, allow_hyphen_values = true)]
+    pub duck_amount: f32,
+
+    /// seconds ducked tracks take to come back up
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5)]
+    pub duck_release: f64,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+impl MixArgs {
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if !(self.duck_threshold.is_finite() && self.duck_amount.is_finite()) {
+            return Err(TranscoderError::Argument("Ducking levels must be finite numbers of dB".to_string()));
+        }
+        if !(self.duck_release.is_finite() && self.duck_release >= 0.0) {
+            return Err(TranscoderError::Argument("Duck release must be a non-negative number of seconds".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn duck_settings(&self) -> DuckSettings {
+        DuckSettings {
+            threshold_db: self.duck_threshold,
+            amount_db: self.duck_amount,
+            release: Duration::from_secs_f64(self.duck_release),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SplitChannelsArgs {
+    /// input audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub input: PathBuf,
+
+    /// output path template; `{label}` is replaced by the speaker of the channel (e.g. FL, LFE), `{index}` by its 1-based number
+    #[arg(short, long, value_name = "TEMPLATE")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeChannelsArgs {
+    /// mono input audio file paths, one per output channel in order (may be repeated)
+    #[arg(short, long = "input", value_name = "FILE", required = true, num_args = 1..)]
+    pub inputs: Vec<PathBuf>,
+
+    /// output audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// speakers the inputs are assigned to, as a layout name (stereo, quad, 5.1, 7.1, ...), speakers joined by `+`
+    /// (e.g. FL+FR+LFE) or a channel mask (e.g. 0x3F); defaults to the standard layout for the number of inputs
+    #[arg(long, value_name = "LAYOUT", value_parser = channel_layout::parse_mask)]
+    pub layout: Option<u32>,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// job file (.toml or .json) listing inputs, their outputs and options
+    #[arg(value_name = "JOBFILE")]
+    pub job_file: PathBuf,
+
+    /// manifest recording the outputs of the job file across runs, defaults to the job file with the extension
+    /// `.manifest.jsonl`
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// transcode every output, even those the manifest lists as up to date
+    #[arg(long)]
+    pub force: bool,
+
+    /// defaults for every transcode in the job file, which the options given there override; --timeout limits every job
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// folder watched for new audio files
+    #[arg(value_name = "DIR")]
+    pub dir: PathBuf,
+
+    /// folder the transcoded files are written to
+    #[arg(short, long, value_name = "DIR")]
+    pub output_dir: PathBuf,
+
+    /// extension of the transcoded files, which selects their format (e.g. mp3)
+    #[arg(short, long, value_name = "EXT")]
+    pub extension: String,
+
+    /// TOML or JSON file with a table of transcode options named as in job files, overriding the command line options
+    #[arg(long, value_name = "FILE")]
+    pub profile: Option<PathBuf>,
+
+    /// folder successfully transcoded originals are moved to, defaults to `done` in the watched folder
+    #[arg(long, value_name = "DIR")]
+    pub done_dir: Option<PathBuf>,
+
+    /// folder originals that failed to transcode are moved to, defaults to `failed` in the watched folder
+    #[arg(long, value_name = "DIR")]
+    pub failed_dir: Option<PathBuf>,
+
+    /// seconds the size and modification time of a new file must stay unchanged before it is transcoded
+    #[arg(long, value_name = "SECONDS", default_value_t = 2.0)]
+    pub settle: f64,
+
+    /// options of every transcode, --timeout limiting each file
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+impl WatchArgs {
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if !self.settle.is_finite() || self.settle < 0.0 {
+            return Err(TranscoderError::Argument(format!("Settle time must be a non-negative number of seconds, got {}", self.settle)));
+        }
+        if self.extension.trim_start_matches('.').is_empty() {
+            return Err(TranscoderError::Argument("Output extension must not be empty".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// address and port to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    /// number of transcodes run at the same time
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub workers: usize,
+
+    /// number of jobs waiting for a worker before submissions are rejected with 503
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    pub queue_size: usize,
+
+    /// directory for uploads and outputs, which are kept until their job is deleted; defaults to a temporary directory
+    /// removed when the server stops
+    #[arg(long, value_name = "DIR")]
+    pub work_dir: Option<PathBuf>,
+
+    /// largest accepted upload in MB
+    #[arg(long, value_name = "MB", default_value_t = 1024)]
+    pub max_upload: u64,
+
+    /// directory local files may be submitted from by path; without it only uploads are accepted
+    #[arg(long, value_name = "DIR")]
+    pub input_root: Option<PathBuf>,
+
+    /// defaults for every job, which the options of a submission override; --timeout limits every job
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+impl ServeArgs {
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if self.workers == 0 || self.queue_size == 0 {
+            return Err(TranscoderError::Argument("--workers and --queue-size must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// original audio file path
+    #[arg(short, long, value_name = "FILE")]
+    pub input: PathBuf,
+
+    /// transcoded audio file path compared with the input
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub thresholds: VerifyThresholdArgs,
+
+    /// options the output was transcoded with; --sample-rate, --channels and --raw-format describe raw PCM outputs
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+/// thresholds for outputs that cannot match their input sample for sample, being lossy, resampled or of lower bit depth
+#[derive(Args, Debug, Clone)]
+pub struct VerifyThresholdArgs {
+    /// lowest signal-to-noise ratio in dB of such an output against its input
+    #[arg(long, value_name = "DB", default_value_t = 10.0, allow_hyphen_values = true)]
+    pub min_snr: f64,
+
+    /// lowest correlation between such an output and its input, from -1 to 1
+    #[arg(long, value_name = "R", default_value_t = 0.95, allow_hyphen_values = true)]
+    pub min_correlation: f64,
+}
+
+impl VerifyThresholdArgs {
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if !self.min_snr.is_finite() || !(-1.0..=1.0).contains(&self.min_correlation) {
+            return Err(TranscoderError::Argument("--min-snr must be a finite number of dB and --min-correlation between -1 and 1".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn settings(&self) -> VerifySettings {
+        VerifySettings { min_snr_db: self.min_snr, min_correlation: self.min_correlation }
+    }
+}
+
+/// encoding and processing options shared by transcoding and all subcommands
+#[derive(Args, Debug, Clone)]
+pub struct TranscodeArgs {
+    /// input format (e.g. wav, flac, mp3, aac), overriding detection from the file content and extension
+    #[arg(long, value_name = "FORMAT")]
+    pub input_format: Option<String>,
+
+    /// output format (e.g. wav, mp3, ogg, m4a), overriding the output file extension
+    #[arg(long, value_name = "FORMAT")]
+    pub output_format: Option<String>,
+
+    /// desired output audio codec
+    /// if not specified, ffmpeg will choose a default for the format
+    /// codecs without a native encoder are routed to ffmpeg
+    #[arg(long)]
+    pub codec: Option<String>,
+
+    /// desired output bitrate in kbps
+    /// primarily for lossy codecs; if not specified, the encoder will choose a default
+    /// lossless codecs will ignore this option
+    #[arg(long, value_name = "KBPS")]
+    pub bitrate: Option<u32>,
+
+    /// desired output sample rate in Hz
+    #[arg(long, value_name = "HZ")]
+    pub sample_rate: Option<u32>,
+
+    /// desired number of output audio channels
+    #[arg(long, value_name = "NUM")]
+    pub channels: Option<u8>,
+
+    /// quality preset mapped to codec-specific parameters (LAME VBR quality, Opus bitrate and application,
+    /// Vorbis and AAC VBR quality, FLAC compression level); explicit bitrate options take precedence
+    #[arg(long, value_enum)]
+    pub quality_preset: Option<presets::QualityPreset>,
+
+    /// highpass filter removing rumble below the cutoff, as HZ[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ[:Q]", value_parser = |value: &str| BiquadSpec::parse(BiquadKind::Highpass, value))]
+    pub highpass: Vec<BiquadSpec>,
+
+    /// lowpass filter removing content above the cutoff, as HZ[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ[:Q]", value_parser = |value: &str| BiquadSpec::parse(BiquadKind::Lowpass, value))]
+    pub lowpass: Vec<BiquadSpec>,
+
+    /// bandpass filter around a center frequency, as HZ[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ[:Q]", value_parser = |value: &str| BiquadSpec::parse(BiquadKind::Bandpass, value))]
+    pub bandpass: Vec<BiquadSpec>,
+
+    /// notch filter removing a narrow band (e.g. mains hum), as HZ[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ[:Q]", value_parser = |value: &str| BiquadSpec::parse(BiquadKind::Notch, value))]
+    pub notch: Vec<BiquadSpec>,
+
+    /// low shelf boosting or cutting below the corner frequency, as HZ:GAIN_DB[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ:GAIN[:Q]", allow_hyphen_values = true, value_parser = |value: &str| BiquadSpec::parse(BiquadKind::LowShelf, value))]
+    pub low_shelf: Vec<BiquadSpec>,
+
+    /// high shelf boosting or cutting above the corner frequency, as HZ:GAIN_DB[:Q] (may be repeated)
+    #[arg(long, value_name = "HZ:GAIN[:Q]", allow_hyphen_values = true, value_parser = |value: &str| BiquadSpec::parse(BiquadKind::HighShelf, value))]
+    pub high_shelf: Vec<BiquadSpec>,
+
+    /// peaking EQ band, as HZ:GAIN_DB[:Q] (e.g. 3000:+2:1.0, may be repeated)
+    #[arg(long, value_name = "HZ:GAIN[:Q]", allow_hyphen_values = true, value_parser = |value: &str| BiquadSpec::parse(BiquadKind::Peaking, value))]
+    pub eq: Vec<BiquadSpec>,
+
+    /// ffmpeg audio filter graph (e.g. "highpass=f=80,volume=-3dB")
+    /// chains of volume, highpass, lowpass and aresample run natively, anything else routes the job to ffmpeg
+    #[arg(long = "af", value_name = "FILTERGRAPH")]
+    pub audio_filter: Option<String>,
+
+    /// gain in dB applied after all filters (e.g. -3 or +6)
+    #[arg(long, value_name = "DB", allow_hyphen_values = true)]
+    pub gain: Option<f32>,
+
+    /// normalize the output peak to the given level in dBFS (e.g. -1), measured in an extra pass over the input
+    /// requires native input and output formats
+    #[arg(long, value_name = "DBFS", allow_hyphen_values = true)]
+    pub normalize_peak: Option<f32>,
+
+    /// soft-limit the output below the given ceiling in dBFS instead of hard clipping overs, defaults to -1
+    #[arg(long, value_name = "DBFS", num_args = 0..=1, default_missing_value = "-1", allow_hyphen_values = true)]
+    pub soft_limit: Option<f32>,
+
+    /// bitrate mode for MP3 output (cbr, vbr, abr)
+    /// defaults to VBR unless only a bitrate is given, which selects CBR
+    #[arg(long, value_enum)]
+    pub bitrate_mode: Option<transcoders::BitrateMode>,
+
+    /// VBR quality level for MP3 output, from 0 (best) to 9 (smallest)
+    #[arg(long, value_name = "0-9", value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub vbr_quality: Option<u8>,
+
+    /// encode MP3 output in joint stereo mode (the encoder default)
+    #[arg(long, overrides_with = "no_joint_stereo")]
+    pub joint_stereo: bool,
+
+    /// encode MP3 output in plain stereo mode
+    #[arg(long, overrides_with = "joint_stereo")]
+    pub no_joint_stereo: bool,
+
+    /// sample layout of raw PCM input and output (.raw, .pcm), defaults to s16le
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub raw_format: Option<codecs::raw::RawPcmFormat>,
+
+    /// sample rate of raw PCM input in Hz
+    #[arg(long, value_name = "HZ")]
+    pub raw_rate: Option<u32>,
+
+    /// number of channels of raw PCM input
+    #[arg(long, value_name = "NUM")]
+    pub raw_channels: Option<u8>,
+
+    /// path to the ffmpeg binary used as the fallback transcoder
+    /// defaults to the REWAV_FFMPEG environment variable, then to `ffmpeg` from the PATH
+    #[arg(long, value_name = "PATH")]
+    pub ffmpeg_path: Option<PathBuf>,
+
+    /// number of threads ffmpeg should use for encoding
+    /// defaults to the number of logical CPU cores
+    /// applicable only to the fallback ffmpeg transcoder
+    #[arg(long, value_name = "NUM")]
+    pub threads: Option<usize>,
+
+    /// replace the output file if it already exists (the default)
+    #[arg(long, overrides_with = "no_overwrite")]
+    pub overwrite: bool,
+
+    /// fail instead of replacing an existing output file
+    #[arg(long, overrides_with = "overwrite")]
+    pub no_overwrite: bool,
+
+    /// strip leading and trailing silence (see --silence-threshold and --min-silence)
+    #[arg(long)]
+    pub trim_silence: bool,
+
+    /// level in dBFS below which audio counts as silence, for --trim-silence and split
+    #[arg(long, value_name = "DB", default_value_t = -50.0, allow_hyphen_values = true)]
+    pub silence_threshold: f32,
+
+    /// shortest silence in seconds that is trimmed or split at
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5)]
+    pub min_silence: f64,
+
+    /// abort the transcode after the given number of seconds, removing the partial output
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// disable the progress bar, which is only shown when stderr is a terminal
+    #[arg(long)]
+    pub no_progress: bool,
+}
+
+impl TranscodeArgs {
+    /// rejects values clap cannot check on its own
+    pub fn validate(&self) -> Result<(), TranscoderError> {
+        if self.sample_rate == Some(0) || self.channels == Some(0) || self.raw_rate == Some(0) || self.raw_channels == Some(0) {
+            return Err(TranscoderError::Argument("Sample rate and number of channels must be greater than zero".to_string()));
+        }
+        if [self.gain, self.normalize_peak, self.soft_limit, Some(self.silence_threshold)].iter().flatten().any(|level| !level.is_finite()) {
+            return Err(TranscoderError::Argument("Gain and levels must be finite numbers of dB".to_string()));
+        }
+        if !(self.min_silence.is_finite() && self.min_silence >= 0.0) {
+            return Err(TranscoderError::Argument("Minimum silence must be a non-negative number of seconds".to_string()));
+        }
+        Ok(())
+    }
+
+    /// silence detection settings for --trim-silence and split
+    pub fn silence_settings(&self) -> SilenceSettings {
+        SilenceSettings {
+            threshold_db: self.silence_threshold,
+            min_duration: Duration::from_secs_f64(self.min_silence),
+        }
+    }
+
+    /// builds the transcode options for an output, detecting its format from the extension unless --output-format is given
+    pub fn to_options(
+        &self,
+        output_path: &Path,
+        progress: Option<ProgressCallback>,
+        cancellation: CancellationToken,
+    ) -> Result<TranscodeOptions, TranscoderError> {
+        let output_format = format_detection::detect_output_format(output_path, self.output_format.as_deref())?;
+        if output_format.format.is_empty() {
+            return Err(TranscoderError::Path(format!("Output file path must have an extension: {}", output_path.display())));
+        }
+
+        Ok(TranscodeOptions {
+            output_format_extension: output_format.format,
+            input_format: self.input_format.as_deref().map(format_detection::canonical_format),
+            output_format: self.output_format.as_deref().map(format_detection::canonical_format),
+            output_codec: self.codec.clone(),
+            bitrate_kbps: self.bitrate,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            channel_mask: None,
+            quality_preset: self.quality_preset,
+            // the filters are linear and time-invariant, so their order does not change the result
+            equalizer: [&self.highpass, &self.lowpass, &self.bandpass, &self.notch, &self.low_shelf, &self.high_shelf, &self.eq]
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+            audio_filter: self.audio_filter.clone(),
+            gain_db: self.gain,
+            normalize_peak_dbfs: self.normalize_peak,
+            soft_limit_dbfs: self.soft_limit,
+            trim_silence: self.trim_silence.then(|| self.silence_settings()),
+            threads: self.threads,
+            bitrate_mode: self.bitrate_mode,
+            vbr_quality: self.vbr_quality,
+            joint_stereo: match (self.joint_stereo, self.no_joint_stereo) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            raw_format: self.raw_format,
+            raw_sample_rate: self.raw_rate,
+            raw_channels: self.raw_channels,
+            progress,
+            cancellation,
+            overwrite: !self.no_overwrite,
+            ffmpeg_path: self.ffmpeg_path.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Command {
+        CliArgs::try_parse_from([&["rewav"], args].concat()).unwrap().command.unwrap()
+    }
+
+    #[test]
+    fn tells_which_subcommands_write_to_stdout() {
+        assert!(command(&["concat", "-i", "a.wav", "-i", "b.wav", "-o", "-", "--output-format", "wav"]).writes_stdout());
+        assert!(command(&["mix", "-i", "a.wav", "-o", "-", "--output-format", "wav"]).writes_stdout());
+        assert!(command(&["merge-channels", "-i", "l.wav", "-i", "r.wav", "-o", "-", "--output-format", "wav"]).writes_stdout());
+        assert!(!command(&["concat", "-i", "a.wav", "-i", "b.wav", "-o", "out.wav"]).writes_stdout());
+        assert!(!command(&["split", "-i", "a.wav", "-o", "part{index}.wav"]).writes_stdout());
+    }
+}
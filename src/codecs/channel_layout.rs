@@ -0,0 +1,64 @@
+/// speaker positions of the WAVE_FORMAT_EXTENSIBLE channel mask, in bit order, named as in ffmpeg
+const SPEAKERS: [&str; 18] = [
+    "FL", "FR", "FC", "LFE", "BL", "BR", "FLC", "FRC", "BC", "SL", "SR", "TC", "TFL", "TFC", "TFR", "TBL", "TBC", "TBR",
+];
+
+/// common layouts by name, as understood by ffmpeg
+const LAYOUTS: [(&str, u32); 10] = [
+    ("mono", 0x4),
+    ("stereo", 0x3),
+    ("2.1", 0xB),
+    ("3.0", 0x7),
+    ("quad", 0x33),
+    ("4.0", 0x107),
+    ("5.0", 0x37),
+    ("5.1", 0x3F),
+    ("6.1", 0x70F),
+    ("7.1", 0x63F),
+];
+
+/// the channel mask assumed for `channels` channels without an explicit one, following the FLAC channel assignments
+pub fn default_mask(channels: u8) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33,
+        5 => 0x37,
+        6 => 0x3F,
+        7 => 0x70F,
+        8 => 0x63F,
+        // no standard layout, the channels are left unassigned
+        _ => 0,
+    }
+}
+
+/// a label for every channel of a stream with the given mask, e.g. `FL`, `FR`; channels beyond the mask are numbered
+pub fn labels(mask: u32, channels: u8) -> Vec<String> {
+    let mut speakers = SPEAKERS.iter().enumerate().filter(|(bit, _)| mask & (1 << bit) != 0).map(|(_, name)| name.to_string());
+    (1..=channels as usize)
+        .map(|channel| speakers.next().unwrap_or_else(|| format!("ch{}", channel)))
+        .collect()
+}
+
+/// parses a channel mask given as a layout name (`5.1`), speakers joined by `+` (`FL+FR+LFE`) or a number (`0x3F`)
+pub fn parse_mask(value: &str) -> Result<u32, String> {
+    if let Some((_, mask)) = LAYOUTS.iter().find(|(name, _)| name.eq_ignore_ascii_case(value)) {
+        return Ok(*mask);
+    }
+    let number = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+    if let Some(mask) = number {
+        return Ok(mask);
+    }
+
+    value.split('+').try_fold(0, |mask, speaker| {
+        let bit = SPEAKERS.iter().position(|name| name.eq_ignore_ascii_case(speaker)).ok_or_else(|| format!(
+            "unknown channel layout or speaker {:?}, expected one of {} or speakers such as FL+FR+LFE",
+            speaker, LAYOUTS.map(|(name, _)| name).join(", ")
+        ))?;
+        Ok(mask | 1 << bit)
+    })
+}
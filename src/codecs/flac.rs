@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::path::Path;
+use log::info;
+use claxon::FlacReader;
+use md5::{Digest, Md5};
+use crate::audio_processor;
+use crate::codecs::{channel_layout, AudioDecoder, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::utils;
+
+/// decodes FLAC files or streams block by block with `claxon`
+pub struct FlacDecoder {
+    reader: FlacReader<Box<dyn Read>>,
+    spec: StreamSpec,
+    // decoded interleaved samples not yet handed out
+    pending: VecDeque<f32>,
+    block_buffer: Vec<i32>,
+}
+
+impl FlacDecoder {
+    pub fn open(input_path: &Path) -> Result<Self, TranscoderError> {
+        // claxon only needs `Read`, so FLAC can be decoded from stdin as well
+        let reader = FlacReader::new(utils::open_input(input_path)?)
+            .map_err(|e| TranscoderError::Flac(format!("Failed to create FLAC decoder: {:?}", e)))?;
+
+        let stream_info = reader.streaminfo();
+        info!("Input FLAC stream info: {:?}", stream_info);
+
+        let spec = StreamSpec {
+            sample_rate: stream_info.sample_rate,
+            channels: stream_info.channels as u8,
+            bits_per_sample: stream_info.bits_per_sample as u16,
+            is_float: false,
+            total_frames: stream_info.samples,
+        };
+
+        Ok(Self {
+            reader,
+            spec,
+            pending: VecDeque::new(),
+            block_buffer: Vec::new(),
+        })
+    }
+
+    // decodes the next FLAC block into the pending queue, returns false at the end of the stream
+    fn decode_block(&mut self) -> Result<bool, TranscoderError> {
+        let buffer = std::mem::take(&mut self.block_buffer);
+        let block = self.reader
+            .blocks()
+            .read_next_or_eof(buffer)
+            .map_err(|e| TranscoderError::Flac(format!("Error decoding FLAC block: {:?}", e)))?;
+
+        let Some(block) = block else {
+            return Ok(false);
+        };
+
+        // claxon stores blocks channel after channel, the pipeline expects interleaved frames
+        let mut interleaved = Vec::with_capacity(block.len() as usize);
+        for i in 0..block.duration() {
+            for c in 0..block.channels() {
+                interleaved.push(block.sample(c, i));
+            }
+        }
+        self.pending.extend(audio_processor::i32_to_f32(&interleaved, self.spec.bits_per_sample));
+        self.block_buffer = block.into_buffer();
+        Ok(true)
+    }
+}
+
+impl AudioDecoder for FlacDecoder {
+    fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    fn tags(&self) -> Tags {
+        self.reader
+            .tags()
+            .map(|(key, value)| (key.to_ascii_uppercase(), value.to_string()))
+            .collect()
+    }
+
+    fn channel_mask(&self) -> Option<u32> {
+        // FLAC declares layouts beyond the standard channel assignments in a Vorbis comment
+        self.reader
+            .get_tag("WAVEFORMATEXTENSIBLE_CHANNEL_MASK")
+            .next()
+            .and_then(|mask| channel_layout::parse_mask(mask).ok())
+    }
+
+    fn read_frames(&mut self, max_frames: usize) -> Result<Vec<f32>, TranscoderError> {
+        let max_samples = max_frames * self.spec.channels as usize;
+        while self.pending.len() < max_samples {
+            if !self.decode_block()? {
+                break;
+            }
+        }
+
+        let available = self.pending.len().min(max_samples);
+        Ok(self.pending.drain(..available).collect())
+    }
+}
+
+/// checks the decoded samples of a FLAC file against the MD5 signature in its STREAMINFO block
+/// returns None if the encoder left the signature unset
+pub fn check_md5(input_path: &Path) -> Result<Option<bool>, TranscoderError> {
+    let mut reader = FlacReader::new(utils::open_input(input_path)?)
+        .map_err(|e| TranscoderError::Flac(format!("Failed to create FLAC decoder: {:?}", e)))?;
+    let stream_info = reader.streaminfo();
+    if stream_info.md5sum == [0; 16] {
+        return Ok(None);
+    }
+
+    // the signature covers the interleaved samples as little-endian integers of whole bytes
+    let bytes_per_sample = stream_info.bits_per_sample.div_ceil(8) as usize;
+    let mut hasher = Md5::new();
+    let mut blocks = reader.blocks();
+    let mut buffer = Vec::new();
+    let mut bytes = Vec::new();
+    while let Some(block) = blocks
+        .read_next_or_eof(buffer)
+        .map_err(|e| TranscoderError::Flac(format!("Error decoding FLAC block: {:?}", e)))?
+    {
+        bytes.clear();
+        for i in 0..block.duration() {
+            for c in 0..block.channels() {
+                bytes.extend_from_slice(&block.sample(c, i).to_le_bytes()[..bytes_per_sample]);
+            }
+        }
+        hasher.update(&bytes);
+        buffer = block.into_buffer();
+    }
+    Ok(Some(hasher.finalize()[..] == stream_info.md5sum))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/short.flac");
+    // the MD5 signature ends STREAMINFO, after the "fLaC" marker, the block header and 18 bytes of stream parameters
+    const MD5_OFFSET: usize = 4 + 4 + 18;
+
+    // copies the fixture with its MD5 signature replaced by `change`
+    fn altered_fixture(dir: &TempDir, change: impl Fn(&mut [u8])) -> std::path::PathBuf {
+        let mut bytes = std::fs::read(FIXTURE).unwrap();
+        change(&mut bytes[MD5_OFFSET..MD5_OFFSET + 16]);
+        let path = dir.path().join("altered.flac");
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn accepts_a_matching_signature() {
+        assert_eq!(check_md5(Path::new(FIXTURE)).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn detects_a_corrupted_signature() {
+        let dir = TempDir::new().unwrap();
+        let path = altered_fixture(&dir, |md5| md5[0] ^= 0xff);
+        assert_eq!(check_md5(&path).unwrap(), Some(false));
+    }
+
+    #[test]
+    fn reports_a_missing_signature() {
+        let dir = TempDir::new().unwrap();
+        let path = altered_fixture(&dir, |md5| md5.fill(0));
+        assert_eq!(check_md5(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,245 @@
+pub mod wav;
+pub mod flac;
+pub mod raw;
+pub mod channel_layout;
+#[cfg(feature = "vorbis")]
+pub mod vorbis;
+#[cfg(feature = "opus")]
+pub mod opus;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+
+use std::path::Path;
+use log::{debug, warn};
+use crate::errors::TranscoderError;
+use crate::transcoders::TranscodeOptions;
+use crate::utils;
+
+/// description of an interleaved PCM stream flowing through the native pipeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamSpec {
+    /// sample rate in Hz
+    pub sample_rate: u32,
+    /// number of interleaved channels
+    pub channels: u8,
+    /// bit depth of the stored samples
+    pub bits_per_sample: u16,
+    /// whether the stored samples are IEEE floats rather than integers
+    pub is_float: bool,
+    /// number of frames in the stream, if known up front
+    pub total_frames: Option<u64>,
+}
+
+/// metadata tags as (key, value) pairs, keys follow the Vorbis comment convention (e.g. `ARTIST`, `TITLE`)
+pub type Tags = Vec<(String, String)>;
+
+/// a source of interleaved f32 samples normalized to [-1.0, 1.0]
+pub trait AudioDecoder {
+    /// specification of the decoded stream
+    fn spec(&self) -> StreamSpec;
+
+    /// metadata tags found in the input
+    fn tags(&self) -> Tags {
+        Vec::new()
+    }
+
+    /// speaker assignment of the channels as a WAVE_FORMAT_EXTENSIBLE mask, if the input declares one
+    fn channel_mask(&self) -> Option<u32> {
+        None
+    }
+
+    /// reads up to `max_frames` interleaved frames; an empty vector signals the end of the stream
+    fn read_frames(&mut self, max_frames: usize) -> Result<Vec<f32>, TranscoderError>;
+}
+
+/// a sink consuming interleaved f32 samples normalized to [-1.0, 1.0]
+pub trait AudioEncoder {
+    /// encodes interleaved samples; partial frames are not allowed
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError>;
+
+    /// flushes buffered audio and finalizes the container
+    fn finalize(self: Box<Self>) -> Result<(), TranscoderError>;
+}
+
+/// output codecs implemented natively
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeCodec {
+    Wav,
+    Raw,
+    #[cfg(feature = "vorbis")]
+    Vorbis,
+    #[cfg(feature = "opus")]
+    Opus,
+    #[cfg(feature = "mp3")]
+    Mp3,
+}
+
+impl NativeCodec {
+    /// selects a native encoder for the output extension and the optionally requested codec name
+    /// returns None if the combination has to be handled by ffmpeg
+    pub fn for_output(extension: &str, codec: Option<&str>) -> Option<Self> {
+        match (extension, codec) {
+            ("wav", None) => Some(NativeCodec::Wav),
+            ("raw", None) => Some(NativeCodec::Raw),
+            #[cfg(feature = "vorbis")]
+            ("ogg" | "oga", None | Some("vorbis" | "libvorbis")) => Some(NativeCodec::Vorbis),
+            #[cfg(feature = "opus")]
+            ("opus", None | Some("opus" | "libopus")) | ("ogg" | "oga", Some("opus" | "libopus")) => Some(NativeCodec::Opus),
+            #[cfg(feature = "mp3")]
+            ("mp3", None | Some("mp3" | "libmp3lame")) => Some(NativeCodec::Mp3),
+            _ => None,
+        }
+    }
+
+    /// checks whether the encoder can write to a non-seekable output such as stdout
+    pub fn supports_streaming(self) -> bool {
+        matches!(self, NativeCodec::Wav | NativeCodec::Raw)
+    }
+
+    /// derives the stream specification handed to the encoder from the decoded input and the requested options
+    pub fn output_spec(self, input: &StreamSpec, options: &TranscodeOptions) -> Result<StreamSpec, TranscoderError> {
+        let spec = StreamSpec {
+            sample_rate: options.sample_rate.unwrap_or(input.sample_rate),
+            channels: options.channels.unwrap_or(input.channels),
+            ..*input
+        };
+
+        match self {
+            NativeCodec::Wav => Ok(spec),
+            NativeCodec::Raw => {
+                let format = options.raw_format.unwrap_or(raw::RawPcmFormat::S16le);
+                Ok(StreamSpec { bits_per_sample: (format.bytes_per_sample() * 8) as u16, is_float: format.is_float(), ..spec })
+            }
+            #[cfg(feature = "vorbis")]
+            NativeCodec::Vorbis => Ok(StreamSpec { bits_per_sample: 32, is_float: true, ..spec }),
+            #[cfg(feature = "opus")]
+            NativeCodec::Opus => {
+                let mut spec = StreamSpec { bits_per_sample: 32, is_float: true, ..spec };
+                // Ogg Opus is always decoded at 48 kHz
+                if spec.sample_rate != opus::OPUS_SAMPLE_RATE {
+                    if options.sample_rate.is_some() {
+                        warn!("Opus output is always encoded at {} Hz, ignoring requested sample rate of {} Hz", opus::OPUS_SAMPLE_RATE, spec.sample_rate);
+                    }
+                    spec.sample_rate = opus::OPUS_SAMPLE_RATE;
+                }
+                spec.channels = limit_to_stereo(spec.channels, options, "Opus")?;
+                Ok(spec)
+            }
+            #[cfg(feature = "mp3")]
+            NativeCodec::Mp3 => {
+                let mut spec = StreamSpec { bits_per_sample: 32, is_float: true, ..spec };
+                if !mp3::MP3_SAMPLE_RATES.contains(&spec.sample_rate) {
+                    let nearest = mp3::MP3_SAMPLE_RATES.iter()
+                        .copied()
+                        .min_by_key(|rate| rate.abs_diff(spec.sample_rate))
+                        .unwrap_or(44100);
+                    warn!("MP3 does not support a sample rate of {} Hz, resampling to {} Hz", spec.sample_rate, nearest);
+                    spec.sample_rate = nearest;
+                }
+                spec.channels = limit_to_stereo(spec.channels, options, "MP3")?;
+                Ok(spec)
+            }
+        }
+    }
+
+    /// creates the encoder writing to `output_path`
+    pub fn create_encoder(
+        self,
+        output_path: &Path,
+        spec: &StreamSpec,
+        options: &TranscodeOptions,
+        tags: &Tags,
+    ) -> Result<Box<dyn AudioEncoder>, TranscoderError> {
+        match self {
+            NativeCodec::Wav => {
+                if options.bitrate_kbps.is_some() {
+                    warn!("Bitrate is ignored for lossless WAV output");
+                }
+                if !tags.is_empty() {
+                    debug!("WAV output does not carry metadata, dropping {} tags", tags.len());
+                }
+                if utils::is_stdio(output_path) {
+                    if options.channel_mask.is_some() {
+                        warn!("Streamed WAV output does not carry a channel mask");
+                    }
+                    return Ok(Box::new(wav::create_stream_encoder(utils::create_output(output_path)?, spec)?));
+                }
+                Ok(Box::new(wav::WavEncoder::create(output_path, spec, options.channel_mask)?))
+            }
+            NativeCodec::Raw => {
+                if options.bitrate_kbps.is_some() {
+                    warn!("Bitrate is ignored for raw PCM output");
+                }
+                if !tags.is_empty() {
+                    debug!("Raw PCM output does not carry metadata, dropping {} tags", tags.len());
+                }
+                let format = options.raw_format.unwrap_or(raw::RawPcmFormat::S16le);
+                Ok(Box::new(raw::RawPcmEncoder::create(output_path, format)?))
+            }
+            #[cfg(feature = "vorbis")]
+            NativeCodec::Vorbis => Ok(Box::new(vorbis::VorbisEncoder::create(output_path, spec, options, tags)?)),
+            #[cfg(feature = "opus")]
+            NativeCodec::Opus => Ok(Box::new(opus::OpusEncoder::create(output_path, spec, options, tags)?)),
+            #[cfg(feature = "mp3")]
+            NativeCodec::Mp3 => Ok(Box::new(mp3::Mp3Encoder::create(output_path, spec, options, tags)?)),
+        }
+    }
+}
+
+/// checks whether inputs of the detected format extension can be decoded natively
+pub fn supports_native_input(format_extension: &str) -> bool {
+    matches!(format_extension, "wav" | "flac" | "raw")
+}
+
+/// opens a native decoder for the input file, or stdin for `-`, based on its detected format extension
+/// raw PCM inputs are described by the `raw_*` options since they carry no header
+pub fn open_decoder(
+    input_path: &Path,
+    format_extension: &str,
+    options: &TranscodeOptions,
+) -> Result<Box<dyn AudioDecoder>, TranscoderError> {
+    match format_extension {
+        "wav" if utils::is_stdio(input_path) => Ok(Box::new(wav::WavStreamDecoder::from_reader(utils::open_input(input_path)?)?)),
+        "wav" => match wav::WavDecoder::open(input_path) {
+            Ok(decoder) => Ok(Box::new(decoder)),
+            // files captured from a WAV stream keep the placeholder sizes that `hound` rejects
+            Err(e) => {
+                debug!("Falling back to the streaming WAV parser: {}", e);
+                let decoder = wav::WavStreamDecoder::from_reader(utils::open_input(input_path)?).map_err(|_| e)?;
+                Ok(Box::new(decoder))
+            }
+        },
+        "flac" => Ok(Box::new(flac::FlacDecoder::open(input_path)?)),
+        "raw" => {
+            let (sample_rate, channels) = raw_input_layout(options)?;
+            let format = options.raw_format.unwrap_or(raw::RawPcmFormat::S16le);
+            Ok(Box::new(raw::RawPcmDecoder::open(input_path, format, sample_rate, channels)?))
+        }
+        other => Err(TranscoderError::UnsupportedInputFormat(format!("no native decoder for '{}'", other))),
+    }
+}
+
+/// returns the sample rate and channel count of a raw PCM input, which have to be given explicitly
+pub fn raw_input_layout(options: &TranscodeOptions) -> Result<(u32, u8), TranscoderError> {
+    match (options.raw_sample_rate, options.raw_channels) {
+        (Some(sample_rate), Some(channels)) => Ok((sample_rate, channels)),
+        _ => Err(TranscoderError::Argument(
+            "raw PCM input requires --raw-rate and --raw-channels to describe its layout".to_string(),
+        )),
+    }
+}
+
+#[cfg(any(feature = "opus", feature = "mp3"))]
+// lossy encoders limited to mono and stereo mix wider inputs down unless more channels were requested explicitly
+fn limit_to_stereo(channels: u8, options: &TranscodeOptions, encoder_name: &str) -> Result<u8, TranscoderError> {
+    if channels <= 2 {
+        return Ok(channels);
+    }
+    if options.channels.is_some() {
+        return Err(TranscoderError::UnsupportedOutputFormat(format!(
+            "native {} encoder supports at most 2 channels, {} requested", encoder_name, channels
+        )));
+    }
+    warn!("Native {} encoder supports at most 2 channels, mixing {} input channels down to stereo", encoder_name, channels);
+    Ok(2)
+}
@@ -0,0 +1,325 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use log::{debug, info, warn};
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm, Mode, MonoPcm, Quality, VbrMode};
+use crate::codecs::{AudioEncoder, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::presets;
+use crate::transcoders::{BitrateMode, TranscodeOptions};
+
+/// sample rates supported by MPEG-1, MPEG-2 and MPEG-2.5 layer III
+pub const MP3_SAMPLE_RATES: [u32; 9] = [8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+/// encodes MP3 files with LAME, prefixed by an ID3v2.4 tag built from the input metadata
+pub struct Mp3Encoder {
+    encoder: mp3lame_encoder::Encoder,
+    writer: BufWriter<File>,
+    channels: usize,
+    // offset of the first MPEG frame, which LAME reserves for the Xing/LAME info tag
+    audio_start: u64,
+    buffer: Vec<u8>,
+}
+
+impl Mp3Encoder {
+    pub fn create(
+        output_path: &Path,
+        spec: &StreamSpec,
+        options: &TranscodeOptions,
+        tags: &Tags,
+    ) -> Result<Self, TranscoderError> {
+        // the preset fills in the bitrate options unless any of them was given explicitly
+        let explicit_bitrate = options.bitrate_kbps.is_some();
+        let options = &presets::with_mp3_preset(options);
+
+        let mut builder = Builder::new()
+            .ok_or_else(|| TranscoderError::Mp3("Failed to allocate LAME encoder".to_string()))?;
+        let lame_error = |e: mp3lame_encoder::BuildError| TranscoderError::Mp3(format!("Failed to configure LAME encoder: {}", e));
+
+        builder.set_num_channels(spec.channels).map_err(lame_error)?;
+        builder.set_sample_rate(spec.sample_rate).map_err(lame_error)?;
+        // the pipeline already resampled to a valid MPEG rate, LAME must not resample again
+        builder.set_output_sample_rate(std::num::NonZeroU32::new(spec.sample_rate)).map_err(lame_error)?;
+
+        let mode = match (spec.channels, options.joint_stereo) {
+            (1, _) => Mode::Mono,
+            (_, Some(false)) => Mode::Stereo,
+            _ => Mode::JointStereo,
+        };
+        builder.set_mode(mode).map_err(lame_error)?;
+
+        // algorithm quality follows the preset, independently of the bitrate mode
+        if let Some(preset) = options.quality_preset {
+            builder.set_quality(lame_quality(preset.mp3().algorithm_quality)).map_err(lame_error)?;
+        }
+
+        let bitrate_mode = resolve_bitrate_mode(options);
+        match bitrate_mode {
+            BitrateMode::Cbr => {
+                let (_, bitrates) = mpeg_bitrates(spec.sample_rate);
+                // bitrates of presets are capped at the highest one of the MPEG version, explicit ones are checked
+                let kbps = match options.bitrate_kbps {
+                    Some(kbps) if explicit_bitrate => kbps,
+                    kbps => kbps.unwrap_or(128).min(bitrates[bitrates.len() - 1]),
+                };
+                builder.set_vbr_mode(VbrMode::Off).map_err(lame_error)?;
+                builder.set_brate(lame_bitrate(kbps, spec.sample_rate)?).map_err(lame_error)?;
+                info!("MP3 encoder: CBR at {} kbps", kbps);
+            }
+            BitrateMode::Abr => {
+                let kbps = options.bitrate_kbps.ok_or_else(|| {
+                    TranscoderError::Argument("ABR encoding requires a target bitrate".to_string())
+                })?;
+                let (version, bitrates) = mpeg_bitrates(spec.sample_rate);
+                let (min, max) = (bitrates[0], bitrates[bitrates.len() - 1]);
+                if !(min..=max).contains(&kbps) {
+                    return Err(TranscoderError::Argument(format!(
+                        "{} kbps is not a valid ABR bitrate for {} Hz {} layer III, expected {} to {} kbps",
+                        kbps, spec.sample_rate, version, min, max
+                    )));
+                }
+                builder.set_vbr_mode(VbrMode::Abr).map_err(lame_error)?;
+                // the builder has no setter for the ABR target, which unlike CBR bitrates may be any value in range
+                // SAFETY: `as_ptr` returns the `lame_global_flags` the builder allocated with `lame_init` and owns until it
+                // is built or dropped, both of which happen only after this call; the setter merely stores the value in
+                // that struct, and the pointer is neither kept nor freed here
+                let result = unsafe {
+                    mp3lame_encoder::ffi::lame_set_VBR_mean_bitrate_kbps(builder.as_ptr(), kbps as i32)
+                };
+                if result != 0 {
+                    return Err(TranscoderError::Mp3(format!("LAME rejected the ABR bitrate of {} kbps", kbps)));
+                }
+                info!("MP3 encoder: ABR averaging {} kbps", kbps);
+            }
+            BitrateMode::Vbr => {
+                let quality = options.vbr_quality.unwrap_or(4);
+                if options.bitrate_kbps.is_some() {
+                    warn!("Bitrate is ignored for VBR MP3 encoding, using VBR quality {}", quality);
+                }
+                builder.set_vbr_mode(VbrMode::Mtrh).map_err(lame_error)?;
+                builder.set_vbr_quality(lame_quality(quality)).map_err(lame_error)?;
+                info!("MP3 encoder: VBR quality V{}", quality);
+            }
+        }
+
+        let encoder = builder.build().map_err(lame_error)?;
+
+        let mut writer = BufWriter::new(File::create(output_path)?);
+        let audio_start = write_id3v2_tag(&mut writer, tags)?;
+
+        Ok(Self {
+            encoder,
+            writer,
+            channels: spec.channels as usize,
+            audio_start,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl AudioEncoder for Mp3Encoder {
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let frames = samples.len() / self.channels;
+        self.buffer.clear();
+        self.buffer.reserve(mp3lame_encoder::max_required_buffer_size(frames));
+
+        let result = if self.channels == 1 {
+            self.encoder.encode_to_vec(MonoPcm(samples), &mut self.buffer)
+        } else {
+            self.encoder.encode_to_vec(InterleavedPcm(samples), &mut self.buffer)
+        };
+        result.map_err(|e| TranscoderError::Mp3(format!("Error encoding MP3 frames: {}", e)))?;
+
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), TranscoderError> {
+        self.buffer.clear();
+        self.buffer.reserve(7200); // worst case size of the last frames
+        self.encoder.flush_to_vec::<FlushNoGap>(&mut self.buffer)
+            .map_err(|e| TranscoderError::Mp3(format!("Error flushing MP3 encoder: {}", e)))?;
+        self.writer.write_all(&self.buffer)?;
+
+        // replacing the placeholder frame with the final Xing/LAME tag so that players know the real duration
+        let mut lame_tag = Vec::with_capacity(self.encoder.lame_tag_size());
+        if self.encoder.lame_tag_encode_to_vec(&mut lame_tag).is_some() {
+            self.writer.seek(SeekFrom::Start(self.audio_start))?;
+            self.writer.write_all(&lame_tag)?;
+            debug!("MP3 encoder: wrote {} byte LAME tag at offset {}", lame_tag.len(), self.audio_start);
+        }
+
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// picks the bitrate mode when none was requested explicitly
+// a VBR quality selects VBR, a bitrate alone selects CBR, and VBR is used when neither is given
+fn resolve_bitrate_mode(options: &TranscodeOptions) -> BitrateMode {
+    match (options.bitrate_mode, options.vbr_quality, options.bitrate_kbps) {
+        (Some(mode), _, _) => mode,
+        (None, Some(_), _) => BitrateMode::Vbr,
+        (None, None, Some(_)) => BitrateMode::Cbr,
+        (None, None, None) => BitrateMode::Vbr,
+    }
+}
+
+// CBR bitrates of layer III offered by the LAME bindings, which lack 56 and 144 kbps
+// MPEG-1 covers 32 to 48 kHz, MPEG-2 and MPEG-2.5 the lower sample rates
+const MPEG1_BITRATES: [u32; 13] = [32, 40, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_BITRATES: [u32; 12] = [8, 16, 24, 32, 40, 48, 64, 80, 96, 112, 128, 160];
+
+// the MPEG version implied by the sample rate and the CBR bitrates it allows
+fn mpeg_bitrates(sample_rate: u32) -> (&'static str, &'static [u32]) {
+    if sample_rate >= 32000 {
+        ("MPEG-1", &MPEG1_BITRATES)
+    } else {
+        ("MPEG-2", &MPEG2_BITRATES)
+    }
+}
+
+// maps a CBR bitrate in kbps onto the values accepted by LAME for the sample rate
+fn lame_bitrate(kbps: u32, sample_rate: u32) -> Result<Bitrate, TranscoderError> {
+    let (version, bitrates) = mpeg_bitrates(sample_rate);
+    if !bitrates.contains(&kbps) {
+        let valid: Vec<String> = bitrates.iter().map(ToString::to_string).collect();
+        return Err(TranscoderError::Argument(format!(
+            "{} kbps is not a valid CBR bitrate for {} Hz {} layer III ({})", kbps, sample_rate, version, valid.join(", ")
+        )));
+    }
+    let bitrate = match kbps {
+        8 => Bitrate::Kbps8,
+        16 => Bitrate::Kbps16,
+        24 => Bitrate::Kbps24,
+        32 => Bitrate::Kbps32,
+        40 => Bitrate::Kbps40,
+        48 => Bitrate::Kbps48,
+        64 => Bitrate::Kbps64,
+        80 => Bitrate::Kbps80,
+        96 => Bitrate::Kbps96,
+        112 => Bitrate::Kbps112,
+        128 => Bitrate::Kbps128,
+        160 => Bitrate::Kbps160,
+        192 => Bitrate::Kbps192,
+        224 => Bitrate::Kbps224,
+        256 => Bitrate::Kbps256,
+        _ => Bitrate::Kbps320,
+    };
+    Ok(bitrate)
+}
+
+// maps 0 (best) to 9 (worst) onto LAME's quality scale
+fn lame_quality(level: u8) -> Quality {
+    match level {
+        0 => Quality::Best,
+        1 => Quality::SecondBest,
+        2 => Quality::NearBest,
+        3 => Quality::VeryNice,
+        4 => Quality::Nice,
+        5 => Quality::Good,
+        6 => Quality::Decent,
+        7 => Quality::Ok,
+        8 => Quality::SecondWorst,
+        _ => Quality::Worst,
+    }
+}
+
+// writes the input metadata as an ID3v2.4 tag and returns the number of bytes written
+fn write_id3v2_tag(writer: &mut BufWriter<File>, tags: &Tags) -> Result<u64, TranscoderError> {
+    use id3::TagLike;
+
+    if tags.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tag = id3::Tag::new();
+    for (key, value) in tags {
+        match key.as_str() {
+            "TITLE" => tag.set_text("TIT2", value),
+            "ARTIST" => tag.set_text("TPE1", value),
+            "ALBUM" => tag.set_text("TALB", value),
+            "ALBUMARTIST" => tag.set_text("TPE2", value),
+            "COMPOSER" => tag.set_text("TCOM", value),
+            "GENRE" => tag.set_text("TCON", value),
+            "DATE" | "YEAR" => tag.set_text("TDRC", value),
+            "TRACKNUMBER" => tag.set_text("TRCK", value),
+            "DISCNUMBER" => tag.set_text("TPOS", value),
+            "COPYRIGHT" => tag.set_text("TCOP", value),
+            "COMMENT" | "DESCRIPTION" => {
+                tag.add_frame(id3::frame::Comment {
+                    lang: "eng".to_string(),
+                    description: String::new(),
+                    text: value.clone(),
+                });
+            }
+            other => {
+                tag.add_frame(id3::frame::ExtendedText {
+                    description: other.to_string(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+
+    tag.write_to(&mut *writer, id3::Version::Id3v24)
+        .map_err(|e| TranscoderError::Mp3(format!("Failed to write ID3v2 tag: {}", e)))?;
+    let tag_size = writer.stream_position()?;
+
+    debug!("MP3 encoder: wrote {} ID3v2 frames ({} bytes)", tags.len(), tag_size);
+    Ok(tag_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use crate::presets::QualityPreset;
+
+    fn spec(sample_rate: u32) -> StreamSpec {
+        StreamSpec { sample_rate, channels: 2, bits_per_sample: 16, is_float: false, total_frames: None }
+    }
+
+    // encodes a second of a tone and returns the size of the output
+    fn encode(sample_rate: u32, options: &TranscodeOptions) -> Result<u64, TranscoderError> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.mp3");
+        let mut encoder = Box::new(Mp3Encoder::create(&path, &spec(sample_rate), options, &Tags::new())?);
+        let samples: Vec<f32> = (0..sample_rate as usize * 2).map(|i| (i as f32 * 0.03).sin() * 0.5).collect();
+        encoder.write_samples(&samples)?;
+        encoder.finalize()?;
+        Ok(std::fs::metadata(&path)?.len())
+    }
+
+    #[test]
+    fn checks_cbr_bitrates_against_the_mpeg_version() {
+        assert!(lame_bitrate(320, 44100).is_ok());
+        assert!(lame_bitrate(32, 32000).is_ok());
+        assert!(lame_bitrate(8, 22050).is_ok());
+        assert!(lame_bitrate(160, 8000).is_ok());
+        // MPEG-1 starts at 32 kbps, MPEG-2 and 2.5 end at 160 kbps
+        assert!(matches!(lame_bitrate(8, 48000), Err(TranscoderError::Argument(_))));
+        assert!(matches!(lame_bitrate(320, 24000), Err(TranscoderError::Argument(_))));
+        assert!(matches!(lame_bitrate(100, 44100), Err(TranscoderError::Argument(_))));
+    }
+
+    #[test]
+    fn encodes_abr() {
+        let options = TranscodeOptions { bitrate_mode: Some(BitrateMode::Abr), bitrate_kbps: Some(100), ..TranscodeOptions::default() };
+        assert!(encode(44100, &options).unwrap() > 0);
+        let options = TranscodeOptions { bitrate_kbps: Some(256), ..options };
+        assert!(matches!(encode(16000, &options), Err(TranscoderError::Argument(_))));
+    }
+
+    #[test]
+    fn caps_preset_bitrates_at_low_sample_rates() {
+        let options = TranscodeOptions { quality_preset: Some(QualityPreset::Archival), ..TranscodeOptions::default() };
+        assert!(encode(22050, &options).is_ok());
+        let options = TranscodeOptions { bitrate_kbps: Some(320), ..TranscodeOptions::default() };
+        assert!(matches!(encode(22050, &options), Err(TranscoderError::Argument(_))));
+    }
+}
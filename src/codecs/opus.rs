@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, info};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use crate::codecs::{AudioEncoder, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::presets::OpusApplication;
+use crate::transcoders::TranscodeOptions;
+
+/// sample rate of every Ogg Opus stream produced by the native encoder
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+// 20 ms frames, the recommended frame size for general audio
+const FRAME_SIZE: usize = 960;
+// maximum size of a single Opus packet as recommended by libopus
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// encodes Ogg Opus files (RFC 7845) with libopus, muxing the packets with the `ogg` crate
+pub struct OpusEncoder {
+    encoder: ::opus::Encoder,
+    writer: PacketWriter<'static, BufWriter<File>>,
+    serial: u32,
+    channels: usize,
+    pre_skip: u64,
+    // interleaved samples waiting for a complete frame
+    pending: Vec<f32>,
+    // the most recent packet is held back so that it can be flagged as the end of the stream
+    last_packet: Option<(Vec<u8>, u64)>,
+    frames_in: u64,
+    frames_encoded: u64,
+}
+
+impl OpusEncoder {
+    pub fn create(
+        output_path: &Path,
+        spec: &StreamSpec,
+        options: &TranscodeOptions,
+        tags: &Tags,
+    ) -> Result<Self, TranscoderError> {
+        let channels = match spec.channels {
+            1 => ::opus::Channels::Mono,
+            2 => ::opus::Channels::Stereo,
+            n => return Err(TranscoderError::UnsupportedOutputFormat(format!("native Opus encoder supports 1 or 2 channels, got {}", n))),
+        };
+
+        // the preset picks the application and a bitrate, an explicit bitrate still wins
+        let preset = options.quality_preset.map(|preset| preset.opus());
+        let application = match preset.map(|preset| preset.application) {
+            Some(OpusApplication::Voip) => ::opus::Application::Voip,
+            Some(OpusApplication::Audio) | None => ::opus::Application::Audio,
+        };
+        let mut encoder = ::opus::Encoder::new(OPUS_SAMPLE_RATE, channels, application)
+            .map_err(|e| TranscoderError::Opus(format!("Failed to create Opus encoder: {}", e)))?;
+
+        let bitrate = match options.bitrate_kbps.or(preset.map(|preset| preset.bitrate_kbps)) {
+            Some(kbps) => ::opus::Bitrate::Bits(kbps as i32 * 1000),
+            None => ::opus::Bitrate::Auto,
+        };
+        encoder.set_bitrate(bitrate)
+            .map_err(|e| TranscoderError::Opus(format!("Invalid Opus bitrate {:?}: {}", bitrate, e)))?;
+
+        let pre_skip = encoder.get_lookahead()
+            .map_err(|e| TranscoderError::Opus(format!("Failed to query Opus lookahead: {}", e)))? as u64;
+        info!("Opus encoder: {} channels, {:?}, pre-skip of {} samples", spec.channels, bitrate, pre_skip);
+
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default() ^ std::process::id();
+
+        let mut writer = PacketWriter::new(BufWriter::new(File::create(output_path)?));
+
+        // both header packets must sit on pages of their own
+        writer.write_packet(opus_head(spec, pre_skip as u16), serial, PacketWriteEndInfo::EndPage, 0)?;
+        writer.write_packet(opus_tags(tags), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self {
+            encoder,
+            writer,
+            serial,
+            channels: spec.channels as usize,
+            pre_skip,
+            pending: Vec::with_capacity(FRAME_SIZE * spec.channels as usize * 2),
+            last_packet: None,
+            frames_in: 0,
+            frames_encoded: 0,
+        })
+    }
+
+    // encodes every complete frame in the pending buffer
+    fn encode_pending(&mut self) -> Result<(), TranscoderError> {
+        let frame_samples = FRAME_SIZE * self.channels;
+        let complete = self.pending.len() / frame_samples * frame_samples;
+
+        let mut packet = [0u8; MAX_PACKET_SIZE];
+        for frame in self.pending[..complete].chunks_exact(frame_samples) {
+            let packet_len = self.encoder.encode_float(frame, &mut packet)
+                .map_err(|e| TranscoderError::Opus(format!("Error encoding Opus frame: {}", e)))?;
+            self.frames_encoded += FRAME_SIZE as u64;
+
+            if let Some((previous, granule)) = self.last_packet.take() {
+                self.writer.write_packet(previous, self.serial, PacketWriteEndInfo::NormalPacket, granule)?;
+            }
+            self.last_packet = Some((packet[..packet_len].to_vec(), self.frames_encoded));
+        }
+
+        self.pending.drain(..complete);
+        Ok(())
+    }
+}
+
+impl AudioEncoder for OpusEncoder {
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        self.pending.extend_from_slice(samples);
+        self.frames_in += (samples.len() / self.channels) as u64;
+        self.encode_pending()
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), TranscoderError> {
+        // pushing the encoder lookahead out with silence and padding the last frame
+        let frame_samples = FRAME_SIZE * self.channels;
+        let padded_len = (self.pending.len() + self.pre_skip as usize * self.channels).div_ceil(frame_samples) * frame_samples;
+        self.pending.resize(padded_len.max(frame_samples), 0.0);
+        self.encode_pending()?;
+
+        // the granule position of the final page marks where the real audio ends
+        let end_granule = self.pre_skip + self.frames_in;
+        if let Some((packet, granule)) = self.last_packet.take() {
+            self.writer.write_packet(packet, self.serial, PacketWriteEndInfo::EndStream, granule.min(end_granule))?;
+        }
+        debug!("Opus encoder: wrote {} frames ({} samples incl. padding)", self.frames_in, self.frames_encoded);
+
+        self.writer.inner_mut().flush()?;
+        Ok(())
+    }
+}
+
+// identification header, see RFC 7845 section 5.1
+fn opus_head(spec: &StreamSpec, pre_skip: u16) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // version
+    head.push(spec.channels);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family for mono and stereo
+    head
+}
+
+// comment header, see RFC 7845 section 5.2
+fn opus_tags(tags: &Tags) -> Vec<u8> {
+    let vendor = format!("rewav {}", env!("CARGO_PKG_VERSION"));
+    let mut packet = Vec::new();
+    packet.extend_from_slice(b"OpusTags");
+    packet.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    packet.extend_from_slice(vendor.as_bytes());
+    packet.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    for (key, value) in tags {
+        let comment = format!("{}={}", key, value);
+        packet.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        packet.extend_from_slice(comment.as_bytes());
+    }
+    packet
+}
@@ -0,0 +1,335 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::audio_processor;
+use crate::codecs::{AudioDecoder, AudioEncoder, StreamSpec};
+use crate::errors::TranscoderError;
+use crate::utils;
+
+/// sample layouts of headerless PCM data, named after the matching ffmpeg formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RawPcmFormat {
+    /// unsigned 8-bit
+    U8,
+    /// signed 8-bit
+    S8,
+    /// signed 16-bit little-endian
+    S16le,
+    /// signed 16-bit big-endian
+    S16be,
+    /// signed 24-bit little-endian, packed in 3 bytes
+    S24le,
+    /// signed 24-bit big-endian, packed in 3 bytes
+    S24be,
+    /// signed 32-bit little-endian
+    S32le,
+    /// signed 32-bit big-endian
+    S32be,
+    /// 32-bit float little-endian
+    F32le,
+    /// 32-bit float big-endian
+    F32be,
+    /// 64-bit float little-endian
+    F64le,
+    /// 64-bit float big-endian
+    F64be,
+}
+
+impl RawPcmFormat {
+    /// name of the ffmpeg raw (de)muxer for this layout
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            RawPcmFormat::U8 => "u8",
+            RawPcmFormat::S8 => "s8",
+            RawPcmFormat::S16le => "s16le",
+            RawPcmFormat::S16be => "s16be",
+            RawPcmFormat::S24le => "s24le",
+            RawPcmFormat::S24be => "s24be",
+            RawPcmFormat::S32le => "s32le",
+            RawPcmFormat::S32be => "s32be",
+            RawPcmFormat::F32le => "f32le",
+            RawPcmFormat::F32be => "f32be",
+            RawPcmFormat::F64le => "f64le",
+            RawPcmFormat::F64be => "f64be",
+        }
+    }
+
+    /// number of bytes per sample
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            RawPcmFormat::U8 | RawPcmFormat::S8 => 1,
+            RawPcmFormat::S16le | RawPcmFormat::S16be => 2,
+            RawPcmFormat::S24le | RawPcmFormat::S24be => 3,
+            RawPcmFormat::S32le | RawPcmFormat::S32be | RawPcmFormat::F32le | RawPcmFormat::F32be => 4,
+            RawPcmFormat::F64le | RawPcmFormat::F64be => 8,
+        }
+    }
+
+    /// whether samples are IEEE floats
+    pub fn is_float(self) -> bool {
+        matches!(self, RawPcmFormat::F32le | RawPcmFormat::F32be | RawPcmFormat::F64le | RawPcmFormat::F64be)
+    }
+
+    /// little-endian layout matching the given WAV sample format, if any
+    pub fn little_endian(bits_per_sample: u16, is_float: bool) -> Option<Self> {
+        match (bits_per_sample, is_float) {
+            (8, false) => Some(RawPcmFormat::U8),
+            (16, false) => Some(RawPcmFormat::S16le),
+            (24, false) => Some(RawPcmFormat::S24le),
+            (32, false) => Some(RawPcmFormat::S32le),
+            (32, true) => Some(RawPcmFormat::F32le),
+            (64, true) => Some(RawPcmFormat::F64le),
+            _ => None,
+        }
+    }
+
+    fn is_big_endian(self) -> bool {
+        matches!(self, RawPcmFormat::S16be | RawPcmFormat::S24be | RawPcmFormat::S32be | RawPcmFormat::F32be | RawPcmFormat::F64be)
+    }
+
+    // decodes one sample to f32
+    fn decode(self, bytes: &[u8]) -> f32 {
+        // normalizing to little-endian so that every layout shares the conversion below
+        let mut le = [0u8; 8];
+        le[..bytes.len()].copy_from_slice(bytes);
+        if self.is_big_endian() {
+            le[..bytes.len()].reverse();
+        }
+
+        match self {
+            RawPcmFormat::U8 => (le[0] as f32 - 128.0) / 128.0,
+            RawPcmFormat::S8 => le[0] as i8 as f32 / 128.0,
+            RawPcmFormat::S16le | RawPcmFormat::S16be => i16::from_le_bytes([le[0], le[1]]) as f32 / 32768.0,
+            // shifting the 24-bit value into the top of an i32 to sign-extend it
+            RawPcmFormat::S24le | RawPcmFormat::S24be => (i32::from_le_bytes([0, le[0], le[1], le[2]]) >> 8) as f32 / 8388608.0,
+            RawPcmFormat::S32le | RawPcmFormat::S32be => i32::from_le_bytes([le[0], le[1], le[2], le[3]]) as f32 / 2147483648.0,
+            RawPcmFormat::F32le | RawPcmFormat::F32be => f32::from_le_bytes([le[0], le[1], le[2], le[3]]),
+            RawPcmFormat::F64le | RawPcmFormat::F64be => f64::from_le_bytes(le) as f32,
+        }
+    }
+
+    // encodes a block of f32 samples
+    fn encode(self, samples: &[f32], output: &mut Vec<u8>) {
+        let bytes_per_sample = self.bytes_per_sample();
+        output.reserve(samples.len() * bytes_per_sample);
+        let start = output.len();
+
+        match self {
+            RawPcmFormat::U8 => output.extend(audio_processor::f32_to_i32(samples, 8).into_iter().map(|s| (s + 128) as u8)),
+            RawPcmFormat::S8 => output.extend(audio_processor::f32_to_i32(samples, 8).into_iter().map(|s| s as i8 as u8)),
+            RawPcmFormat::S16le | RawPcmFormat::S16be => {
+                for sample in audio_processor::f32_to_i16(samples) {
+                    output.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            RawPcmFormat::S24le | RawPcmFormat::S24be => {
+                for sample in audio_processor::f32_to_i32(samples, 24) {
+                    output.extend_from_slice(&sample.to_le_bytes()[..3]);
+                }
+            }
+            RawPcmFormat::S32le | RawPcmFormat::S32be => {
+                for sample in audio_processor::f32_to_i32(samples, 32) {
+                    output.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            RawPcmFormat::F32le | RawPcmFormat::F32be => {
+                for &sample in samples {
+                    output.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            RawPcmFormat::F64le | RawPcmFormat::F64be => {
+                for &sample in samples {
+                    output.extend_from_slice(&(sample as f64).to_le_bytes());
+                }
+            }
+        }
+
+        if self.is_big_endian() {
+            for sample in output[start..].chunks_exact_mut(bytes_per_sample) {
+                sample.reverse();
+            }
+        }
+    }
+}
+
+/// decodes headerless PCM described by `--raw-format`, `--raw-rate` and `--raw-channels`
+pub struct RawPcmDecoder {
+    reader: Box<dyn Read>,
+    format: RawPcmFormat,
+    spec: StreamSpec,
+    buffer: Vec<u8>,
+}
+
+impl RawPcmDecoder {
+    pub fn open(input_path: &Path, format: RawPcmFormat, sample_rate: u32, channels: u8) -> Result<Self, TranscoderError> {
+        // the length of stdin is unknown, the stream is read until EOF
+        let data_size = if utils::is_stdio(input_path) {
+            None
+        } else {
+            Some(std::fs::metadata(input_path)?.len())
+        };
+
+        let decoder = Self::from_reader(utils::open_input(input_path)?, format, sample_rate, channels, data_size);
+        info!("Input raw PCM: {:?}, {:?}", format, decoder.spec);
+        Ok(decoder)
+    }
+
+    /// decodes PCM samples from `reader`, stopping after `data_size` bytes if given
+    pub fn from_reader(reader: Box<dyn Read>, format: RawPcmFormat, sample_rate: u32, channels: u8, data_size: Option<u64>) -> Self {
+        let frame_size = (format.bytes_per_sample() * channels as usize) as u64;
+        let reader: Box<dyn Read> = match data_size {
+            Some(size) => Box::new(reader.take(size)),
+            None => reader,
+        };
+
+        let spec = StreamSpec {
+            sample_rate,
+            channels,
+            bits_per_sample: (format.bytes_per_sample() * 8) as u16,
+            is_float: format.is_float(),
+            total_frames: data_size.map(|size| size / frame_size),
+        };
+
+        Self {
+            reader,
+            format,
+            spec,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl AudioDecoder for RawPcmDecoder {
+    fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    fn read_frames(&mut self, max_frames: usize) -> Result<Vec<f32>, TranscoderError> {
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let frame_size = bytes_per_sample * self.spec.channels as usize;
+
+        self.buffer.clear();
+        (&mut self.reader).take((max_frames * frame_size) as u64).read_to_end(&mut self.buffer)?;
+
+        // a trailing partial frame cannot be decoded and is dropped
+        let complete = self.buffer.len() / frame_size * frame_size;
+        Ok(self.buffer[..complete]
+            .chunks_exact(bytes_per_sample)
+            .map(|sample| self.format.decode(sample))
+            .collect())
+    }
+}
+
+/// writes headerless PCM in the layout given by `--raw-format`
+pub struct RawPcmEncoder {
+    writer: Box<dyn Write>,
+    format: RawPcmFormat,
+    buffer: Vec<u8>,
+}
+
+impl RawPcmEncoder {
+    pub fn create(output_path: &Path, format: RawPcmFormat) -> Result<Self, TranscoderError> {
+        info!("Output raw PCM: {:?}", format);
+        Ok(Self::from_writer(utils::create_output(output_path)?, format))
+    }
+
+    /// encodes PCM samples into `writer`, which is expected to be buffered
+    pub fn from_writer(writer: Box<dyn Write>, format: RawPcmFormat) -> Self {
+        Self {
+            writer,
+            format,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl AudioEncoder for RawPcmEncoder {
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        self.buffer.clear();
+        self.format.encode(samples, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    fn finalize(mut self: Box<Self>) -> Result<(), TranscoderError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SAMPLES: [f32; 8] = [0.0, 0.5, -0.5, 0.25, -1.0, 0.999, 0.001, -0.125];
+
+    fn encode_file(path: &Path, format: RawPcmFormat, samples: &[f32]) {
+        let mut encoder = Box::new(RawPcmEncoder::create(path, format).unwrap());
+        encoder.write_samples(samples).unwrap();
+        encoder.finalize().unwrap();
+    }
+
+    // reads the whole file a few frames at a time
+    fn decode_file(path: &Path, format: RawPcmFormat, channels: u8) -> (StreamSpec, Vec<f32>) {
+        let mut decoder = RawPcmDecoder::open(path, format, 44100, channels).unwrap();
+        let mut samples = Vec::new();
+        loop {
+            let chunk = decoder.read_frames(3).unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            samples.extend(chunk);
+        }
+        (decoder.spec(), samples)
+    }
+
+    #[test]
+    fn round_trips_every_layout() {
+        let dir = TempDir::new().unwrap();
+        for (format, tolerance) in [(RawPcmFormat::S16le, 1.0 / 32768.0), (RawPcmFormat::S24be, 1.0 / 8388608.0), (RawPcmFormat::F32le, 0.0)] {
+            let path = dir.path().join(format!("{:?}.raw", format));
+            encode_file(&path, format, &SAMPLES);
+            assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, SAMPLES.len() * format.bytes_per_sample());
+
+            let (spec, decoded) = decode_file(&path, format, 2);
+            assert_eq!(spec.total_frames, Some(4));
+            assert_eq!(spec.bits_per_sample as usize, format.bytes_per_sample() * 8);
+            assert_eq!(spec.is_float, format == RawPcmFormat::F32le);
+            assert_eq!(decoded.len(), SAMPLES.len());
+            for (decoded, original) in decoded.iter().zip(SAMPLES) {
+                assert!((decoded - original).abs() <= tolerance, "{:?}: {} decoded as {}", format, original, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn orders_bytes_by_endianness() {
+        let mut little = Vec::new();
+        let mut big = Vec::new();
+        RawPcmFormat::S24le.encode(&[0.5], &mut little);
+        RawPcmFormat::S24be.encode(&[0.5], &mut big);
+        assert_eq!(big, [0x40, 0x00, 0x00]);
+        assert_eq!(little, [0x00, 0x00, 0x40]);
+
+        assert_eq!(RawPcmFormat::F32be.decode(&1.0f32.to_be_bytes()), 1.0);
+        assert_eq!(RawPcmFormat::S16be.decode(&[0x80, 0x00]), -1.0);
+        assert_eq!(RawPcmFormat::U8.decode(&[0x80]), 0.0);
+    }
+
+    #[test]
+    fn drops_a_truncated_last_frame() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("truncated.raw");
+        // four stereo 16-bit frames followed by three bytes of a fifth
+        let mut bytes = vec![0u8; 4 * 4];
+        bytes.extend_from_slice(&[0x00, 0x40, 0x00]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let (spec, decoded) = decode_file(&path, RawPcmFormat::S16le, 2);
+        assert_eq!(spec.total_frames, Some(4));
+        assert_eq!(decoded, vec![0.0; 8]);
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::Path;
+use log::info;
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoderBuilder};
+use crate::codecs::{AudioEncoder, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::transcoders::TranscodeOptions;
+
+/// encodes Ogg Vorbis files with `vorbis_rs` (libvorbis with the aoTuV and Lancer patches)
+pub struct VorbisEncoder {
+    encoder: vorbis_rs::VorbisEncoder<BufWriter<File>>,
+    channels: usize,
+}
+
+impl VorbisEncoder {
+    pub fn create(
+        output_path: &Path,
+        spec: &StreamSpec,
+        options: &TranscodeOptions,
+        tags: &Tags,
+    ) -> Result<Self, TranscoderError> {
+        let sample_rate = NonZeroU32::new(spec.sample_rate)
+            .ok_or_else(|| TranscoderError::Vorbis("sample rate must be non-zero".to_string()))?;
+        let channels = NonZeroU8::new(spec.channels)
+            .ok_or_else(|| TranscoderError::Vorbis("channel count must be non-zero".to_string()))?;
+
+        // an explicit bitrate selects ABR, otherwise the preset picks a quality level for VBR
+        let strategy = match options.bitrate_kbps.and_then(|kbps| NonZeroU32::new(kbps * 1000)) {
+            Some(average_bitrate) => VorbisBitrateManagementStrategy::Abr { average_bitrate },
+            None => {
+                let quality = options.quality_preset.map_or(5.0, |preset| preset.vorbis_quality());
+                VorbisBitrateManagementStrategy::QualityVbr { target_quality: quality / 10.0 }
+            }
+        };
+        info!("Vorbis encoder: {} Hz, {} channels, {:?}", spec.sample_rate, spec.channels, strategy);
+
+        let sink = BufWriter::new(File::create(output_path)?);
+        let mut builder = VorbisEncoderBuilder::new(sample_rate, channels, sink)
+            .map_err(|e| TranscoderError::Vorbis(format!("Failed to create Vorbis encoder: {}", e)))?;
+        builder.bitrate_management_strategy(strategy);
+        builder.comment_tags(tags.iter().map(|(key, value)| (key.as_str(), value.as_str())))
+            .map_err(|e| TranscoderError::Vorbis(format!("Invalid Vorbis comment: {}", e)))?;
+
+        let encoder = builder.build()
+            .map_err(|e| TranscoderError::Vorbis(format!("Failed to create Vorbis encoder: {}", e)))?;
+
+        Ok(Self { encoder, channels: spec.channels as usize })
+    }
+}
+
+impl AudioEncoder for VorbisEncoder {
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        // libvorbis consumes planar blocks
+        let frames = samples.len() / self.channels;
+        let mut planar = vec![Vec::with_capacity(frames); self.channels];
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                planar[c].push(sample);
+            }
+        }
+
+        self.encoder.encode_audio_block(&planar)
+            .map_err(|e| TranscoderError::Vorbis(format!("Error encoding Vorbis block: {}", e)))
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), TranscoderError> {
+        let mut sink = self.encoder.finish()
+            .map_err(|e| TranscoderError::Vorbis(format!("Failed to finish Vorbis stream: {}", e)))?;
+        std::io::Write::flush(&mut sink)?;
+        Ok(())
+    }
+}
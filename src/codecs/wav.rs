@@ -0,0 +1,502 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use crate::audio_processor;
+use crate::codecs::{channel_layout, AudioDecoder, AudioEncoder, StreamSpec, Tags};
+use crate::codecs::raw::{RawPcmDecoder, RawPcmEncoder, RawPcmFormat};
+use crate::errors::TranscoderError;
+
+// RIFF and data chunk size written by streaming encoders that cannot seek back to patch the header
+const UNKNOWN_CHUNK_SIZE: u32 = u32::MAX;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// size of a WAVE_FORMAT_EXTENSIBLE fmt chunk, the largest one holding fields read here
+const FMT_CHUNK_MAX_SIZE: u64 = 40;
+
+/// decodes WAV files with `hound`, supporting 8 to 32-bit integer and 32-bit float samples
+pub struct WavDecoder {
+    reader: WavReader<BufReader<File>>,
+    spec: StreamSpec,
+    tags: Tags,
+    channel_mask: Option<u32>,
+}
+
+impl WavDecoder {
+    pub fn open(input_path: &Path) -> Result<Self, TranscoderError> {
+        let reader = WavReader::open(input_path)?;
+        let wav_spec = reader.spec();
+        info!("Input WAV specifications: {:?}", wav_spec);
+
+        let spec = StreamSpec {
+            sample_rate: wav_spec.sample_rate,
+            channels: wav_spec.channels as u8,
+            bits_per_sample: wav_spec.bits_per_sample,
+            is_float: wav_spec.sample_format == SampleFormat::Float,
+            total_frames: Some(reader.duration() as u64),
+        };
+
+        // `hound` skips unknown chunks, so the LIST/INFO metadata is read separately
+        let tags = read_info_tags(input_path).unwrap_or_else(|e| {
+            debug!("Could not read WAV INFO tags: {}", e);
+            Vec::new()
+        });
+
+        let channel_mask = read_channel_mask(input_path).unwrap_or_else(|e| {
+            debug!("Could not read the WAV channel mask: {}", e);
+            None
+        });
+
+        Ok(Self { reader, spec, tags, channel_mask })
+    }
+}
+
+impl AudioDecoder for WavDecoder {
+    fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    fn tags(&self) -> Tags {
+        self.tags.clone()
+    }
+
+    fn channel_mask(&self) -> Option<u32> {
+        self.channel_mask
+    }
+
+    fn read_frames(&mut self, max_frames: usize) -> Result<Vec<f32>, TranscoderError> {
+        let max_samples = max_frames * self.spec.channels as usize;
+
+        if self.spec.is_float {
+            return self.reader
+                .samples::<f32>()
+                .take(max_samples)
+                .collect::<Result<Vec<f32>, hound::Error>>()
+                .map_err(TranscoderError::from);
+        }
+
+        match self.spec.bits_per_sample {
+            16 => {
+                let chunk_i16 = self.reader
+                    .samples::<i16>()
+                    .take(max_samples)
+                    .collect::<Result<Vec<i16>, hound::Error>>()?;
+                Ok(audio_processor::i16_to_f32(&chunk_i16))
+            }
+            bits => {
+                let chunk_i32 = self.reader
+                    .samples::<i32>()
+                    .take(max_samples)
+                    .collect::<Result<Vec<i32>, hound::Error>>()?;
+                Ok(audio_processor::i32_to_f32(&chunk_i32, bits))
+            }
+        }
+    }
+}
+
+/// encodes WAV files with `hound`, writing samples at the bit depth and sample format of the given specification
+/// a `channel_mask` is patched into the header once it is written, since `hound` always assigns the first speakers
+pub struct WavEncoder {
+    writer: WavWriter<std::io::BufWriter<File>>,
+    spec: StreamSpec,
+    output_path: PathBuf,
+    channel_mask: Option<u32>,
+}
+
+impl WavEncoder {
+    pub fn create(output_path: &Path, spec: &StreamSpec, channel_mask: Option<u32>) -> Result<Self, TranscoderError> {
+        let wav_spec = WavSpec {
+            channels: spec.channels as u16,
+            sample_rate: spec.sample_rate,
+            bits_per_sample: spec.bits_per_sample,
+            sample_format: if spec.is_float { SampleFormat::Float } else { SampleFormat::Int },
+        };
+
+        info!("Output WAV specifications: {:?}", wav_spec);
+
+        let writer = WavWriter::create(output_path, wav_spec)?;
+        Ok(Self { writer, spec: *spec, output_path: output_path.to_path_buf(), channel_mask })
+    }
+}
+
+impl AudioEncoder for WavEncoder {
+    fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        if self.spec.is_float {
+            for &sample in samples {
+                self.writer.write_sample(sample)?;
+            }
+            return Ok(());
+        }
+
+        match self.spec.bits_per_sample {
+            16 => {
+                let mut sample_writer = self.writer.get_i16_writer(samples.len() as u32);
+                for sample in audio_processor::f32_to_i16(samples) {
+                    sample_writer.write_sample(sample);
+                }
+                sample_writer.flush()?;
+            }
+            bits => {
+                for sample in audio_processor::f32_to_i32(samples, bits) {
+                    self.writer.write_sample(sample)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> Result<(), TranscoderError> {
+        self.writer.finalize()?;
+        if let Some(channel_mask) = self.channel_mask {
+            write_channel_mask(&self.output_path, &self.spec, channel_mask)?;
+        }
+        Ok(())
+    }
+}
+
+// overwrites the channel mask of the WAVE_FORMAT_EXTENSIBLE fmt chunk `hound` writes right after the RIFF header
+// plain PCM headers, used for mono and stereo up to 16 bits, imply the default layout and cannot carry another one
+fn write_channel_mask(output_path: &Path, spec: &StreamSpec, channel_mask: u32) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(output_path)?;
+    let mut header = [0u8; 22];
+    file.read_exact(&mut header)?;
+    if &header[12..16] != b"fmt " || u16::from_le_bytes([header[20], header[21]]) != WAVE_FORMAT_EXTENSIBLE {
+        if channel_mask != channel_layout::default_mask(spec.channels) {
+            warn!(
+                "{}-bit WAV output with {} channels has no room for a channel mask, keeping the default layout",
+                spec.bits_per_sample, spec.channels
+            );
+        }
+        return Ok(());
+    }
+
+    // the mask follows the 16 bytes of WAVEFORMATEX, the extension size and the valid bits per sample
+    file.seek(SeekFrom::Start(20 + 16 + 4))?;
+    file.write_all(&channel_mask.to_le_bytes())?;
+    debug!("Wrote WAV channel mask {:#x}", channel_mask);
+    Ok(())
+}
+
+// reads the channel mask from the fmt chunk of a WAV file, present only in the WAVE_FORMAT_EXTENSIBLE format
+fn read_channel_mask(input_path: &Path) -> io::Result<Option<u32>> {
+    let mut file = BufReader::new(File::open(input_path)?);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let chunk_size = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]) as u64;
+        if &chunk_header[0..4] != b"fmt " {
+            // chunks are padded to an even size
+            file.seek(SeekFrom::Current((chunk_size + (chunk_size & 1)) as i64))?;
+            continue;
+        }
+
+        // the declared size comes from an untrusted header, only the fields read here are buffered
+        let mut chunk = Vec::new();
+        (&mut file).take(chunk_size.min(FMT_CHUNK_MAX_SIZE)).read_to_end(&mut chunk)?;
+        let is_extensible = chunk.len() >= 24 && u16::from_le_bytes([chunk[0], chunk[1]]) == WAVE_FORMAT_EXTENSIBLE;
+        let mask = is_extensible.then(|| u32::from_le_bytes([chunk[20], chunk[21], chunk[22], chunk[23]]));
+        // a zero mask leaves the channels unassigned, which is treated like no mask at all
+        return Ok(mask.filter(|&mask| mask != 0));
+    }
+
+    Ok(None)
+}
+
+/// decodes WAV streams from non-seekable readers such as stdin
+/// the header is parsed by hand since streamed WAV often carries a placeholder data size (0 or 0xFFFFFFFF), in which case the data runs until EOF
+pub struct WavStreamDecoder {
+    pcm: RawPcmDecoder,
+    tags: Tags,
+}
+
+impl WavStreamDecoder {
+    pub fn from_reader(mut reader: Box<dyn Read>) -> Result<Self, TranscoderError> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err(TranscoderError::UnsupportedInputFormat("input stream is not a RIFF/WAVE stream".to_string()));
+        }
+
+        let mut format = None;
+        let mut tags = Vec::new();
+        let mut chunk_header = [0u8; 8];
+        loop {
+            reader.read_exact(&mut chunk_header)?;
+            let chunk_size = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]);
+
+            if &chunk_header[0..4] == b"data" {
+                let (sample_format, sample_rate, channels) = format.ok_or_else(|| {
+                    TranscoderError::UnsupportedInputFormat("WAV stream has no fmt chunk before its data".to_string())
+                })?;
+                let data_size = match chunk_size {
+                    0 | UNKNOWN_CHUNK_SIZE => None,
+                    size => Some(size as u64),
+                };
+
+                let pcm = RawPcmDecoder::from_reader(reader, sample_format, sample_rate, channels, data_size);
+                info!("Input WAV stream: {:?}, {:?}", sample_format, pcm.spec());
+                return Ok(Self { pcm, tags });
+            }
+
+            // chunks are padded to an even size
+            let padded_size = chunk_size as u64 + (chunk_size & 1) as u64;
+            let mut chunk = Vec::new();
+            (&mut reader).take(padded_size).read_to_end(&mut chunk)?;
+            if chunk.len() as u64 != padded_size {
+                return Err(TranscoderError::Io(io::Error::from(io::ErrorKind::UnexpectedEof)));
+            }
+
+            match &chunk_header[0..4] {
+                b"fmt " => format = Some(parse_fmt_chunk(&chunk)?),
+                b"LIST" if chunk.len() >= 4 && &chunk[0..4] == b"INFO" => tags.extend(parse_info_list(&chunk, chunk_size as usize)),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl AudioDecoder for WavStreamDecoder {
+    fn spec(&self) -> StreamSpec {
+        self.pcm.spec()
+    }
+
+    fn tags(&self) -> Tags {
+        self.tags.clone()
+    }
+
+    fn read_frames(&mut self, max_frames: usize) -> Result<Vec<f32>, TranscoderError> {
+        self.pcm.read_frames(max_frames)
+    }
+}
+
+/// creates an encoder streaming WAV to a non-seekable writer such as stdout
+/// the RIFF and data sizes are left at 0xFFFFFFFF, which readers treat as "until EOF"
+pub fn create_stream_encoder(mut writer: Box<dyn Write>, spec: &StreamSpec) -> Result<RawPcmEncoder, TranscoderError> {
+    let sample_format = RawPcmFormat::little_endian(spec.bits_per_sample, spec.is_float).ok_or_else(|| {
+        TranscoderError::UnsupportedOutputFormat(format!("cannot stream {}-bit WAV samples", spec.bits_per_sample))
+    })?;
+
+    let block_align = spec.channels as u16 * sample_format.bytes_per_sample() as u16;
+    let format_tag = if spec.is_float { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM };
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&UNKNOWN_CHUNK_SIZE.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&format_tag.to_le_bytes());
+    header.extend_from_slice(&(spec.channels as u16).to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(spec.sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&UNKNOWN_CHUNK_SIZE.to_le_bytes());
+    writer.write_all(&header)?;
+
+    info!("Output WAV stream: {:?}, {:?}", sample_format, spec);
+    Ok(RawPcmEncoder::from_writer(writer, sample_format))
+}
+
+// parses a fmt chunk into the sample layout, sample rate and channel count
+fn parse_fmt_chunk(chunk: &[u8]) -> Result<(RawPcmFormat, u32, u8), TranscoderError> {
+    if chunk.len() < 16 {
+        return Err(TranscoderError::UnsupportedInputFormat("WAV fmt chunk is too short".to_string()));
+    }
+    let read_u16 = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+
+    let mut format_tag = read_u16(0);
+    let channels = read_u16(2);
+    let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+    let block_align = read_u16(12);
+    let bits_per_sample = read_u16(14);
+
+    // the extensible format stores the actual format tag at the start of its sub-format GUID
+    if format_tag == WAVE_FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+        format_tag = read_u16(24);
+    }
+
+    let sample_format = match format_tag {
+        WAVE_FORMAT_PCM => RawPcmFormat::little_endian(bits_per_sample, false),
+        WAVE_FORMAT_IEEE_FLOAT => RawPcmFormat::little_endian(bits_per_sample, true),
+        _ => None,
+    };
+    match sample_format {
+        Some(sample_format) if channels > 0 && block_align as usize == channels as usize * sample_format.bytes_per_sample() => {
+            Ok((sample_format, sample_rate, channels as u8))
+        }
+        _ => Err(TranscoderError::UnsupportedInputFormat(format!(
+            "unsupported WAV stream format (tag {:#06x}, {} bits, {} channels)", format_tag, bits_per_sample, channels
+        ))),
+    }
+}
+
+/// reads the RIFF LIST/INFO chunk of a WAV file into Vorbis comment style tags
+fn read_info_tags(input_path: &Path) -> std::io::Result<Tags> {
+    let mut file = BufReader::new(File::open(input_path)?);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(Vec::new());
+    }
+
+    let mut tags = Vec::new();
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let chunk_size = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]) as u64;
+        // chunks are padded to an even size
+        let padded_size = chunk_size + (chunk_size & 1);
+
+        if &chunk_header[0..4] != b"LIST" || chunk_size < 4 {
+            file.seek(SeekFrom::Current(padded_size as i64))?;
+            continue;
+        }
+
+        // reading through `take` bounds the buffer by the file length rather than the declared size
+        let mut list = Vec::new();
+        (&mut file).take(padded_size).read_to_end(&mut list)?;
+        if !list.starts_with(b"INFO") {
+            continue;
+        }
+
+        tags.extend(parse_info_list(&list, (chunk_size as usize).min(list.len())));
+    }
+
+    Ok(tags)
+}
+
+// parses the sub-chunks of a LIST/INFO chunk of `chunk_size` bytes into Vorbis comment style tags
+fn parse_info_list(list: &[u8], chunk_size: usize) -> Tags {
+    let mut tags = Vec::new();
+    let mut offset = 4;
+    while offset + 8 <= chunk_size {
+        let id = &list[offset..offset + 4];
+        let size = u32::from_le_bytes([list[offset + 4], list[offset + 5], list[offset + 6], list[offset + 7]]) as usize;
+        let start = offset + 8;
+        let end = (start + size).min(list.len());
+        let value = String::from_utf8_lossy(&list[start..end]).trim_end_matches('\0').trim().to_string();
+
+        let key = match id {
+            b"INAM" => Some("TITLE"),
+            b"IART" => Some("ARTIST"),
+            b"IPRD" => Some("ALBUM"),
+            b"ICRD" => Some("DATE"),
+            b"IGNR" => Some("GENRE"),
+            b"ICMT" => Some("COMMENT"),
+            b"ICOP" => Some("COPYRIGHT"),
+            b"ITRK" | b"IPRT" => Some("TRACKNUMBER"),
+            _ => None,
+        };
+        if let Some(key) = key.filter(|_| !value.is_empty()) {
+            tags.push((key.to_string(), value));
+        }
+        offset = start + size + (size & 1);
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    // a writer whose bytes remain readable after the encoder owning it is finalized
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn round_trips_streamed_wav_with_placeholder_sizes() {
+        let samples: Vec<f32> = (0..2000).map(|i| ((i as f32) * 0.01).sin() * 0.8).collect();
+        for (bits_per_sample, is_float, tolerance) in [(16, false, 1.0 / 32768.0), (32, true, 0.0)] {
+            let spec = StreamSpec { sample_rate: 48000, channels: 2, bits_per_sample, is_float, total_frames: None };
+            let buffer = SharedBuffer::default();
+            let mut encoder = Box::new(create_stream_encoder(Box::new(buffer.clone()), &spec).unwrap());
+            encoder.write_samples(&samples).unwrap();
+            encoder.finalize().unwrap();
+            let bytes = buffer.0.take();
+
+            // neither size is known while streaming
+            assert_eq!(&bytes[0..4], b"RIFF");
+            assert_eq!(bytes[4..8], UNKNOWN_CHUNK_SIZE.to_le_bytes());
+            let data = bytes.windows(4).position(|window| window == b"data").unwrap();
+            assert_eq!(bytes[data + 4..data + 8], UNKNOWN_CHUNK_SIZE.to_le_bytes());
+            assert_eq!(bytes.len() - data - 8, samples.len() * bits_per_sample as usize / 8);
+
+            let mut decoder = WavStreamDecoder::from_reader(Box::new(Cursor::new(bytes))).unwrap();
+            let decoded_spec = decoder.spec();
+            assert_eq!((decoded_spec.sample_rate, decoded_spec.channels), (48000, 2));
+            assert_eq!((decoded_spec.bits_per_sample, decoded_spec.is_float), (bits_per_sample, is_float));
+            assert_eq!(decoded_spec.total_frames, None);
+            let mut decoded = Vec::new();
+            loop {
+                let chunk = decoder.read_frames(256).unwrap();
+                if chunk.is_empty() {
+                    break;
+                }
+                decoded.extend(chunk);
+            }
+            assert_eq!(decoded.len(), samples.len());
+            for (decoded, original) in decoded.iter().zip(&samples) {
+                assert!((decoded - original).abs() <= tolerance, "{} decoded as {}", original, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_streams_without_a_fmt_chunk() {
+        let mut bytes = b"RIFF\xff\xff\xff\xffWAVEdata".to_vec();
+        bytes.extend_from_slice(&UNKNOWN_CHUNK_SIZE.to_le_bytes());
+        let result = WavStreamDecoder::from_reader(Box::new(Cursor::new(bytes)));
+        assert!(matches!(result, Err(TranscoderError::UnsupportedInputFormat(_))));
+        let result = WavStreamDecoder::from_reader(Box::new(Cursor::new(b"OggS\0\0\0\0\0\0\0\0".to_vec())));
+        assert!(matches!(result, Err(TranscoderError::UnsupportedInputFormat(_))));
+    }
+
+    // a RIFF/WAVE file holding a single chunk whose header declares `declared_size` bytes but carries only `body`
+    fn wav_with_chunk(id: &[u8; 4], declared_size: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\xff\xff\xff\xffWAVE".to_vec();
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&declared_size.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn does_not_trust_declared_chunk_sizes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("huge.wav");
+
+        let mut fmt = vec![0u8; 40];
+        fmt[0..2].copy_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        fmt[20..24].copy_from_slice(&0x3u32.to_le_bytes());
+        std::fs::write(&path, wav_with_chunk(b"fmt ", u32::MAX - 1, &fmt)).unwrap();
+        assert_eq!(read_channel_mask(&path).unwrap(), Some(0x3));
+
+        let mut list = b"INFOINAM".to_vec();
+        list.extend_from_slice(&5u32.to_le_bytes());
+        list.extend_from_slice(b"Song\0");
+        std::fs::write(&path, wav_with_chunk(b"LIST", u32::MAX - 1, &list)).unwrap();
+        assert_eq!(read_info_tags(&path).unwrap(), vec![("TITLE".to_string(), "Song".to_string())]);
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// error type for the transcoder
@@ -24,6 +25,21 @@ pub enum TranscoderError {
     #[error("FLAC error: {0}")]
     Flac(String),
 
+    /// error: error from the `vorbis_rs` crate for Ogg Vorbis
+    #[cfg(feature = "vorbis")]
+    #[error("Vorbis error: {0}")]
+    Vorbis(String),
+
+    /// error: error from the `opus` crate for Ogg Opus
+    #[cfg(feature = "opus")]
+    #[error("Opus error: {0}")]
+    Opus(String),
+
+    /// error: error from the `mp3lame-encoder` and `id3` crates for MP3
+    #[cfg(feature = "mp3")]
+    #[error("MP3 error: {0}")]
+    Mp3(String),
+
     /// error: error from the `rubato` crate for resampling
     #[error("Resampler error: {0}")]
     Resampler(String),
@@ -32,15 +48,56 @@ pub enum TranscoderError {
     #[error("FFmpeg CLI error: {0}")]
     FfmpegCli(String),
 
+    /// error: ffmpeg does not provide the requested encoder
+    #[error("Unknown encoder: {0}")]
+    UnknownEncoder(String),
+
+    /// error: the encoder rejected the sample rate
+    #[error("Sample rate of {sample_rate} Hz is not supported by the {codec} encoder")]
+    UnsupportedSampleRate { codec: String, sample_rate: u32 },
+
+    /// error: the encoder rejected the channel layout
+    #[error("Channel layout '{layout}' is not supported by the {codec} encoder")]
+    UnsupportedChannelLayout { codec: String, layout: String },
+
+    /// error: the input could not be demuxed or decoded
+    #[error("Corrupt or unreadable input: {0}")]
+    CorruptInput(String),
+
+    /// error: a file could not be opened for lack of permissions
+    #[error("Permission denied: {}", .0.display())]
+    PermissionDenied(PathBuf),
+
     /// error: error with respect to file paths
     #[error("Path error: {0}")]
     Path(String),
 
+    /// error: transcoding was cancelled or timed out before completion
+    #[error("Transcoding cancelled: {0}")]
+    Cancelled(String),
+
+    /// error: the job file could not be read or describes invalid transcodes
+    #[error("Job file error: {0}")]
+    Job(String),
+
+    /// error: the watched folder could not be monitored
+    #[error("Watch error: {0}")]
+    Watch(String),
+
+    /// error: the HTTP service could not be started
+    #[error("Server error: {0}")]
+    Serve(String),
+
+    /// error: an output does not match its input
+    #[error("Verification failed: {0}")]
+    Verification(String),
+
     /// error: error during argument parsing or validation
     #[error("Argument error: {0}")]
     Argument(String),
 
     // catch all for other errors
+    #[allow(dead_code)]
     #[error("An unexpected error occurred: {0}")]
     Other(String),
 }
\ No newline at end of file
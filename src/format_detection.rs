@@ -0,0 +1,427 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use log::{debug, info, warn};
+use crate::errors::TranscoderError;
+use crate::utils::{get_file_extension, is_stdio};
+
+// number of bytes read for probing, enough to walk past the usual RIFF and Ogg headers
+const PROBE_SIZE: u64 = 64 * 1024;
+
+/// how much a detected format can be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// only the file name hints at the format
+    Low,
+    /// a sync word or a loose signature matched
+    Medium,
+    /// a container signature and its structure matched, or the user named the format
+    High,
+}
+
+/// where a detected format came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionSource {
+    /// `--input-format` / `--output-format`
+    Override,
+    /// magic bytes at the start of the file
+    Content,
+    /// the file extension
+    Extension,
+}
+
+/// result of format detection; `format` uses the canonical extension of the format (e.g. `wav`, `aac`, `m4a`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedFormat {
+    pub format: String,
+    pub confidence: Confidence,
+    pub source: DetectionSource,
+}
+
+impl fmt::Display for DetectedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?} confidence, from {:?})", self.format, self.confidence, self.source)
+    }
+}
+
+/// detects the format of an input file
+/// an explicit override wins, then the file content is probed, and the extension is used as a last resort
+pub fn detect_input_format(path: &Path, format_override: Option<&str>) -> Result<DetectedFormat, TranscoderError> {
+    if let Some(format) = format_override {
+        return Ok(DetectedFormat {
+            format: canonical_format(format),
+            confidence: Confidence::High,
+            source: DetectionSource::Override,
+        });
+    }
+
+    // stdin cannot be rewound after probing, so its format has to be given
+    if is_stdio(path) {
+        return Err(TranscoderError::Argument("reading from stdin requires --input-format".to_string()));
+    }
+
+    let extension = get_file_extension(path).ok().map(|ext| canonical_format(&ext));
+
+    // headerless PCM has no signature to probe and may accidentally look like an MPEG frame sync
+    if extension.as_deref() == Some("raw") {
+        return Ok(DetectedFormat { format: "raw".to_string(), confidence: Confidence::Low, source: DetectionSource::Extension });
+    }
+
+    let probed = probe_file(path)?;
+
+    match (probed, extension) {
+        (Some((format, confidence)), extension) => {
+            if let Some(extension) = extension.filter(|ext| !same_family(ext, &format)) {
+                warn!("Input {:?} has a '{}' extension but its content looks like '{}'", path, extension, format);
+            }
+            Ok(DetectedFormat { format, confidence, source: DetectionSource::Content })
+        }
+        (None, Some(extension)) => {
+            info!("Could not recognize the content of {:?}, falling back to its '{}' extension", path, extension);
+            Ok(DetectedFormat { format: extension, confidence: Confidence::Low, source: DetectionSource::Extension })
+        }
+        (None, None) => Err(TranscoderError::UnsupportedInputFormat(format!(
+            "could not detect the format of {:?}, please specify --input-format", path
+        ))),
+    }
+}
+
+/// determines the format of an output file from an explicit override or its extension
+pub fn detect_output_format(path: &Path, format_override: Option<&str>) -> Result<DetectedFormat, TranscoderError> {
+    if let Some(format) = format_override {
+        return Ok(DetectedFormat {
+            format: canonical_format(format),
+            confidence: Confidence::High,
+            source: DetectionSource::Override,
+        });
+    }
+
+    if is_stdio(path) {
+        return Err(TranscoderError::Argument("writing to stdout requires --output-format".to_string()));
+    }
+
+    let extension = get_file_extension(path).map_err(|_| TranscoderError::Path(format!(
+        "Output file path must have an extension or --output-format must be given: {}", path.display()
+    )))?;
+    Ok(DetectedFormat {
+        format: canonical_format(&extension),
+        confidence: Confidence::Low,
+        source: DetectionSource::Extension,
+    })
+}
+
+/// maps format names and extension aliases onto the canonical names used throughout rewav
+pub fn canonical_format(name: &str) -> String {
+    let name = name.trim().trim_start_matches('.').to_ascii_lowercase();
+    match name.as_str() {
+        "wave" => "wav",
+        "aif" | "aifc" => "aiff",
+        "oga" | "vorbis" => "ogg",
+        "adts" => "aac",
+        "mp4" | "m4b" | "mov" => "m4a",
+        "mpga" => "mp3",
+        "weba" | "webm" | "mkv" => "mka",
+        "pcm" => "raw",
+        other => other,
+    }.to_string()
+}
+
+/// maps a canonical format onto the name of the corresponding ffmpeg (de)muxer
+pub fn ffmpeg_format_name(format: &str) -> &str {
+    match format {
+        "aac" => "adts",
+        "m4a" => "mp4",
+        "mka" => "matroska",
+        "wma" => "asf",
+        "rf64" => "wav",
+        "spx" => "ogg",
+        other => other,
+    }
+}
+
+// formats that share a canonical family even though their names differ
+fn same_family(a: &str, b: &str) -> bool {
+    a == b || matches!((a, b), ("wav", "rf64") | ("rf64", "wav") | ("ogg", "opus") | ("opus", "ogg"))
+}
+
+// reads the start of the file, skipping a leading ID3v2 tag, and probes it
+fn probe_file(path: &Path) -> Result<Option<(String, Confidence)>, TranscoderError> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::with_capacity(PROBE_SIZE as usize);
+    (&mut file).take(PROBE_SIZE).read_to_end(&mut buffer)?;
+
+    // ID3v2 tags are prepended to MP3, AAC and sometimes FLAC files, the real signature follows them
+    if let Some(tag_size) = id3v2_size(&buffer) {
+        debug!("Skipping {} byte ID3v2 tag while probing {:?}", tag_size, path);
+        file.seek(SeekFrom::Start(tag_size as u64))?;
+        buffer.clear();
+        (&mut file).take(PROBE_SIZE).read_to_end(&mut buffer)?;
+        return Ok(Some(probe_bytes(&buffer).unwrap_or(("mp3".to_string(), Confidence::Medium))));
+    }
+
+    Ok(probe_bytes(&buffer))
+}
+
+/// probes a buffer holding the start of a file for known audio signatures
+pub fn probe_bytes(buffer: &[u8]) -> Option<(String, Confidence)> {
+    let high = |format: &str| Some((format.to_string(), Confidence::High));
+    let medium = |format: &str| Some((format.to_string(), Confidence::Medium));
+
+    if buffer.len() < 4 {
+        return None;
+    }
+
+    match &buffer[..4] {
+        // RIFF/WAVE, including files with JUNK or other chunks in front of `fmt `
+        b"RIFF" if buffer.get(8..12) == Some(b"WAVE") => return high("wav"),
+        b"RF64" | b"BW64" if buffer.get(8..12) == Some(b"WAVE") => return high("rf64"),
+        b"FORM" if matches!(buffer.get(8..12), Some(b"AIFF" | b"AIFC")) => return high("aiff"),
+        b"fLaC" => return high("flac"),
+        b"OggS" => return probe_ogg(buffer).map(|format| (format.to_string(), Confidence::High)),
+        b"caff" => return high("caf"),
+        b"wvpk" => return high("wv"),
+        b"MAC " => return high("ape"),
+        b"#!AM" if buffer.starts_with(b"#!AMR") => return high("amr"),
+        [0x1A, 0x45, 0xDF, 0xA3] => return high("mka"),
+        [0x30, 0x26, 0xB2, 0x75] => return high("wma"),
+        _ => {}
+    }
+
+    // Sony Wave64 starts with a GUID spelling "riff"
+    if buffer.starts_with(b"riff\x2e\x91\xcf\x11") {
+        return high("w64");
+    }
+    // ISO base media files (M4A, MP4, ALAC) carry an `ftyp` box first
+    if buffer.get(4..8) == Some(b"ftyp") {
+        return high("m4a");
+    }
+    if buffer.starts_with(&[0x0B, 0x77]) {
+        return medium("ac3");
+    }
+    if buffer.starts_with(&[0x7F, 0xFE, 0x80, 0x01]) {
+        return medium("dts");
+    }
+
+    // headerless MPEG audio and raw ADTS AAC only have frame sync words, two consecutive frames are required
+    if let Some(format) = probe_mpeg_frames(buffer) {
+        return medium(format);
+    }
+
+    // anything else `infer` knows about, e.g. MIDI or rarer containers
+    infer::get(buffer)
+        .filter(|kind| kind.matcher_type() == infer::MatcherType::Audio)
+        .and_then(|kind| medium(&canonical_format(kind.extension())))
+}
+
+// identifies the codec of the first logical stream of an Ogg file
+fn probe_ogg(buffer: &[u8]) -> Option<&'static str> {
+    // page header: 27 bytes followed by the segment table
+    let segments = *buffer.get(26)? as usize;
+    let packet = buffer.get(27 + segments..)?;
+
+    if packet.starts_with(b"OpusHead") {
+        Some("opus")
+    } else if packet.starts_with(b"Speex   ") {
+        Some("spx")
+    } else {
+        // Vorbis, Ogg FLAC and anything unknown is handled as a generic Ogg file
+        Some("ogg")
+    }
+}
+
+// returns the total size of an ID3v2 tag at the start of the buffer
+fn id3v2_size(buffer: &[u8]) -> Option<usize> {
+    if buffer.len() < 10 || &buffer[..3] != b"ID3" {
+        return None;
+    }
+    // the size is a 28-bit syncsafe integer, excluding the 10 byte header and the optional footer
+    let size = buffer[6..10].iter().fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    let footer = if buffer[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+// parses the header of a frame and returns the frame length
+type FrameParser = fn(&[u8]) -> Option<usize>;
+
+const FRAME_PARSERS: [(&str, FrameParser); 2] = [("aac", adts_frame_length), ("mp3", mp3_frame_length)];
+
+// scans for MPEG layer III or ADTS frames and checks that the next frame header follows where expected
+fn probe_mpeg_frames(buffer: &[u8]) -> Option<&'static str> {
+    let limit = buffer.len().saturating_sub(4).min(4096);
+    for offset in 0..limit {
+        if buffer[offset] != 0xFF || buffer[offset + 1] & 0xE0 != 0xE0 {
+            continue;
+        }
+
+        for (format, frame_length) in FRAME_PARSERS {
+            let followed_by_frame = frame_length(&buffer[offset..])
+                .and_then(|length| buffer.get(offset + length..))
+                .is_some_and(|next| frame_length(next).is_some());
+            if followed_by_frame {
+                return Some(format);
+            }
+        }
+    }
+    None
+}
+
+// length of the ADTS frame starting at the buffer, if the header is valid
+fn adts_frame_length(header: &[u8]) -> Option<usize> {
+    if header.len() < 7 || header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    // sampling frequency index 13 to 15 is reserved
+    if (header[2] >> 2) & 0x0F > 12 {
+        return None;
+    }
+    let length = (((header[3] & 0x03) as usize) << 11) | ((header[4] as usize) << 3) | ((header[5] >> 5) as usize);
+    (length >= 7).then_some(length)
+}
+
+// length of the MPEG audio layer III frame starting at the buffer, if the header is valid
+fn mp3_frame_length(header: &[u8]) -> Option<usize> {
+    if header.len() < 4 || header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0x03; // 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1
+    let layer = (header[1] >> 1) & 0x03; // 1 = layer III
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as usize;
+
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    const MPEG1_BITRATES: [usize; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const MPEG2_BITRATES: [usize; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const SAMPLE_RATES: [usize; 3] = [44100, 48000, 32000];
+
+    let (bitrate, sample_rate, samples_per_frame) = match version {
+        3 => (MPEG1_BITRATES[bitrate_index], SAMPLE_RATES[sample_rate_index], 1152),
+        2 => (MPEG2_BITRATES[bitrate_index], SAMPLE_RATES[sample_rate_index] / 2, 576),
+        _ => (MPEG2_BITRATES[bitrate_index], SAMPLE_RATES[sample_rate_index] / 4, 576),
+    };
+
+    Some(samples_per_frame / 8 * bitrate * 1000 / sample_rate + padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use crate::test_support;
+
+    // a RIFF file of the given form with a chunk of `padding` bytes in front of `fmt `
+    fn riff_with_junk(magic: &[u8; 4], padding: usize) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEJUNK");
+        bytes.extend_from_slice(&(padding as u32).to_le_bytes());
+        bytes.resize(bytes.len() + padding, 0);
+        bytes.extend_from_slice(b"fmt ");
+        bytes
+    }
+
+    // ADTS frames of AAC LC at 44.1 kHz, each `length` bytes long including the header
+    fn adts_frames(count: usize, length: usize) -> Vec<u8> {
+        let header = [0xFF, 0xF1, 0x50, 0x80 | (length >> 11) as u8, (length >> 3) as u8, ((length & 0x07) << 5) as u8 | 0x1F, 0xFC];
+        let mut frame = header.to_vec();
+        frame.resize(length, 0);
+        frame.repeat(count)
+    }
+
+    // MPEG-1 layer III frames at 128 kb/s and 44.1 kHz, 417 bytes each
+    fn mp3_frames(count: usize) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame.repeat(count)
+    }
+
+    fn id3v2_tag(content_size: usize) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend((0..4).rev().map(|shift| ((content_size >> (7 * shift)) & 0x7F) as u8));
+        tag.resize(10 + content_size, 0);
+        tag
+    }
+
+    fn probed(format: &str, confidence: Confidence) -> Option<(String, Confidence)> {
+        Some((format.to_string(), confidence))
+    }
+
+    #[test]
+    fn probes_wav_files() {
+        assert_eq!(probe_bytes(&test_support::sine_wav(44100, 16)), probed("wav", Confidence::High));
+        assert_eq!(probe_bytes(&riff_with_junk(b"RIFF", 28)), probed("wav", Confidence::High));
+    }
+
+    #[test]
+    fn probes_rf64_files() {
+        assert_eq!(probe_bytes(&riff_with_junk(b"RF64", 28)), probed("rf64", Confidence::High));
+        assert_eq!(probe_bytes(&riff_with_junk(b"BW64", 28)), probed("rf64", Confidence::High));
+        // a RIFF file of another form is no WAV file
+        assert_eq!(probe_bytes(b"RF64\0\0\0\0AVI LIST"), None);
+    }
+
+    #[test]
+    fn probes_raw_adts() {
+        assert_eq!(probe_bytes(&adts_frames(3, 371)), probed("aac", Confidence::Medium));
+        // garbage in front of the first frame is skipped
+        let mut bytes = vec![0x42; 100];
+        bytes.extend(adts_frames(3, 371));
+        assert_eq!(probe_mpeg_frames(&bytes), Some("aac"));
+    }
+
+    #[test]
+    fn probes_mp3_frames() {
+        assert_eq!(probe_mpeg_frames(&mp3_frames(3)), Some("mp3"));
+        // a single frame sync without a following frame is no evidence
+        assert_eq!(probe_mpeg_frames(&mp3_frames(1)), None);
+    }
+
+    #[test]
+    fn measures_id3v2_tags() {
+        assert_eq!(id3v2_size(&id3v2_tag(300)), Some(310));
+        let mut with_footer = id3v2_tag(300);
+        with_footer[5] = 0x10;
+        assert_eq!(id3v2_size(&with_footer), Some(320));
+        assert_eq!(id3v2_size(b"ID3"), None);
+        assert_eq!(id3v2_size(&mp3_frames(1)), None);
+    }
+
+    #[test]
+    fn probes_mp3_behind_an_id3v2_tag() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.bin");
+        let mut bytes = id3v2_tag(2000);
+        bytes.extend(mp3_frames(4));
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(probe_file(&path).unwrap(), probed("mp3", Confidence::Medium));
+        let detected = detect_input_format(&path, None).unwrap();
+        assert_eq!((detected.format.as_str(), detected.source), ("mp3", DetectionSource::Content));
+    }
+
+    #[test]
+    fn ignores_random_bytes() {
+        let mut state = 0x9E37_79B9u32;
+        let bytes: Vec<u8> = (0..PROBE_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(probe_bytes(&bytes), None);
+        assert_eq!(probe_bytes(b"ID"), None);
+    }
+
+    #[test]
+    fn keeps_mp2_apart_from_mp3() {
+        assert_eq!(canonical_format("MP2"), "mp2");
+        assert_eq!(canonical_format(".mpga"), "mp3");
+        assert_eq!(canonical_format("wave"), "wav");
+    }
+}
@@ -0,0 +1,723 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::ValueEnum;
+use log::{error, info};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use crate::audio_processor::biquad::{BiquadKind, BiquadSpec};
+use crate::cancellation::CancellationToken;
+use crate::cli::{RunArgs, TranscodeArgs};
+use crate::errors::TranscoderError;
+use crate::manifest::{EntryStatus, Manifest, ManifestEntry};
+use crate::progress::ProgressCallback;
+use crate::operations::fan_out;
+use crate::transcoders::TranscodeOptions;
+use crate::utils;
+
+/// a job file: inputs to transcode, each to one or more outputs
+/// options are layered from the command line over the file-wide `options`, the job's `options` and the output's `options`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobFile {
+    #[serde(default)]
+    pub options: JobOptions,
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// input file, relative to the job file
+    pub input: PathBuf,
+    #[serde(default)]
+    pub options: JobOptions,
+    pub outputs: Vec<JobOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobOutput {
+    /// output file, relative to the job file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub options: JobOptions,
+}
+
+/// transcode options of a job file, named like the long command line options
+/// values are checked when they are applied, since their parsers report errors as plain messages
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JobOptions {
+    pub input_format: Option<String>,
+    pub output_format: Option<String>,
+    pub codec: Option<String>,
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub quality_preset: Option<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub highpass: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub lowpass: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub bandpass: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub notch: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub low_shelf: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub high_shelf: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub eq: Option<Vec<String>>,
+    pub af: Option<String>,
+    pub gain: Option<f32>,
+    pub normalize_peak: Option<f32>,
+    pub soft_limit: Option<f32>,
+    pub bitrate_mode: Option<String>,
+    pub vbr_quality: Option<u8>,
+    pub joint_stereo: Option<bool>,
+    pub raw_format: Option<String>,
+    pub raw_rate: Option<u32>,
+    pub raw_channels: Option<u8>,
+    pub ffmpeg_path: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub overwrite: Option<bool>,
+    pub trim_silence: Option<bool>,
+    pub silence_threshold: Option<f32>,
+    pub min_silence: Option<f64>,
+}
+
+impl JobOptions {
+    /// builds options from `KEY=VALUE` pairs given as text, e.g. on the command line or in a query string
+    /// numbers and booleans keep their type unless the value is put in double quotes; repeated filter keys add filters
+    pub fn from_settings<'a>(settings: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self, String> {
+        let mut table = toml::Table::new();
+        for (key, value) in settings {
+            let value = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+                Some(quoted) => toml::Value::String(quoted.to_string()),
+                None => toml::from_str::<toml::Table>(&format!("value = {}", value))
+                    .ok()
+                    .and_then(|mut parsed| parsed.remove("value"))
+                    .unwrap_or_else(|| toml::Value::String(value.to_string())),
+            };
+            match table.get_mut(key) {
+                None => {
+                    table.insert(key.to_string(), value);
+                }
+                Some(toml::Value::Array(values)) => values.push(value),
+                Some(earlier) => *earlier = toml::Value::Array(vec![earlier.clone(), value]),
+            }
+        }
+        toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| e.message().to_string())
+    }
+
+    /// overrides the options set in this table, reporting invalid values with the name of their field
+    pub fn apply(&self, args: &mut TranscodeArgs) -> Result<(), String> {
+        set(&mut args.input_format, &self.input_format);
+        set(&mut args.output_format, &self.output_format);
+        set(&mut args.codec, &self.codec);
+        set(&mut args.bitrate, &self.bitrate);
+        set(&mut args.sample_rate, &self.sample_rate);
+        set(&mut args.channels, &self.channels);
+        if let Some(preset) = &self.quality_preset {
+            args.quality_preset = Some(value_enum("quality-preset", preset)?);
+        }
+
+        let filters = [
+            (&mut args.highpass, &self.highpass, BiquadKind::Highpass, "highpass"),
+            (&mut args.lowpass, &self.lowpass, BiquadKind::Lowpass, "lowpass"),
+            (&mut args.bandpass, &self.bandpass, BiquadKind::Bandpass, "bandpass"),
+            (&mut args.notch, &self.notch, BiquadKind::Notch, "notch"),
+            (&mut args.low_shelf, &self.low_shelf, BiquadKind::LowShelf, "low-shelf"),
+            (&mut args.high_shelf, &self.high_shelf, BiquadKind::HighShelf, "high-shelf"),
+            (&mut args.eq, &self.eq, BiquadKind::Peaking, "eq"),
+        ];
+        for (specs, values, kind, field) in filters {
+            if let Some(values) = values {
+                *specs = values
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| BiquadSpec::parse(kind, value).map_err(|e| format!("{}[{}]: {}", field, index, e)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        if let Some(af) = &self.af {
+            args.audio_filter = Some(af.clone());
+        }
+
+        set(&mut args.gain, &self.gain);
+        set(&mut args.normalize_peak, &self.normalize_peak);
+        set(&mut args.soft_limit, &self.soft_limit);
+        if let Some(mode) = &self.bitrate_mode {
+            args.bitrate_mode = Some(value_enum("bitrate-mode", mode)?);
+        }
+        if let Some(quality) = self.vbr_quality {
+            if quality > 9 {
+                return Err(format!("vbr-quality: {} is not in 0..=9", quality));
+            }
+            args.vbr_quality = Some(quality);
+        }
+        if let Some(joint_stereo) = self.joint_stereo {
+            args.joint_stereo = joint_stereo;
+            args.no_joint_stereo = !joint_stereo;
+        }
+        if let Some(format) = &self.raw_format {
+            args.raw_format = Some(value_enum("raw-format", format)?);
+        }
+        set(&mut args.raw_rate, &self.raw_rate);
+        set(&mut args.raw_channels, &self.raw_channels);
+        set(&mut args.ffmpeg_path, &self.ffmpeg_path);
+        set(&mut args.threads, &self.threads);
+        if let Some(overwrite) = self.overwrite {
+            args.overwrite = overwrite;
+            args.no_overwrite = !overwrite;
+        }
+        if let Some(trim_silence) = self.trim_silence {
+            args.trim_silence = trim_silence;
+        }
+        if let Some(threshold) = self.silence_threshold {
+            args.silence_threshold = threshold;
+        }
+        if let Some(min_silence) = self.min_silence {
+            args.min_silence = min_silence;
+        }
+        Ok(())
+    }
+}
+
+/// an output given on the command line as `FILE[,KEY=VALUE...]`, the keys being those of the job file options
+#[derive(Debug, Clone)]
+pub struct OutputArg {
+    pub path: PathBuf,
+    pub options: JobOptions,
+}
+
+impl OutputArg {
+    /// parses `FILE[,KEY=VALUE...]`; values containing commas are put in double quotes, repeated filter keys add filters
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = split_unquoted(value);
+        let mut settings = Vec::new();
+
+        // settings are taken from the end, so the file name itself may contain commas
+        while parts.len() > 1 {
+            let Some((key, value)) = parts[parts.len() - 1].split_once('=') else {
+                break;
+            };
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+                break;
+            }
+            settings.push((key, value));
+            parts.pop();
+        }
+        settings.reverse();
+
+        let path = PathBuf::from(parts.join(","));
+        if path.as_os_str().is_empty() {
+            return Err("missing output file".to_string());
+        }
+        Ok(Self { path, options: JobOptions::from_settings(settings)? })
+    }
+
+    /// the transcode options of this output: the command line options overridden by its own
+    pub fn to_options(
+        &self,
+        base_args: &TranscodeArgs,
+        progress: Option<ProgressCallback>,
+        cancellation: CancellationToken,
+    ) -> Result<TranscodeOptions, TranscoderError> {
+        let mut args = base_args.clone();
+        self.options.apply(&mut args).map_err(|e| TranscoderError::Argument(format!("{}: {}", self.path.display(), e)))?;
+        args.validate()?;
+        args.to_options(&self.path, progress, cancellation)
+    }
+}
+
+/// reads a TOML or JSON job file, chosen by its extension, and runs every transcode it describes in order
+/// the whole file is validated before the first transcode starts; relative paths are resolved against the job file
+/// outputs are recorded in a manifest as they finish, and outputs whose input and options are unchanged since they were
+/// written are skipped, so that a rerun resumes an interrupted batch; failed jobs are recorded and do not stop the run
+pub fn run_job_file(args: &RunArgs, progress: Option<ProgressCallback>, cancellation: CancellationToken) -> Result<(), TranscoderError> {
+    let job_path = args.job_file.as_path();
+    let job_file: JobFile = load(job_path)?;
+    let base_dir = job_path.parent().unwrap_or(Path::new(""));
+    let plan = plan(job_path, &job_file, base_dir, &args.transcode, progress, cancellation.clone())?;
+    let manifest_path = args.manifest.clone().unwrap_or_else(|| job_path.with_extension("manifest.jsonl"));
+    let mut manifest = Manifest::open(&manifest_path)?;
+    info!("Job file {:?}: {} jobs", job_path, plan.len());
+
+    let (total, mut failed, mut skipped) = (plan.len(), 0, 0);
+    for (index, (input, outputs)) in plan.into_iter().enumerate() {
+        cancellation.check()?;
+        // --timeout limits every job rather than the whole run
+        let job_cancellation = match args.transcode.timeout {
+            Some(timeout) => cancellation.with_timeout(Duration::from_secs(timeout)),
+            None => cancellation.clone(),
+        };
+        // stdin cannot be hashed, its outputs are always transcoded
+        let input_state = if utils::is_stdio(&input) {
+            None
+        } else {
+            let absolute = std::path::absolute(&input)?;
+            let state = manifest.input_state(&absolute)?;
+            Some((absolute, state))
+        };
+
+        let mut pending = Vec::new();
+        let mut records = Vec::new();
+        for (path, mut options) in outputs {
+            options.cancellation = job_cancellation.clone();
+            let record = match (&input_state, utils::is_stdio(&path)) {
+                (Some(_), false) => Some((std::path::absolute(&path)?, options.fingerprint().hash())),
+                _ => None,
+            };
+            if let (Some((_, state)), Some((output, options_hash))) = (&input_state, &record) {
+                if !args.force && manifest.is_done(output, state, options_hash) {
+                    info!("Skipping {:?}, its input and options are unchanged", path);
+                    manifest.refresh(output, state)?;
+                    skipped += 1;
+                    continue;
+                }
+                // outputs written by an earlier run are replaced once their input or options changed
+                if manifest.contains(output) {
+                    options.overwrite = true;
+                }
+            }
+            pending.push((path, options));
+            records.push(record);
+        }
+        if pending.is_empty() {
+            continue;
+        }
+
+        let paths: Vec<&PathBuf> = pending.iter().map(|(path, _)| path).collect();
+        info!("Job {}/{}: {:?} -> {:?}", index + 1, total, input, paths);
+        // the outputs of a job share a single decode of its input
+        let result = fan_out::transcode_to_outputs(&input, &pending);
+        if let Err(TranscoderError::Cancelled(_)) = &result {
+            // left unrecorded, the job is run again on the next run; a job that only timed out is recorded as failed
+            cancellation.check()?;
+        }
+
+        if let Some((input, state)) = &input_state {
+            for (output, options_hash) in records.into_iter().flatten() {
+                manifest.record(ManifestEntry {
+                    output,
+                    input: input.clone(),
+                    input_hash: state.hash.clone(),
+                    input_size: state.size,
+                    input_modified: state.modified,
+                    options_hash,
+                    status: if result.is_ok() { EntryStatus::Done } else { EntryStatus::Failed },
+                    error: result.as_ref().err().map(ToString::to_string),
+                })?;
+            }
+        }
+        if let Err(e) = result {
+            error!("Job {}/{} failed: {}", index + 1, total, e);
+            failed += 1;
+        }
+    }
+
+    if skipped > 0 {
+        info!("Skipped {} outputs already up to date according to {:?}", skipped, manifest_path);
+    }
+    if failed > 0 {
+        return Err(TranscoderError::Job(format!("{}: {} of {} jobs failed", job_path.display(), failed, total)));
+    }
+    Ok(())
+}
+
+/// reads a TOML or JSON profile holding a single table of job options, as used by `watch`
+pub fn load_profile(profile_path: &Path) -> Result<JobOptions, TranscoderError> {
+    load(profile_path)
+}
+
+// parses a job file or profile, reporting errors with the path of the offending field (e.g. `jobs[0].outputs[1].options.bitrate`)
+fn load<T: DeserializeOwned>(job_path: &Path) -> Result<T, TranscoderError> {
+    let content = std::fs::read_to_string(job_path)?;
+    let extension = job_path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let invalid = |path: String, message: String| TranscoderError::Job(format!("{}: {}: {}", job_path.display(), path, message));
+
+    match extension.as_str() {
+        "toml" => {
+            let deserializer = toml::Deserializer::parse(&content)
+                .map_err(|e| TranscoderError::Job(format!("{}: {}", job_path.display(), e)))?;
+            serde_path_to_error::deserialize(deserializer).map_err(|e| invalid(e.path().to_string(), e.inner().to_string()))
+        }
+        "json" => {
+            let mut deserializer = serde_json::Deserializer::from_str(&content);
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|e| invalid(e.path().to_string(), e.inner().to_string()))
+        }
+        _ => Err(TranscoderError::Job(format!("{}: expected a .toml or .json file", job_path.display()))),
+    }
+}
+
+// the input of a job and the paths and options of its outputs
+type PlannedJob = (PathBuf, Vec<(PathBuf, TranscodeOptions)>);
+
+// resolves every job of the job file for running
+fn plan(
+    job_path: &Path,
+    job_file: &JobFile,
+    base_dir: &Path,
+    base_args: &TranscodeArgs,
+    progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+) -> Result<Vec<PlannedJob>, TranscoderError> {
+    let invalid = |path: String, message: String| TranscoderError::Job(format!("{}: {}: {}", job_path.display(), path, message));
+    // options report the offending field first, which continues the path of their table
+    let invalid_option = |path: String, message: String| TranscoderError::Job(format!("{}: {}.{}", job_path.display(), path, message));
+    if job_file.jobs.is_empty() {
+        return Err(invalid("jobs".to_string(), "at least one job is required".to_string()));
+    }
+
+    let mut plan = Vec::new();
+    for (job_index, job) in job_file.jobs.iter().enumerate() {
+        let job_field = format!("jobs[{}]", job_index);
+        let input = resolve(base_dir, &job.input);
+        if !utils::is_stdio(&input) && !input.is_file() {
+            return Err(invalid(format!("{}.input", job_field), format!("{:?} does not exist or is not a file", input)));
+        }
+        if job.outputs.is_empty() {
+            return Err(invalid(format!("{}.outputs", job_field), "at least one output is required".to_string()));
+        }
+
+        let mut outputs = Vec::new();
+        for (output_index, output) in job.outputs.iter().enumerate() {
+            let output_field = format!("{}.outputs[{}]", job_field, output_index);
+            let mut args = base_args.clone();
+            job_file.options.apply(&mut args).map_err(|e| invalid_option("options".to_string(), e))?;
+            job.options.apply(&mut args).map_err(|e| invalid_option(format!("{}.options", job_field), e))?;
+            output.options.apply(&mut args).map_err(|e| invalid_option(format!("{}.options", output_field), e))?;
+            args.validate().map_err(|e| invalid(output_field.clone(), e.to_string()))?;
+
+            let path = resolve(base_dir, &output.path);
+            let options = args
+                .to_options(&path, progress.clone(), cancellation.clone())
+                .map_err(|e| invalid(format!("{}.path", output_field), e.to_string()))?;
+            outputs.push((path, options));
+        }
+        plan.push((input, outputs));
+    }
+    Ok(plan)
+}
+
+// paths in a job file are relative to the file itself, `-` stays stdin/stdout
+fn resolve(base_dir: &Path, path: &Path) -> PathBuf {
+    if utils::is_stdio(path) { path.to_path_buf() } else { base_dir.join(path) }
+}
+
+// splits at commas outside of double quotes, keeping the quotes
+fn split_unquoted(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+// filter options take a single value or a list of them, plain numbers (e.g. a cutoff frequency) included
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a filter setting or a list of them")]
+    enum OneOrMany {
+        One(Setting),
+        Many(Vec<Setting>),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Text(String),
+        Number(f64),
+    }
+    let text = |setting| match setting {
+        Setting::Text(text) => text,
+        Setting::Number(number) => number.to_string(),
+    };
+    Ok(Some(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(setting) => vec![text(setting)],
+        OneOrMany::Many(settings) => settings.into_iter().map(text).collect(),
+    }))
+}
+
+fn set<T: Clone>(target: &mut Option<T>, value: &Option<T>) {
+    if value.is_some() {
+        target.clone_from(value);
+    }
+}
+
+// parses a value the way clap parses the command line option `field`
+fn value_enum<T: ValueEnum>(field: &str, value: &str) -> Result<T, String> {
+    T::from_str(value, true).map_err(|_| {
+        let names: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value().map(|value| value.get_name().to_string()))
+            .collect();
+        format!("{}: invalid value {:?}, expected one of {}", field, value, names.join(", "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use clap::Parser;
+    use tempfile::TempDir;
+    use crate::cli::{CliArgs, Command};
+    use crate::test_support;
+
+    fn run_args(job_file: &Path, options: &[&str]) -> RunArgs {
+        let job_file = job_file.to_str().unwrap();
+        let cli = CliArgs::try_parse_from(["rewav", "run", job_file].iter().chain(options)).unwrap();
+        let Some(Command::Run(args)) = cli.command else { unreachable!() };
+        args
+    }
+
+    // writes a job file with one input next to it and plans it
+    fn plan_job_file(name: &str, content: &str) -> Result<Vec<PlannedJob>, TranscoderError> {
+        let dir = TempDir::new().unwrap();
+        test_support::write_sine_wav(&dir.path().join("in.wav"), 44100, 100);
+        let job_path = dir.path().join(name);
+        std::fs::write(&job_path, content).unwrap();
+        let args = run_args(&job_path, &["--bitrate", "192"]);
+        let job_file: JobFile = load(&job_path)?;
+        plan(&job_path, &job_file, dir.path(), &args.transcode, None, CancellationToken::new())
+    }
+
+    fn error_message(result: Result<Vec<PlannedJob>, TranscoderError>) -> String {
+        match result {
+            Err(TranscoderError::Job(message)) => message,
+            other => panic!("expected a job error, got {:?}", other.map(|plan| plan.len())),
+        }
+    }
+
+    #[test]
+    fn parses_output_arguments() {
+        let output = OutputArg::parse("out.mp3").unwrap();
+        assert_eq!(output.path, Path::new("out.mp3"));
+        assert!(output.options.bitrate.is_none());
+
+        let output = OutputArg::parse("out.mp3,bitrate=320,sample-rate=48000,joint-stereo=false").unwrap();
+        assert_eq!(output.path, Path::new("out.mp3"));
+        assert_eq!((output.options.bitrate, output.options.sample_rate, output.options.joint_stereo), (Some(320), Some(48000), Some(false)));
+    }
+
+    #[test]
+    fn parses_quoted_and_repeated_settings() {
+        let output = OutputArg::parse(r#"out.wav,af="highpass=f=80,volume=0.5",eq=3000:2,eq=100:-1"#).unwrap();
+        assert_eq!(output.options.af.as_deref(), Some("highpass=f=80,volume=0.5"));
+        assert_eq!(output.options.eq, Some(vec!["3000:2".to_string(), "100:-1".to_string()]));
+        // a quoted number stays text
+        let output = OutputArg::parse(r#"out.ogg,codec="1""#).unwrap();
+        assert_eq!(output.options.codec.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn keeps_commas_in_file_names() {
+        let output = OutputArg::parse("live, part 1.flac,channels=1").unwrap();
+        assert_eq!(output.path, Path::new("live, part 1.flac"));
+        assert_eq!(output.options.channels, Some(1));
+        // settings are only taken from the end
+        let output = OutputArg::parse("a=b,c.wav").unwrap();
+        assert_eq!(output.path, Path::new("a=b,c.wav"));
+    }
+
+    #[test]
+    fn rejects_invalid_output_arguments() {
+        assert!(OutputArg::parse("").is_err());
+        assert!(OutputArg::parse("out.mp3,bitrate=fast").is_err());
+        assert!(OutputArg::parse("out.mp3,color=blue").is_err());
+        assert!(OutputArg::parse("out.mp3,channels=300").is_err());
+    }
+
+    #[test]
+    fn reports_invalid_values_with_their_field() {
+        let output = OutputArg::parse("out.mp3,quality-preset=ultra").unwrap();
+        let mut args = run_args(Path::new("jobs.toml"), &[]).transcode;
+        assert!(output.options.apply(&mut args).unwrap_err().starts_with("quality-preset: invalid value"));
+        let output = OutputArg::parse("out.mp3,highpass=80,highpass=-1").unwrap();
+        assert!(output.options.apply(&mut args).unwrap_err().starts_with("highpass[1]:"));
+    }
+
+    #[test]
+    fn layers_job_file_options() {
+        let plan = plan_job_file(
+            "jobs.toml",
+            r#"
+options = { sample-rate = 48000 }
+
+[[jobs]]
+input = "in.wav"
+options = { channels = 1 }
+outputs = [
+    { path = "a.mp3" },
+    { path = "b.mp3", options = { bitrate = 128, highpass = 80, sample-rate = 44100 } },
+]
+"#,
+        )
+        .unwrap();
+        let (input, outputs) = &plan[0];
+        assert!(input.ends_with("in.wav"));
+        let (a, b) = (&outputs[0].1, &outputs[1].1);
+        assert_eq!((a.bitrate_kbps, a.sample_rate, a.channels), (Some(192), Some(48000), Some(1)));
+        assert_eq!((b.bitrate_kbps, b.sample_rate, b.channels), (Some(128), Some(44100), Some(1)));
+        assert_eq!(b.equalizer.len(), 1);
+        assert!(outputs[1].0.ends_with("b.mp3") && outputs[1].0.is_absolute());
+    }
+
+    #[test]
+    fn reads_json_job_files() {
+        let plan = plan_job_file(
+            "jobs.json",
+            r#"{"jobs": [{"input": "in.wav", "outputs": [{"path": "a.flac", "options": {"lowpass": [8000, "12000:0.5"]}}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(plan[0].1[0].1.equalizer.len(), 2);
+    }
+
+    #[test]
+    fn reports_the_path_of_invalid_fields() {
+        let message = error_message(plan_job_file("jobs.toml", "[[jobs]]\ninput = \"in.wav\"\noutputs = [{ path = \"a.mp3\", options = { bitrate = \"high\" } }]\n"));
+        assert!(message.contains("jobs[0].outputs[0].options.bitrate"), "{}", message);
+        let message = error_message(plan_job_file("jobs.json", r#"{"jobs": [{"input": "in.wav", "outputs": [], "extra": 1}]}"#));
+        assert!(message.contains("jobs[0]") && message.contains("extra"), "{}", message);
+        let message = error_message(plan_job_file("jobs.toml", "[[jobs]]\ninput = \"in.wav\"\noutputs = [{ path = \"a.mp3\", options = { vbr-quality = 12 } }]\n"));
+        assert!(message.contains("jobs[0].outputs[0].options.vbr-quality"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_incomplete_jobs() {
+        let message = error_message(plan_job_file("jobs.toml", "jobs = []\n"));
+        assert!(message.contains("at least one job"), "{}", message);
+        let message = error_message(plan_job_file("jobs.toml", "[[jobs]]\ninput = \"missing.wav\"\noutputs = [{ path = \"a.mp3\" }]\n"));
+        assert!(message.contains("jobs[0].input"), "{}", message);
+        let message = error_message(plan_job_file("jobs.toml", "[[jobs]]\ninput = \"in.wav\"\noutputs = []\n"));
+        assert!(message.contains("jobs[0].outputs"), "{}", message);
+        let message = error_message(plan_job_file("jobs.yaml", "jobs: []\n"));
+        assert!(message.contains("expected a .toml or .json file"), "{}", message);
+    }
+    // a job file transcoding one input to two outputs, run repeatedly
+    struct Batch {
+        dir: TempDir,
+        job_path: PathBuf,
+    }
+
+    impl Batch {
+        fn new(sample_rate: u32) -> Self {
+            let dir = TempDir::new().unwrap();
+            test_support::write_sine_wav(&dir.path().join("in.wav"), 44100, 4410);
+            let batch = Self { job_path: dir.path().join("jobs.toml"), dir };
+            batch.set_sample_rate(sample_rate);
+            batch
+        }
+
+        fn set_sample_rate(&self, sample_rate: u32) {
+            let content = format!(
+                "[[jobs]]\ninput = \"in.wav\"\noutputs = [{{ path = \"a.wav\", options = {{ sample-rate = {} }} }}, {{ path = \"b.wav\" }}]\n",
+                sample_rate
+            );
+            std::fs::write(&self.job_path, content).unwrap();
+        }
+
+        fn output(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        // runs the job file and returns the outputs written by the run
+        fn run(&self, options: &[&str]) -> Vec<&'static str> {
+            // modification times may be too coarse to tell consecutive writes apart
+            let old = SystemTime::now() - Duration::from_secs(60);
+            for name in ["a.wav", "b.wav"] {
+                if let Ok(file) = std::fs::File::options().write(true).open(self.output(name)) {
+                    file.set_modified(old).unwrap();
+                }
+            }
+            run_job_file(&run_args(&self.job_path, options), None, CancellationToken::new()).unwrap();
+            ["a.wav", "b.wav"]
+                .into_iter()
+                .filter(|name| self.output(name).metadata().unwrap().modified().unwrap() != old)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn skips_finished_outputs_on_rerun() {
+        let batch = Batch::new(22050);
+        assert_eq!(batch.run(&[]), ["a.wav", "b.wav"]);
+        assert!(batch.job_path.with_extension("manifest.jsonl").exists());
+        assert!(batch.run(&[]).is_empty());
+        // the thread count does not change the outputs
+        assert!(batch.run(&["--threads", "2"]).is_empty());
+        assert_eq!(batch.run(&["--force"]), ["a.wav", "b.wav"]);
+    }
+
+    #[test]
+    fn transcodes_outputs_whose_options_changed() {
+        let batch = Batch::new(22050);
+        batch.run(&[]);
+        batch.set_sample_rate(16000);
+        assert_eq!(batch.run(&[]), ["a.wav"]);
+        assert_eq!(hound::WavReader::open(batch.output("a.wav")).unwrap().spec().sample_rate, 16000);
+        // options given on the command line count as well
+        assert_eq!(batch.run(&["--channels", "1"]), ["a.wav", "b.wav"]);
+    }
+
+    #[test]
+    fn transcodes_outputs_whose_input_changed() {
+        let batch = Batch::new(22050);
+        batch.run(&[]);
+        test_support::write_sine_wav(&batch.dir.path().join("in.wav"), 44100, 8820);
+        assert_eq!(batch.run(&[]), ["a.wav", "b.wav"]);
+        assert_eq!(hound::WavReader::open(batch.output("b.wav")).unwrap().duration(), 8820);
+    }
+
+    #[test]
+    fn transcodes_deleted_outputs() {
+        let batch = Batch::new(22050);
+        batch.run(&[]);
+        std::fs::remove_file(batch.output("b.wav")).unwrap();
+        assert_eq!(batch.run(&[]), ["b.wav"]);
+    }
+
+    #[test]
+    fn records_failed_jobs_and_fails_the_run() {
+        let batch = Batch::new(22050);
+        std::fs::write(batch.dir.path().join("in.wav"), b"RIFF\0\0\0\0WAVEjunk").unwrap();
+        let result = run_job_file(&run_args(&batch.job_path, &[]), None, CancellationToken::new());
+        assert!(matches!(result, Err(TranscoderError::Job(_))), "{:?}", result);
+        let manifest = std::fs::read_to_string(batch.job_path.with_extension("manifest.jsonl")).unwrap();
+        assert_eq!(manifest.matches("\"status\":\"failed\"").count(), 2);
+    }
+
+    #[test]
+    fn times_out_jobs_rather_than_the_run() {
+        let batch = Batch::new(22050);
+        let second_input = batch.dir.path().join("in2.wav");
+        test_support::write_sine_wav(&second_input, 44100, 4410);
+        let content = "[[jobs]]\ninput = \"in.wav\"\noutputs = [{ path = \"a.wav\" }]\n[[jobs]]\ninput = \"in2.wav\"\noutputs = [{ path = \"b.wav\" }]\n";
+        std::fs::write(&batch.job_path, content).unwrap();
+
+        // every job times out at once, but each is recorded as failed instead of ending the run
+        let result = run_job_file(&run_args(&batch.job_path, &["--timeout", "0"]), None, CancellationToken::new());
+        assert!(matches!(result, Err(TranscoderError::Job(ref message)) if message.contains("2 of 2")), "{:?}", result);
+        let manifest = std::fs::read_to_string(batch.job_path.with_extension("manifest.jsonl")).unwrap();
+        assert_eq!(manifest.matches("\"status\":\"failed\"").count(), 2);
+        assert!(manifest.contains("timed out"), "{}", manifest);
+
+        // a cancelled run stops before the next job
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        let result = run_job_file(&run_args(&batch.job_path, &["--force"]), None, cancellation);
+        assert!(matches!(result, Err(TranscoderError::Cancelled(_))), "{:?}", result);
+    }
+}
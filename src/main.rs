@@ -2,82 +2,71 @@ mod errors;
 mod utils;
 mod transcoders;
 mod audio_processor;
+mod codecs;
+mod format_detection;
+mod progress;
+mod presets;
+mod cancellation;
+mod cli;
+mod operations;
+mod jobs;
+mod manifest;
+mod sidecar;
+mod watch;
+mod serve;
+#[cfg(test)]
+mod test_support;
 
-use clap::{Parser, Subcommand};
-use core::num;
-use std::{path::{Path, PathBuf}, thread::Thread};
+use clap::Parser;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, error, warn, LevelFilter};
 use env_logger::{Builder, Target};
 use rayon::ThreadPoolBuilder;
-use num_cpus;
-
-#[derive(Parser, Debug)]
-#[clap(author, version, about = "An audio transcoder written in Rust", long_about = None)]
-struct CliArgs {
-    /// input audio file path
-    #[arg(short, long, value_name = "FILE")]
-    input: PathBuf,
-
-    /// output audio file path determined by the output file extension
-    #[arg(short, long, value_name = "FILE")]
-    output: PathBuf,
-
-    /// desired output audio codec
-    /// if not specified, ffmpeg will choose a default for the format
-    /// native transcoders will ignore this option
-    #[arg(long)]
-    codec: Option<String>,
-
-    /// desired output bitrate in kbps
-    /// primarily for lossy codecs; if not specified, ffmpeg will choose a default
-    /// lossless codecs will ignore this option
-    #[arg(long, value_name = "KBPS")]
-    bitrate: Option<u32>,
-
-    /// desired output sample rate in Hz
-    #[arg(long, value_name = "HZ")]
-    sample_rate: Option<u32>,
-
-    /// desired number of output audio channels
-    #[arg(long, value_name = "NUM")]
-    channels: Option<u8>,
-
-    /// quality preset for ffmpeg encoders
-    /// this is codec-specific and influences the encoding speed vs compression efficiency
-    /// this option only applies to the ffmpeg transcoder
-    #[arg(long)]
-    quality_preset: Option<String>,
-
-    /// number of threads ffmpeg should use for encoding
-    /// defaults to the number of logical CPU cores
-    /// applicable only to the fallback ffmpeg transcoder
-    #[arg(long, value_name = "NUM")]
-    threads: Option<usize>,
-
-    /// increasing verbosity of logging
-    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
-    verbose: u8,
-}
+use cli::{CliArgs, Command};
 
 fn main() -> Result<(), errors::TranscoderError> {
+    // parsing command line arguments
+    let cli = CliArgs::parse();
+
     // configuring logging based on level of verbosity
-    let log_level = match CliArgs::parse().verbose {
+    let log_level = match cli.verbose {
         0 => LevelFilter::Info,
         1 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
 
+    let transcode_args = match &cli.command {
+        Some(command) => command.transcode_args(),
+        None => &cli.transcode,
+    };
+
+    // showing a progress bar on interactive terminals only
+    let progress_bar = (!transcode_args.no_progress && std::io::stderr().is_terminal()).then(create_progress_bar);
+
+    // logs must not end up in the audio stream when writing to stdout
+    let log_to_stderr = cli.outputs.iter().any(|output| utils::is_stdio(&output.path))
+        || cli.command.as_ref().is_some_and(Command::writes_stdout);
+    let log_target = match &progress_bar {
+        // log records are printed above the progress bar instead of through it
+        Some(bar) => {
+            let stream: Box<dyn Write + Send> = if log_to_stderr { Box::new(std::io::stderr()) } else { Box::new(std::io::stdout()) };
+            Target::Pipe(Box::new(ProgressBarWriter { bar: bar.clone(), stream }))
+        }
+        None if log_to_stderr => Target::Stderr,
+        None => Target::Stdout,
+    };
+
     Builder::new()
         .filter_level(log_level)
-        .target(Target::Stdout)
+        .target(log_target)
         .init();
 
     info!("Audio transcoder application started");
 
-    // parsing command line arguments
-    let cli = CliArgs::parse();
-
-    let num_threads = cli.threads.unwrap_or_else(num_cpus::get);
+    let num_threads = transcode_args.threads.unwrap_or_else(num_cpus::get);
     if num_threads > 0 {
         match ThreadPoolBuilder::new().num_threads(num_threads).build_global() {
             Ok(_) => info!("Rayon thread pool configured with {} threads", num_threads),
@@ -88,40 +77,198 @@ fn main() -> Result<(), errors::TranscoderError> {
         warn!("Invalid number of threads specified ({}). Rayon will use default threading", num_threads)
     }
 
-    // validating input and output paths
-    if !cli.input.exists() {
+    let inputs = match &cli.command {
+        Some(Command::Split(args)) => vec![args.input.as_path()],
+        Some(Command::Concat(args)) => args.inputs.iter().map(PathBuf::as_path).collect(),
+        Some(Command::Mix(args)) => args.tracks.iter().map(|track| track.path.as_path()).collect(),
+        Some(Command::SplitChannels(args)) => vec![args.input.as_path()],
+        Some(Command::MergeChannels(args)) => args.inputs.iter().map(PathBuf::as_path).collect(),
+        // job files name their inputs themselves, which are checked when the file is loaded
+        Some(Command::Run(args)) => vec![args.job_file.as_path()],
+        // the watched folder is checked when the watch starts
+        Some(Command::Watch(_)) => Vec::new(),
+        // inputs are named by the requests
+        Some(Command::Serve(_)) => Vec::new(),
+        Some(Command::Verify(args)) => vec![args.input.as_path(), args.output.as_path()],
+        // clap requires both paths unless a subcommand is given
+        None => match cli.input.as_deref() {
+            Some(input) if !cli.outputs.is_empty() => vec![input],
+            _ => return Err(errors::TranscoderError::Argument("--input and --output are required".to_string())),
+        },
+    };
+
+    // validating input paths
+    for input in &inputs {
+        validate_input(input)?;
+    }
+
+    transcode_args.validate()?;
+    match &cli.command {
+        Some(Command::Concat(args)) => args.validate()?,
+        Some(Command::Mix(args)) => args.validate()?,
+        Some(Command::Watch(args)) => args.validate()?,
+        Some(Command::Serve(args)) => args.validate()?,
+        Some(Command::Verify(args)) => args.thresholds.validate()?,
+        None if cli.verify => cli.verify_thresholds.validate()?,
+        _ => {}
+    }
+
+    // the first Ctrl+C cancels the transcode cleanly, a second one exits immediately
+    let mut cancellation = cancellation::CancellationToken::new();
+    let handler_token = cancellation.clone();
+    let interrupted = std::sync::atomic::AtomicBool::new(false);
+    if let Err(e) = ctrlc::set_handler(move || {
+        if interrupted.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        handler_token.cancel();
+    }) {
+        warn!("Failed to install the Ctrl+C handler: {}", e);
+    }
+    // a watch or server runs until interrupted and a job file holds many jobs, they apply --timeout to every job instead
+    let long_running = matches!(cli.command, Some(Command::Run(_) | Command::Watch(_) | Command::Serve(_)));
+    if let (Some(timeout), false) = (transcode_args.timeout, long_running) {
+        cancellation = cancellation.with_timeout(Duration::from_secs(timeout));
+    }
+
+    let progress = progress_bar.clone().map(|bar| {
+        progress::ProgressCallback::new(move |progress| update_progress_bar(&bar, progress))
+    });
+    let options = |output: &Path| transcode_args.to_options(output, progress.clone(), cancellation.clone());
+    let result = match &cli.command {
+        Some(Command::Split(args)) => operations::split::split_on_silence(
+            &args.input, &args.output, &transcode_args.silence_settings(), &options(Path::new(&args.output))?,
+        )
+        .map(|parts| info!("Split {:?} into {} parts", args.input, parts.len())),
+        Some(Command::Concat(args)) => operations::concat::concatenate(
+            &args.inputs, &args.output, Duration::from_secs_f64(args.crossfade), &options(&args.output)?,
+        ),
+        Some(Command::Mix(args)) => operations::mix::mix(&args.tracks, &args.output, &args.duck_settings(), &options(&args.output)?),
+        Some(Command::SplitChannels(args)) => {
+            operations::channels::split_channels(&args.input, &args.output, &options(Path::new(&args.output))?)
+                .map(|paths| info!("Split {:?} into {} channels", args.input, paths.len()))
+        }
+        Some(Command::MergeChannels(args)) => {
+            operations::channels::merge_channels(&args.inputs, &args.output, args.layout, &options(&args.output)?)
+        }
+        Some(Command::Run(args)) => jobs::run_job_file(args, progress.clone(), cancellation.clone()),
+        Some(Command::Watch(args)) => watch::watch_folder(args, progress.clone(), cancellation.clone()),
+        Some(Command::Serve(args)) => serve::serve(args, cancellation.clone()),
+        Some(Command::Verify(args)) => {
+            operations::verify::verify(&args.input, &args.output, &args.thresholds.settings(), &options(&args.output)?)
+        }
+        None => {
+            let outputs = cli
+                .outputs
+                .iter()
+                .map(|output| Ok((output.path.clone(), output.to_options(transcode_args, progress.clone(), cancellation.clone())?)))
+                .collect::<Result<Vec<_>, errors::TranscoderError>>()?;
+            // options that keep the outputs from being verified are rejected before transcoding
+            if cli.verify {
+                for (path, options) in &outputs {
+                    operations::verify::check_verifiable(inputs[0], path, options)?;
+                }
+            }
+            let result = if cli.if_changed {
+                sidecar::transcode_if_changed(inputs[0], &outputs)
+            } else {
+                operations::fan_out::transcode_to_outputs(inputs[0], &outputs)
+            };
+            if cli.verify && result.is_ok() {
+                let settings = cli.verify_thresholds.settings();
+                outputs.iter().try_for_each(|(path, options)| {
+                    // the timeout limits every verification as it does every pass
+                    let options = transcoders::TranscodeOptions { cancellation: options.cancellation.restarted(), ..options.clone() };
+                    operations::verify::verify(inputs[0], path, &settings, &options)
+                })
+            } else {
+                result
+            }
+        }
+    };
+    if let Some(bar) = progress_bar {
+        bar.finish_and_clear();
+    }
+
+    let succeeded = match result {
+        Ok(_) => {
+            info!("Audio transcoding completed successfully!");
+            true
+        }
+        Err(e) => {
+            error!("Error during transcoding: {}", e);
+            false
+        }
+    };
+
+    info!("Audio transcoder application finished");
+    // scripts rely on the exit status to notice failed transcodes, jobs and verifications
+    if !succeeded {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+// checks that an input exists and is a file, unless it is stdin
+fn validate_input(input: &Path) -> Result<(), errors::TranscoderError> {
+    if utils::is_stdio(input) {
+        return Ok(());
+    }
+    if !input.exists() {
         return Err(errors::TranscoderError::Io(std::io::Error::new(
             std::io::ErrorKind::NotFound,
-            format!("Input file does not exist: {:?}", cli.input.display()),
+            format!("Input file does not exist: {:?}", input.display()),
         )));
     }
-    if !cli.input.is_file() {
+    if !input.is_file() {
         return Err(errors::TranscoderError::Io(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
-            format!("Input path is not a file: {:?}", cli.input.display()),
+            format!("Input path is not a file: {:?}", input.display()),
         )));
     }
+    Ok(())
+}
 
-    let output_extension = utils::get_file_extension(&cli.output)?;
-    if output_extension.is_empty() {
-        return Err(errors::TranscoderError::Path(format!("Output file path must have an extension: {}", cli.output.display())));
-    }
+// creates a progress bar counting per mille of the input duration
+fn create_progress_bar() -> ProgressBar {
+    let style = ProgressStyle::with_template("{spinner} [{bar:40}] {msg}")
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    ProgressBar::new(1000).with_style(style)
+}
 
-    let options = transcoders::TranscodeOptions {
-        output_format_extension: output_extension,
-        output_codec: cli.codec,
-        bitrate_kbps: cli.bitrate,
-        sample_rate: cli.sample_rate,
-        channels: cli.channels,
-        quality_preset: cli.quality_preset,
-        threads: cli.threads,
+// renders a progress report as bar position and percent, speed and ETA
+fn update_progress_bar(bar: &ProgressBar, progress: &progress::Progress) {
+    let mut message = match progress.percent() {
+        Some(percent) => {
+            bar.set_position((percent * 10.0) as u64);
+            format!("{:5.1}%", percent)
+        }
+        None => format!("{:.1}s", progress.position.as_secs_f64()),
     };
+    if let Some(speed) = progress.speed() {
+        message.push_str(&format!("  {:.1}x", speed));
+    }
+    if let Some(eta) = progress.eta() {
+        message.push_str(&format!("  ETA {}s", eta.as_secs()));
+    }
+    bar.set_message(message);
+    bar.tick();
+}
 
-    match transcoders::transcode_audio(&cli.input, &cli.output, &options) {
-        Ok(_) => info!("Audio transcoding completed successfully!"),
-        Err(e) => error!("Error during transcoding: {}", e),
+// hides the progress bar while writing to the underlying stream
+struct ProgressBarWriter {
+    bar: ProgressBar,
+    stream: Box<dyn Write + Send>,
+}
+
+impl Write for ProgressBarWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Self { bar, stream } = self;
+        bar.suspend(|| stream.write(buf))
     }
 
-    info!("Audio transcoder application finished");
-    Ok(())
-}
\ No newline at end of file
+    fn flush(&mut self) -> std::io::Result<()> {
+        let Self { bar, stream } = self;
+        bar.suspend(|| stream.flush())
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use crate::errors::TranscoderError;
+use crate::utils;
+
+/// the outcome of an output recorded in a manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryStatus {
+    Done,
+    Failed,
+}
+
+/// an output of a batch run as recorded in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// absolute output path, identifying the entry
+    pub output: PathBuf,
+    /// absolute input path
+    pub input: PathBuf,
+    pub input_hash: String,
+    /// size of the input when it was hashed
+    pub input_size: u64,
+    /// modification time of the input when it was hashed, in nanoseconds since the Unix epoch
+    pub input_modified: Option<u64>,
+    /// hash of the fingerprint of the transcode options the output depends on
+    pub options_hash: String,
+    pub status: EntryStatus,
+    pub error: Option<String>,
+}
+
+/// the content hash of an input along with the size and modification time it was computed for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputState {
+    pub hash: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+/// hashes the content of an input, reusing the hash of the `recorded` state if its size and modification time are unchanged
+pub fn input_state(input: &Path, recorded: Option<&InputState>) -> Result<InputState, TranscoderError> {
+    let metadata = fs::metadata(input)?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_nanos() as u64);
+    let hash = match recorded.filter(|state| modified.is_some() && state.size == size && state.modified == modified) {
+        Some(state) => state.hash.clone(),
+        None => {
+            debug!("Hashing {:?}", input);
+            utils::md5_file(input)?
+        }
+    };
+    Ok(InputState { hash, size, modified })
+}
+
+/// a persistent record of the outputs of batch runs, from which reruns skip outputs whose input and options are unchanged
+/// stored as JSON lines appended as soon as an output finishes, so that an interrupted run loses nothing; a later line
+/// replaces an earlier one for the same output
+pub struct Manifest {
+    entries: HashMap<PathBuf, ManifestEntry>,
+    // the state each input was last hashed in, sparing the hash of unchanged inputs
+    inputs: HashMap<PathBuf, InputState>,
+    file: File,
+}
+
+impl Manifest {
+    /// loads the manifest at `path`, creating it if it does not exist
+    pub fn open(path: &Path) -> Result<Self, TranscoderError> {
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<ManifestEntry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.output.clone(), entry);
+                    }
+                    // the last line may be incomplete if a run was killed while writing it
+                    Err(e) => warn!("{}:{}: ignoring an invalid manifest entry: {}", path.display(), index + 1, e),
+                }
+            }
+        }
+
+        // rewriting the latest entries keeps the manifest from growing with every run
+        let mut sorted: Vec<&ManifestEntry> = entries.values().collect();
+        sorted.sort_by(|a, b| a.output.cmp(&b.output));
+        let mut compacted = utils::create_temp_sibling(path)?;
+        for entry in sorted {
+            writeln!(compacted, "{}", serde_json::to_string(entry).map_err(io::Error::from)?)?;
+        }
+        compacted.persist(path).map_err(|e| e.error)?;
+        info!("Manifest {:?}: {} outputs recorded", path, entries.len());
+
+        let inputs = entries
+            .values()
+            .map(|entry| {
+                let state = InputState { hash: entry.input_hash.clone(), size: entry.input_size, modified: entry.input_modified };
+                (entry.input.clone(), state)
+            })
+            .collect();
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self { entries, inputs, file })
+    }
+
+    /// hashes the content of an input, reusing the recorded hash if its size and modification time are unchanged
+    pub fn input_state(&self, input: &Path) -> Result<InputState, TranscoderError> {
+        input_state(input, self.inputs.get(input))
+    }
+
+    /// whether `output` still exists and was written from an input and options with the same hashes
+    pub fn is_done(&self, output: &Path, input: &InputState, options_hash: &str) -> bool {
+        self.entries.get(output).is_some_and(|entry| {
+            entry.status == EntryStatus::Done && entry.input_hash == input.hash && entry.options_hash == options_hash
+        }) && output.exists()
+    }
+
+    /// updates the size and modification time recorded for the unchanged input of `output`, so that it is not hashed again
+    pub fn refresh(&mut self, output: &Path, input: &InputState) -> Result<(), TranscoderError> {
+        match self.entries.get(output) {
+            Some(entry) if entry.input_size != input.size || entry.input_modified != input.modified => {
+                let entry = ManifestEntry { input_size: input.size, input_modified: input.modified, ..entry.clone() };
+                self.record(entry)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// whether an earlier run recorded `output`
+    pub fn contains(&self, output: &Path) -> bool {
+        self.entries.contains_key(output)
+    }
+
+    /// records the outcome of an output, appending it to the manifest file right away
+    pub fn record(&mut self, entry: ManifestEntry) -> Result<(), TranscoderError> {
+        writeln!(self.file, "{}", serde_json::to_string(&entry).map_err(io::Error::from)?)?;
+        let state = InputState { hash: entry.input_hash.clone(), size: entry.input_size, modified: entry.input_modified };
+        self.inputs.insert(entry.input.clone(), state);
+        self.entries.insert(entry.output.clone(), entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(output: &str, input: &Path, state: &InputState, status: EntryStatus) -> ManifestEntry {
+        ManifestEntry {
+            output: PathBuf::from(output),
+            input: input.to_path_buf(),
+            input_hash: state.hash.clone(),
+            input_size: state.size,
+            input_modified: state.modified,
+            options_hash: "options".to_string(),
+            status,
+            error: None,
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn reuses_the_hash_of_an_unchanged_input() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("in.wav");
+        fs::write(&input, b"audio").unwrap();
+        let state = input_state(&input, None).unwrap();
+        assert_eq!(state.hash, utils::md5_hex(b"audio"));
+        assert_eq!(state.size, 5);
+
+        let recorded = InputState { hash: "recorded".to_string(), ..state.clone() };
+        assert_eq!(input_state(&input, Some(&recorded)).unwrap().hash, "recorded");
+        // a different size or modification time means the content has to be hashed again
+        let resized = InputState { size: 6, ..recorded.clone() };
+        assert_eq!(input_state(&input, Some(&resized)).unwrap().hash, state.hash);
+        let touched = InputState { modified: state.modified.map(|modified| modified - 1), ..recorded };
+        assert_eq!(input_state(&input, Some(&touched)).unwrap().hash, state.hash);
+    }
+
+    #[test]
+    fn compacts_the_file_when_opened() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.manifest.jsonl");
+        let input = dir.path().join("in.wav");
+        let state = InputState { hash: "a".to_string(), size: 1, modified: Some(1) };
+        {
+            let mut manifest = Manifest::open(&path).unwrap();
+            manifest.record(entry("/out/b.mp3", &input, &state, EntryStatus::Failed)).unwrap();
+            manifest.record(entry("/out/a.mp3", &input, &state, EntryStatus::Done)).unwrap();
+            manifest.record(entry("/out/b.mp3", &input, &state, EntryStatus::Done)).unwrap();
+        }
+        // a run killed while writing leaves an incomplete line behind
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"output\": \"/out/c").unwrap();
+        assert_eq!(lines(&path).len(), 4);
+
+        let manifest = Manifest::open(&path).unwrap();
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("/out/a.mp3") && lines[1].contains("/out/b.mp3"));
+        assert!(manifest.contains(Path::new("/out/b.mp3")));
+        assert!(!manifest.contains(Path::new("/out/c.mp3")));
+        assert_eq!(manifest.entries[Path::new("/out/b.mp3")].status, EntryStatus::Done);
+    }
+
+    #[test]
+    fn tells_whether_an_output_is_done() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("in.wav");
+        let output = dir.path().join("out.mp3");
+        fs::write(&output, b"mp3").unwrap();
+        let output_name = output.to_str().unwrap();
+        let state = InputState { hash: "a".to_string(), size: 1, modified: Some(1) };
+        let mut manifest = Manifest::open(&dir.path().join("manifest.jsonl")).unwrap();
+        assert!(!manifest.is_done(&output, &state, "options"));
+
+        manifest.record(entry(output_name, &input, &state, EntryStatus::Failed)).unwrap();
+        assert!(!manifest.is_done(&output, &state, "options"));
+        manifest.record(entry(output_name, &input, &state, EntryStatus::Done)).unwrap();
+        assert!(manifest.is_done(&output, &state, "options"));
+        assert!(!manifest.is_done(&output, &state, "other options"));
+        assert!(!manifest.is_done(&output, &InputState { hash: "b".to_string(), ..state.clone() }, "options"));
+        fs::remove_file(&output).unwrap();
+        assert!(!manifest.is_done(&output, &state, "options"));
+    }
+
+    #[test]
+    fn refreshes_the_state_of_a_touched_input() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.jsonl");
+        let input = dir.path().join("in.wav");
+        let state = InputState { hash: "a".to_string(), size: 1, modified: Some(1) };
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest.record(entry("/out/a.mp3", &input, &state, EntryStatus::Done)).unwrap();
+        manifest.refresh(Path::new("/out/a.mp3"), &state).unwrap();
+        assert_eq!(lines(&path).len(), 1);
+
+        let touched = InputState { modified: Some(2), ..state };
+        manifest.refresh(Path::new("/out/a.mp3"), &touched).unwrap();
+        assert_eq!(lines(&path).len(), 2);
+        assert_eq!(Manifest::open(&path).unwrap().inputs[&input], touched);
+    }
+}
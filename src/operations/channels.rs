@@ -0,0 +1,183 @@
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use crate::audio_processor::level::SoftLimiter;
+use crate::audio_processor::pipeline::{Pipeline, CHUNK_FRAMES};
+use crate::codecs::{channel_layout, StreamSpec};
+use crate::errors::TranscoderError;
+use crate::operations::{self, ConformedSource, OutputWriter};
+use crate::progress::ProgressTracker;
+use crate::transcoders::{self, TranscodeOptions};
+
+/// writes every channel of the input to a mono output named by `template`, in which `{label}` is replaced by the
+/// speaker of the channel (e.g. `FL`, `LFE`) and `{index}` by its 1-based number
+/// returns the paths of the written outputs
+pub fn split_channels(input_path: &Path, template: &str, options: &TranscodeOptions) -> Result<Vec<PathBuf>, TranscoderError> {
+    if options.normalize_peak_dbfs.is_some() || options.trim_silence.is_some() {
+        return Err(TranscoderError::Argument(
+            "--normalize-peak and --trim-silence are not supported when splitting channels, they would set the channels apart".to_string(),
+        ));
+    }
+    if options.channels.is_some_and(|channels| channels != 1) {
+        warn!("Ignoring the requested channel count, split channels are always mono");
+    }
+    let filters = transcoders::native_filters(options).ok_or_else(|| {
+        TranscoderError::Argument("Splitting channels supports only filter graphs with native equivalents".to_string())
+    })?;
+
+    let mut source = operations::open_source(input_path, options)?;
+    let input_spec = source.decoder.spec();
+    let channels = input_spec.channels as usize;
+    let mask = source.decoder.channel_mask().unwrap_or_else(|| channel_layout::default_mask(input_spec.channels));
+    let labels = channel_layout::labels(mask, input_spec.channels);
+    if channels == 1 {
+        warn!("{:?} has a single channel only", input_path);
+    }
+    info!("Splitting {:?} into {} channels: {}", input_path, channels, labels.join(", "));
+
+    let paths = labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| channel_path(template, index + 1, label))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mono_options = TranscodeOptions { channels: Some(1), channel_mask: None, ..options.clone() };
+    let mono_input = StreamSpec { channels: 1, ..input_spec };
+    let mono_output = OutputWriter::output_spec(&paths[0], &mono_input, &mono_options)?;
+
+    let tags = source.decoder.tags();
+    let mut writers = paths
+        .iter()
+        .map(|path| OutputWriter::create(path, &mono_input, &tags, &mono_options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut pipelines = (0..channels)
+        .map(|_| Pipeline::new(&mono_input, &mono_output, &filters, 1.0, options.soft_limit_dbfs.map(SoftLimiter::new), None))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut progress = ProgressTracker::new(options.progress.clone(), input_spec.sample_rate, input_spec.total_frames);
+    let mut frames_read: u64 = 0;
+    loop {
+        options.cancellation.check()?;
+
+        let chunk = source.decoder.read_frames(CHUNK_FRAMES)?;
+        if chunk.is_empty() { // EOF
+            break;
+        }
+        frames_read += (chunk.len() / channels) as u64;
+        for (channel, (writer, pipeline)) in writers.iter_mut().zip(&mut pipelines).enumerate() {
+            let samples = chunk.iter().skip(channel).step_by(channels).copied().collect();
+            writer.write_samples(&pipeline.process(samples)?)?;
+        }
+        progress.update(frames_read);
+    }
+
+    for (writer, pipeline) in writers.iter_mut().zip(&mut pipelines) {
+        writer.write_samples(&pipeline.flush()?)?;
+    }
+    for (writer, path) in writers.into_iter().zip(&paths) {
+        writer.finish()?;
+        info!("Wrote {:?}", path);
+    }
+    progress.finish(frames_read);
+
+    Ok(paths)
+}
+
+/// interleaves the inputs into one output with a channel per input, in order, assigned to the speakers of `channel_mask`
+/// (the default layout for the number of inputs if None); inputs with several channels are mixed down to mono and
+/// shorter inputs are padded with silence
+pub fn merge_channels(
+    input_paths: &[PathBuf],
+    output_path: &Path,
+    channel_mask: Option<u32>,
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument("--normalize-peak is not supported when merging channels".to_string()));
+    }
+    let channels = u8::try_from(input_paths.len())
+        .map_err(|_| TranscoderError::Argument(format!("Cannot merge {} inputs, at most 255 channels are supported", input_paths.len())))?;
+    if options.channels.is_some_and(|requested| requested != channels) {
+        return Err(TranscoderError::Argument(format!("Merging {} inputs always yields {} channels", channels, channels)));
+    }
+    let mask = channel_mask.unwrap_or_else(|| channel_layout::default_mask(channels));
+    if mask != 0 && mask.count_ones() != channels as u32 {
+        return Err(TranscoderError::Argument(format!(
+            "Channel layout {:#x} assigns {} speakers, but {} inputs are merged", mask, mask.count_ones(), channels
+        )));
+    }
+    let filters = transcoders::native_filters(options).ok_or_else(|| {
+        TranscoderError::Argument("Merging channels supports only filter graphs with native equivalents".to_string())
+    })?;
+
+    let sources = input_paths
+        .iter()
+        .map(|path| operations::open_source(path, options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let specs: Vec<StreamSpec> = sources.iter().map(|source| source.decoder.spec()).collect();
+    for (path, spec) in input_paths.iter().zip(&specs).filter(|(_, spec)| spec.channels != 1) {
+        warn!("{:?} has {} channels, mixing it down to mono", path, spec.channels);
+    }
+
+    let merged = StreamSpec { channels, ..operations::common_spec(&specs) };
+    let merge_options = TranscodeOptions { channel_mask: (mask != 0).then_some(mask), ..options.clone() };
+    let spec = OutputWriter::output_spec(output_path, &merged, &merge_options)?;
+    let mono = StreamSpec { channels: 1, ..spec };
+    info!("Merging {} inputs to {:?} as {}", channels, output_path, channel_layout::labels(mask, channels).join(", "));
+
+    let total_frames = specs
+        .iter()
+        .map(|input| input.total_frames.map(|frames| frames * spec.sample_rate as u64 / input.sample_rate as u64))
+        .try_fold(0, |longest, frames| frames.map(|frames| u64::max(longest, frames)));
+    let mut progress = ProgressTracker::new(options.progress.clone(), spec.sample_rate, total_frames);
+
+    let tags = sources[0].decoder.tags();
+    let mut inputs = sources
+        .into_iter()
+        .zip(&specs)
+        .map(|(source, input)| Ok(ConformedSource::new(source, Pipeline::new(input, &mono, &[], 1.0, None, None)?)))
+        .collect::<Result<Vec<_>, TranscoderError>>()?;
+    let mut writer = OutputWriter::create(output_path, &merged, &tags, &merge_options)?;
+    let mut post_processing = Pipeline::new(
+        &spec, &spec, &filters, 1.0, options.soft_limit_dbfs.map(SoftLimiter::new), options.trim_silence.as_ref(),
+    )?;
+
+    let mut frames_merged: u64 = 0;
+    while !inputs.iter().all(ConformedSource::is_done) {
+        options.cancellation.check()?;
+
+        let parts = inputs
+            .iter_mut()
+            .map(|input| input.read(CHUNK_FRAMES))
+            .collect::<Result<Vec<_>, _>>()?;
+        let frames = parts.iter().map(Vec::len).max().unwrap_or_default();
+
+        let mut interleaved = vec![0.0; frames * channels as usize];
+        for (channel, part) in parts.iter().enumerate() {
+            for (frame, sample) in part.iter().enumerate() {
+                interleaved[frame * channels as usize + channel] = *sample;
+            }
+        }
+
+        writer.write_samples(&post_processing.process(interleaved)?)?;
+        frames_merged += frames as u64;
+        progress.update(frames_merged);
+    }
+
+    writer.write_samples(&post_processing.flush()?)?;
+    writer.finish()?;
+    progress.finish(frames_merged);
+
+    info!("Wrote {:?}", output_path);
+    Ok(())
+}
+
+// expands `{label}` and `{index}` in the output template of a split channel
+fn channel_path(template: &str, index: usize, label: &str) -> Result<PathBuf, TranscoderError> {
+    let labelled = template.replace("{label}", label);
+    if labelled.contains("{index") {
+        return operations::numbered_path(&labelled, index);
+    }
+    if labelled == template {
+        return Err(TranscoderError::Argument(format!("Output template {:?} must contain {{label}} or {{index}}", template)));
+    }
+    Ok(PathBuf::from(labelled))
+}
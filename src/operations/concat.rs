@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+use crate::audio_processor::crossfade::Crossfader;
+use crate::audio_processor::level::SoftLimiter;
+use crate::audio_processor::pipeline::Pipeline;
+use crate::codecs::StreamSpec;
+use crate::errors::TranscoderError;
+use crate::operations::{self, OutputWriter};
+use crate::progress::ProgressTracker;
+use crate::transcoders::{self, native_transcoder, TranscodeOptions};
+
+/// joins the inputs in order into a single output, overlapping consecutive inputs by `crossfade`
+/// every input is resampled and mixed to the highest sample rate and channel count among them, unless the options
+/// request a specific format; filters and level processing apply to the joined audio
+pub fn concatenate(
+    input_paths: &[PathBuf],
+    output_path: &Path,
+    crossfade: Duration,
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument("--normalize-peak is not supported when concatenating".to_string()));
+    }
+    let filters = transcoders::native_filters(options).ok_or_else(|| {
+        TranscoderError::Argument("Concatenating supports only filter graphs with native equivalents".to_string())
+    })?;
+
+    let mut sources = input_paths
+        .iter()
+        .map(|path| operations::open_source(path, options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let specs: Vec<StreamSpec> = sources.iter().map(|source| source.decoder.spec()).collect();
+    let common = operations::common_spec(&specs);
+    let spec = OutputWriter::output_spec(output_path, &common, options)?;
+    info!("Concatenating {} inputs to {:?} at {} Hz with {} channels", sources.len(), output_path, spec.sample_rate, spec.channels);
+
+    // progress is counted in output frames, since the inputs may differ in sample rate
+    let to_output_frames = |frames: u64, input: &StreamSpec| frames * spec.sample_rate as u64 / input.sample_rate as u64;
+    let total_frames = specs
+        .iter()
+        .map(|input| input.total_frames.map(|frames| to_output_frames(frames, input)))
+        .sum::<Option<u64>>();
+    let mut progress = ProgressTracker::new(options.progress.clone(), spec.sample_rate, total_frames);
+
+    let mut writer = OutputWriter::create(output_path, &common, &sources[0].decoder.tags(), options)?;
+    let mut post_processing = Pipeline::new(
+        &spec, &spec, &filters, 1.0, options.soft_limit_dbfs.map(SoftLimiter::new), options.trim_silence.as_ref(),
+    )?;
+    let fade_samples = (crossfade.as_secs_f64() * spec.sample_rate as f64) as usize * spec.channels as usize;
+    let mut crossfader = Crossfader::new(fade_samples, spec.channels);
+
+    let mut frames_written: u64 = 0;
+    for (index, (source, input)) in sources.iter_mut().zip(&specs).enumerate() {
+        info!("Appending {:?} ({} Hz, {} channels)", input_paths[index], input.sample_rate, input.channels);
+        if index > 0 {
+            writer.write_samples(&post_processing.process(crossfader.start_part())?)?;
+        }
+
+        let mut conform = Pipeline::new(input, &spec, &[], 1.0, None, None)?;
+        let frames_read = native_transcoder::run_pipeline(source.decoder.as_mut(), &mut conform, options, |samples, frames_read| {
+            writer.write_samples(&post_processing.process(crossfader.push(samples))?)?;
+            progress.update(frames_written + to_output_frames(frames_read, input));
+            Ok(())
+        })?;
+        frames_written += to_output_frames(frames_read, input);
+    }
+
+    writer.write_samples(&post_processing.process(crossfader.finish())?)?;
+    writer.write_samples(&post_processing.flush()?)?;
+    writer.finish()?;
+    progress.finish(frames_written);
+
+    info!("Wrote {:?}", output_path);
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use log::info;
+use crate::audio_processor::level::SoftLimiter;
+use crate::audio_processor::pipeline::{Pipeline, CHUNK_FRAMES};
+use crate::errors::TranscoderError;
+use crate::format_detection::{canonical_format, detect_input_format};
+use crate::operations::{self, OutputWriter};
+use crate::progress::ProgressTracker;
+use crate::transcoders::{self, native_transcoder, TranscodeOptions};
+use crate::transcoders::ffmpeg_transcoder::FfmpegEncoder;
+use crate::utils;
+
+// an output fed from the shared decode, with its own filters and encoder
+struct Branch<'a> {
+    path: &'a Path,
+    pipeline: Pipeline,
+    writer: OutputWriter,
+}
+
+/// transcodes one input to several outputs, each with its own options, decoding the input only once
+/// inputs without a native decoder are decoded once by ffmpeg; outputs without a native encoder share a single ffmpeg
+/// process fed with the decoded input, which also runs their filters, unless they trim silence; outputs that need a pass
+/// of their own (`--normalize-peak`, or filter graphs only ffmpeg can run ahead of a native encoder or trimming) are
+/// transcoded separately afterwards
+/// the options describing the input (`--input-format`, `--raw-*`, `--ffmpeg-path`) have to be the same for all outputs
+pub fn transcode_to_outputs(input_path: &Path, outputs: &[(PathBuf, TranscodeOptions)]) -> Result<(), TranscoderError> {
+    if let [(output_path, options)] = outputs {
+        return transcoders::transcode_audio(input_path, output_path, options);
+    }
+
+    for (index, (output_path, _)) in outputs.iter().enumerate() {
+        if outputs[..index].iter().any(|(other, _)| other == output_path) {
+            return Err(TranscoderError::Argument(format!("{:?} is given as an output more than once", output_path)));
+        }
+    }
+
+    check_input_options(input_path, outputs)?;
+
+    let (shared, separate): (Vec<_>, Vec<_>) = outputs.iter().partition(|(path, options)| {
+        options.normalize_peak_dbfs.is_none() && (encoded_by_ffmpeg(path, options) || transcoders::native_filters(options).is_some())
+    });
+    let passes = separate.len() + usize::from(!shared.is_empty());
+    if utils::is_stdio(input_path) && passes > 1 {
+        return Err(TranscoderError::Argument(
+            "Outputs with --normalize-peak or FFmpeg filter graphs need a pass of their own, which stdin cannot provide".to_string(),
+        ));
+    }
+
+    // --timeout limits every pass over the input rather than all of them
+    let restarted = |outputs: &[&(PathBuf, TranscodeOptions)]| -> Vec<(PathBuf, TranscodeOptions)> {
+        outputs
+            .iter()
+            .map(|(path, options)| (path.clone(), TranscodeOptions { cancellation: options.cancellation.restarted(), ..options.clone() }))
+            .collect()
+    };
+    match restarted(&shared).as_slice() {
+        [] => {}
+        [(output_path, options)] => transcoders::transcode_audio(input_path, output_path, options)?,
+        shared => decode_once(input_path, shared)?,
+    }
+    for (output_path, options) in restarted(&separate) {
+        info!("Transcoding {:?} in a pass of its own", output_path);
+        transcoders::transcode_audio(input_path, &output_path, &options)?;
+    }
+    Ok(())
+}
+
+// the input is decoded once, so the options telling how to read it must not differ between outputs
+fn check_input_options(input_path: &Path, outputs: &[(PathBuf, TranscodeOptions)]) -> Result<(), TranscoderError> {
+    let (first_path, first) = &outputs[0];
+    // the raw layout only describes the input when it is headerless PCM
+    let raw_input = detect_input_format(input_path, first.input_format.as_deref()).is_ok_and(|format| format.format == "raw");
+    let input_format = |options: &TranscodeOptions| options.input_format.as_deref().map(canonical_format);
+    for (path, options) in &outputs[1..] {
+        let differing = if input_format(options) != input_format(first) {
+            Some("--input-format")
+        } else if raw_input && options.raw_format != first.raw_format {
+            Some("--raw-format")
+        } else if raw_input && options.raw_sample_rate != first.raw_sample_rate {
+            Some("--raw-rate")
+        } else if raw_input && options.raw_channels != first.raw_channels {
+            Some("--raw-channels")
+        } else if options.ffmpeg_path != first.ffmpeg_path {
+            Some("--ffmpeg-path")
+        } else {
+            None
+        };
+        if let Some(option) = differing {
+            return Err(TranscoderError::Argument(format!(
+                "{:?} and {:?} read the same input, but set {} differently", first_path, path, option
+            )));
+        }
+    }
+    Ok(())
+}
+
+// whether ffmpeg encodes the output from the decoded samples as they are, applying its filters itself
+// silence is trimmed natively, so outputs trimming it run through a pipeline of their own even if ffmpeg encodes them
+fn encoded_by_ffmpeg(output_path: &Path, options: &TranscodeOptions) -> bool {
+    transcoders::native_output_codec(output_path, options).is_none() && options.trim_silence.is_none()
+}
+
+// decodes the input once and hands every chunk to the pipeline and encoder of each branched output, and the decoded
+// samples as they are to the ffmpeg process encoding all other outputs
+fn decode_once(input_path: &Path, outputs: &[(PathBuf, TranscodeOptions)]) -> Result<(), TranscoderError> {
+    let decode_options = &outputs[0].1;
+    let mut source = operations::open_source(input_path, decode_options)?;
+    let input_spec = source.decoder.spec();
+    let channels = input_spec.channels as usize;
+    let tags = source.decoder.tags();
+    info!("Decoding {:?} once for {} outputs", input_path, outputs.len());
+
+    let (branched, streamed): (Vec<_>, Vec<_>) = outputs.iter().partition(|(path, options)| !encoded_by_ffmpeg(path, options));
+    let mut branches = branched
+        .into_iter()
+        .map(|(path, options)| {
+            let filters = transcoders::native_filters(options).unwrap_or_default();
+            let resampled_options = native_transcoder::resampled_options(&filters, options);
+            let options = resampled_options.as_ref().unwrap_or(options);
+            let output_spec = OutputWriter::output_spec(path, &input_spec, options)?;
+            let pipeline = Pipeline::new(
+                &input_spec, &output_spec, &filters, 1.0, options.soft_limit_dbfs.map(SoftLimiter::new), options.trim_silence.as_ref(),
+            )?;
+            let writer = OutputWriter::create(path, &input_spec, &tags, options)?;
+            Ok(Branch { path, pipeline, writer })
+        })
+        .collect::<Result<Vec<_>, TranscoderError>>()?;
+    let mut ffmpeg = match streamed.as_slice() {
+        [] => None,
+        encoded => {
+            let encoded: Vec<(&Path, &TranscodeOptions)> = encoded.iter().map(|(path, options)| (path.as_path(), options)).collect();
+            info!("Encoding {:?} with a single FFmpeg process", encoded.iter().map(|(path, _)| path).collect::<Vec<_>>());
+            Some(FfmpegEncoder::spawn(&input_spec, &tags, &encoded)?)
+        }
+    };
+
+    let mut progress = ProgressTracker::new(decode_options.progress.clone(), input_spec.sample_rate, input_spec.total_frames);
+    let mut frames_read: u64 = 0;
+    loop {
+        decode_options.cancellation.check()?;
+
+        let chunk = source.decoder.read_frames(CHUNK_FRAMES)?;
+        if chunk.is_empty() { // EOF
+            break;
+        }
+        frames_read += (chunk.len() / channels) as u64;
+        if let Some(ffmpeg) = &mut ffmpeg {
+            ffmpeg.write_samples(&chunk)?;
+        }
+        for branch in &mut branches {
+            branch.writer.write_samples(&branch.pipeline.process(chunk.clone())?)?;
+        }
+        progress.update(frames_read);
+    }
+
+    for branch in &mut branches {
+        branch.writer.write_samples(&branch.pipeline.flush()?)?;
+    }
+    for branch in branches {
+        branch.writer.finish()?;
+        info!("Wrote {:?}", branch.path);
+    }
+    if let Some(ffmpeg) = ffmpeg {
+        ffmpeg.finish()?;
+    }
+    progress.finish(frames_read);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use crate::codecs::raw::RawPcmFormat;
+    use crate::test_support;
+
+    fn wav_output(dir: &TempDir, name: &str, options: TranscodeOptions) -> (PathBuf, TranscodeOptions) {
+        (dir.path().join(name), TranscodeOptions { output_format_extension: "wav".to_string(), ..options })
+    }
+
+    fn raw_options(sample_rate: u32) -> TranscodeOptions {
+        TranscodeOptions {
+            raw_format: Some(RawPcmFormat::S16le),
+            raw_sample_rate: Some(sample_rate),
+            raw_channels: Some(2),
+            ..TranscodeOptions::default()
+        }
+    }
+
+    fn assert_rejected(input: &Path, outputs: &[(PathBuf, TranscodeOptions)], option: &str) {
+        match transcode_to_outputs(input, outputs) {
+            Err(TranscoderError::Argument(message)) => assert!(message.contains(option), "{}", message),
+            other => panic!("expected {} to be rejected, got {:?}", option, other),
+        }
+        assert!(outputs.iter().all(|(path, _)| !path.exists()));
+    }
+
+    #[test]
+    fn accepts_aliases_of_the_same_input_format() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input.wav");
+        test_support::write_sine_wav(&input, 44100, 4410);
+        let outputs = [
+            wav_output(&dir, "a.wav", TranscodeOptions { input_format: Some("wav".to_string()), ..TranscodeOptions::default() }),
+            wav_output(&dir, "b.wav", TranscodeOptions { input_format: Some("WAVE".to_string()), ..TranscodeOptions::default() }),
+        ];
+        transcode_to_outputs(&input, &outputs).unwrap();
+        assert!(outputs.iter().all(|(path, _)| path.exists()));
+    }
+
+    #[test]
+    fn rejects_outputs_reading_the_input_as_different_formats() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input.wav");
+        test_support::write_sine_wav(&input, 44100, 4410);
+        let outputs = [
+            wav_output(&dir, "a.wav", TranscodeOptions::default()),
+            wav_output(&dir, "b.wav", TranscodeOptions { input_format: Some("raw".to_string()), ..raw_options(44100) }),
+        ];
+        assert_rejected(&input, &outputs, "--input-format");
+    }
+
+    #[test]
+    fn rejects_differing_raw_layouts_of_raw_input() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input.raw");
+        std::fs::write(&input, vec![0u8; 4 * 4410]).unwrap();
+        let outputs = [wav_output(&dir, "a.wav", raw_options(44100)), wav_output(&dir, "b.wav", raw_options(48000))];
+        assert_rejected(&input, &outputs, "--raw-rate");
+
+        let channels = TranscodeOptions { raw_channels: Some(1), ..raw_options(44100) };
+        let outputs = [wav_output(&dir, "a.wav", raw_options(44100)), wav_output(&dir, "b.wav", channels)];
+        assert_rejected(&input, &outputs, "--raw-channels");
+
+        let outputs = [wav_output(&dir, "a.wav", raw_options(44100)), wav_output(&dir, "b.wav", raw_options(44100))];
+        transcode_to_outputs(&input, &outputs).unwrap();
+    }
+
+    #[test]
+    fn ignores_raw_layouts_of_input_with_a_header() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input.wav");
+        test_support::write_sine_wav(&input, 44100, 4410);
+        let outputs = [wav_output(&dir, "a.wav", raw_options(44100)), wav_output(&dir, "b.wav", TranscodeOptions::default())];
+        transcode_to_outputs(&input, &outputs).unwrap();
+        assert!(outputs.iter().all(|(path, _)| path.exists()));
+    }
+
+    #[test]
+    fn rejects_differing_ffmpeg_binaries() {
+        let dir = TempDir::new().unwrap();
+        let input = dir.path().join("input.wav");
+        test_support::write_sine_wav(&input, 44100, 4410);
+        let other_ffmpeg = TranscodeOptions { ffmpeg_path: Some(PathBuf::from("/opt/ffmpeg/bin/ffmpeg")), ..TranscodeOptions::default() };
+        let outputs = [wav_output(&dir, "a.wav", TranscodeOptions::default()), wav_output(&dir, "b.wav", other_ffmpeg)];
+        assert_rejected(&input, &outputs, "--ffmpeg-path");
+    }
+}
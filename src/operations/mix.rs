@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{info, warn};
+use crate::audio_processor::ducker::{DuckSettings, Ducker};
+use crate::audio_processor::level::{self, SoftLimiter};
+use crate::audio_processor::pipeline::{Pipeline, CHUNK_FRAMES};
+use crate::codecs::StreamSpec;
+use crate::errors::TranscoderError;
+use crate::operations::{self, ConformedSource, OutputWriter};
+use crate::progress::ProgressTracker;
+use crate::transcoders::{self, TranscodeOptions};
+
+// ceiling of the limiter guarding the sum of the tracks unless --soft-limit sets another one
+const DEFAULT_LIMIT_DBFS: f32 = -1.0;
+
+/// an input of a mix and how it is placed in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixTrack {
+    pub path: PathBuf,
+    /// gain in dB applied to the track before summing
+    pub gain_db: f32,
+    /// silence before the track starts
+    pub offset: Duration,
+    /// lower the track while the tracks that are not ducked are heard
+    pub duck: bool,
+}
+
+impl MixTrack {
+    /// parses `FILE[,gain=DB][,offset=SECONDS][,duck]`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut track = Self { path: PathBuf::new(), gain_db: 0.0, offset: Duration::ZERO, duck: false };
+
+        // settings are taken from the end, so the file name itself may contain commas
+        let mut parts: Vec<&str> = value.split(',').collect();
+        while parts.len() > 1 {
+            let setting = parts[parts.len() - 1];
+            if setting == "duck" {
+                track.duck = true;
+            } else if let Some(gain) = setting.strip_prefix("gain=") {
+                track.gain_db = gain.trim_end_matches("dB").parse().ok().filter(|gain: &f32| gain.is_finite())
+                    .ok_or_else(|| format!("invalid gain {:?}, expected a number of dB", gain))?;
+            } else if let Some(offset) = setting.strip_prefix("offset=") {
+                let seconds = offset.parse().ok().filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)
+                    .ok_or_else(|| format!("invalid offset {:?}, expected a non-negative number of seconds", offset))?;
+                track.offset = Duration::from_secs_f64(seconds);
+            } else {
+                break;
+            }
+            parts.pop();
+        }
+
+        track.path = PathBuf::from(parts.join(","));
+        if track.path.as_os_str().is_empty() {
+            return Err("missing input file".to_string());
+        }
+        Ok(track)
+    }
+}
+
+/// sums the tracks into a single output, each conformed to the highest sample rate and channel count among them
+/// unless the options request a specific format; tracks marked for ducking are lowered while any other track is heard,
+/// and the sum passes the filters and a soft limiter before it is written
+pub fn mix(
+    tracks: &[MixTrack],
+    output_path: &Path,
+    ducking: &DuckSettings,
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument("--normalize-peak is not supported when mixing".to_string()));
+    }
+    let filters = transcoders::native_filters(options).ok_or_else(|| {
+        TranscoderError::Argument("Mixing supports only filter graphs with native equivalents".to_string())
+    })?;
+
+    let sources = tracks
+        .iter()
+        .map(|track| operations::open_source(&track.path, options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let specs: Vec<StreamSpec> = sources.iter().map(|source| source.decoder.spec()).collect();
+    let common = operations::common_spec(&specs);
+    let spec = OutputWriter::output_spec(output_path, &common, options)?;
+    info!("Mixing {} tracks to {:?} at {} Hz with {} channels", tracks.len(), output_path, spec.sample_rate, spec.channels);
+
+    let mut ducker = match (tracks.iter().any(|track| track.duck), tracks.iter().all(|track| track.duck)) {
+        (true, false) => Some(Ducker::new(ducking, spec.sample_rate)),
+        (true, true) => {
+            warn!("Every track is marked for ducking, so there is nothing to duck under");
+            None
+        }
+        _ => None,
+    };
+
+    let channels = spec.channels as usize;
+    let mut players = Vec::with_capacity(tracks.len());
+    let mut total_frames = Some(0);
+    for ((track, source), input) in tracks.iter().zip(sources).zip(&specs) {
+        info!("Track {:?}: {:+} dB, starting at {:.2}s{}", track.path, track.gain_db, track.offset.as_secs_f64(), if track.duck { ", ducked" } else { "" });
+        let delay_frames = (track.offset.as_secs_f64() * spec.sample_rate as f64) as u64;
+        let frames = input.total_frames.map(|frames| delay_frames + frames * spec.sample_rate as u64 / input.sample_rate as u64);
+        total_frames = total_frames.zip(frames).map(|(total, frames)| u64::max(total, frames));
+        players.push(TrackPlayer {
+            input: ConformedSource::new(source, Pipeline::new(input, &spec, &[], 1.0, None, None)?),
+            gain: level::db_to_linear(track.gain_db),
+            duck: track.duck,
+            delay: delay_frames as usize * channels,
+        });
+    }
+
+    let mut writer = OutputWriter::create(output_path, &common, &players[0].input.source.decoder.tags(), options)?;
+    let limiter = SoftLimiter::new(options.soft_limit_dbfs.unwrap_or(DEFAULT_LIMIT_DBFS));
+    let mut post_processing = Pipeline::new(&spec, &spec, &filters, 1.0, Some(limiter), options.trim_silence.as_ref())?;
+    let mut progress = ProgressTracker::new(options.progress.clone(), spec.sample_rate, total_frames);
+
+    let mut frames_mixed: u64 = 0;
+    while !players.iter().all(TrackPlayer::is_done) {
+        options.cancellation.check()?;
+
+        let parts = players
+            .iter_mut()
+            .map(|player| player.read(CHUNK_FRAMES * channels))
+            .collect::<Result<Vec<_>, _>>()?;
+        let length = parts.iter().map(Vec::len).max().unwrap_or_default();
+
+        // the tracks that are not ducked form the key signal the ducked ones are lowered under
+        let mut mixed = vec![0.0; length];
+        let mut ducked = vec![0.0; length];
+        for (player, part) in players.iter().zip(&parts) {
+            let sum = if player.duck { &mut ducked } else { &mut mixed };
+            sum.iter_mut().zip(part).for_each(|(sum, sample)| *sum += sample * player.gain);
+        }
+        if let Some(ducker) = &mut ducker {
+            ducker.process(&mixed, &mut ducked, spec.channels);
+        }
+        mixed.iter_mut().zip(&ducked).for_each(|(sum, sample)| *sum += sample);
+
+        writer.write_samples(&post_processing.process(mixed)?)?;
+        frames_mixed += (length / channels) as u64;
+        progress.update(frames_mixed);
+    }
+
+    writer.write_samples(&post_processing.flush()?)?;
+    writer.finish()?;
+    progress.finish(frames_mixed);
+
+    info!("Wrote {:?}", output_path);
+    Ok(())
+}
+
+// a track conformed to the output format, delayed by its offset
+struct TrackPlayer {
+    input: ConformedSource,
+    gain: f32,
+    duck: bool,
+    // samples of silence left before the track starts
+    delay: usize,
+}
+
+impl TrackPlayer {
+    // returns the next `samples` samples, or fewer at the end of the track
+    fn read(&mut self, samples: usize) -> Result<Vec<f32>, TranscoderError> {
+        let silence = self.delay.min(samples);
+        self.delay -= silence;
+        let mut output = vec![0.0; silence];
+        output.extend(self.input.read(samples - silence)?);
+        Ok(output)
+    }
+
+    fn is_done(&self) -> bool {
+        self.delay == 0 && self.input.is_done()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tracks() {
+        let track = MixTrack::parse("music.wav").unwrap();
+        assert_eq!(track, MixTrack { path: PathBuf::from("music.wav"), gain_db: 0.0, offset: Duration::ZERO, duck: false });
+
+        let track = MixTrack::parse("voice.flac,gain=-6dB,offset=1.5,duck").unwrap();
+        assert_eq!(track, MixTrack { path: PathBuf::from("voice.flac"), gain_db: -6.0, offset: Duration::from_millis(1500), duck: true });
+    }
+
+    #[test]
+    fn takes_settings_in_any_order() {
+        let expected = MixTrack { path: PathBuf::from("bed.wav"), gain_db: 3.0, offset: Duration::from_secs(2), duck: true };
+        for value in ["bed.wav,gain=3,offset=2,duck", "bed.wav,duck,offset=2,gain=3", "bed.wav,offset=2,duck,gain=3dB"] {
+            assert_eq!(MixTrack::parse(value).unwrap(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn keeps_commas_in_track_names() {
+        let track = MixTrack::parse("live, take 2.wav,gain=-3").unwrap();
+        assert_eq!((track.path, track.gain_db), (PathBuf::from("live, take 2.wav"), -3.0));
+        // settings are only taken from the end
+        let track = MixTrack::parse("duck,offset=1,b.wav").unwrap();
+        assert_eq!(track.path, PathBuf::from("duck,offset=1,b.wav"));
+        assert!(!track.duck);
+    }
+
+    #[test]
+    fn rejects_invalid_tracks() {
+        assert!(MixTrack::parse("").is_err());
+        assert!(MixTrack::parse(",duck").is_err());
+        assert!(MixTrack::parse("a.wav,offset=-1").is_err());
+        assert!(MixTrack::parse("a.wav,offset=soon").is_err());
+        assert!(MixTrack::parse("a.wav,gain=loud").is_err());
+        assert!(MixTrack::parse("a.wav,gain=inf").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+pub mod channels;
+pub mod concat;
+pub mod fan_out;
+pub mod mix;
+pub mod split;
+pub mod verify;
+
+use std::path::{Path, PathBuf};
+use log::info;
+use tempfile::NamedTempFile;
+use crate::audio_processor::pipeline::{Pipeline, CHUNK_FRAMES};
+use crate::codecs::{self, AudioDecoder, AudioEncoder, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::format_detection::detect_input_format;
+use crate::transcoders::{self, TranscodeOptions};
+use crate::transcoders::ffmpeg_transcoder::FfmpegEncoder;
+
+/// a decoded input of an operation
+pub struct Source {
+    pub decoder: Box<dyn AudioDecoder>,
+    // the float WAV file ffmpeg decoded the input to, removed once the source is dropped
+    _intermediate: Option<NamedTempFile>,
+}
+
+/// opens `input_path` for decoding, natively where possible and otherwise by first decoding it to an intermediate float WAV
+/// file with ffmpeg, which keeps inputs of more than 16 bits and lossy inputs exceeding full scale intact
+pub fn open_source(input_path: &Path, options: &TranscodeOptions) -> Result<Source, TranscoderError> {
+    let input_format = detect_input_format(input_path, options.input_format.as_deref())?;
+    if codecs::supports_native_input(&input_format.format) {
+        let decoder = codecs::open_decoder(input_path, &input_format.format, options)?;
+        return Ok(Source { decoder, _intermediate: None });
+    }
+
+    info!("Decoding {:?} with FFmpeg", input_path);
+    let intermediate = tempfile::Builder::new().prefix(".rewav-").suffix(".wav").tempfile()?;
+    let decode_options = TranscodeOptions {
+        output_format_extension: "wav".to_string(),
+        output_codec: Some("pcm_f32le".to_string()),
+        input_format: options.input_format.clone(),
+        threads: options.threads,
+        cancellation: options.cancellation.clone(),
+        overwrite: true,
+        ffmpeg_path: options.ffmpeg_path.clone(),
+        ..TranscodeOptions::default()
+    };
+    transcoders::transcode_audio(input_path, intermediate.path(), &decode_options)?;
+    let decoder = codecs::open_decoder(intermediate.path(), "wav", &decode_options)?;
+    Ok(Source { decoder, _intermediate: Some(intermediate) })
+}
+
+/// the format several inputs are conformed to before they are combined: the highest sample rate and channel count among them
+pub fn common_spec(specs: &[StreamSpec]) -> StreamSpec {
+    StreamSpec {
+        sample_rate: specs.iter().map(|spec| spec.sample_rate).max().unwrap_or_default(),
+        channels: specs.iter().map(|spec| spec.channels).max().unwrap_or_default(),
+        ..specs[0]
+    }
+}
+
+/// a source decoded and conformed to the format of the output through a pipeline, read in blocks of any size
+pub struct ConformedSource {
+    pub source: Source,
+    pipeline: Pipeline,
+    // conformed samples not read yet
+    buffered: Vec<f32>,
+    finished: bool,
+}
+
+impl ConformedSource {
+    pub fn new(source: Source, pipeline: Pipeline) -> Self {
+        Self { source, pipeline, buffered: Vec::new(), finished: false }
+    }
+
+    /// returns the next `samples` conformed samples, or fewer at the end of the source
+    pub fn read(&mut self, samples: usize) -> Result<Vec<f32>, TranscoderError> {
+        while self.buffered.len() < samples && !self.finished {
+            let chunk = self.source.decoder.read_frames(CHUNK_FRAMES)?;
+            if chunk.is_empty() { // EOF
+                self.finished = true;
+                self.buffered.extend(self.pipeline.flush()?);
+            } else {
+                self.buffered.extend(self.pipeline.process(chunk)?);
+            }
+        }
+
+        let available = samples.min(self.buffered.len());
+        Ok(self.buffered.drain(..available).collect())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.finished && self.buffered.is_empty()
+    }
+}
+
+/// expands `{index}` or a zero-padded `{index:03}` in an output template
+pub fn numbered_path(template: &str, index: usize) -> Result<PathBuf, TranscoderError> {
+    let invalid = || TranscoderError::Argument(format!(
+        "Output template {:?} must contain {{index}} or a zero-padded {{index:03}}", template
+    ));
+    let start = template.find("{index").ok_or_else(invalid)?;
+    let length = template[start..].find('}').ok_or_else(invalid)? + 1;
+    let width = match &template[start + "{index".len()..start + length - 1] {
+        "" => 0,
+        spec => spec.strip_prefix(":0").and_then(|width| width.parse().ok()).ok_or_else(invalid)?,
+    };
+    let rendered = format!("{}{:0width$}{}", &template[..start], index, &template[start + length..], width = width);
+    Ok(PathBuf::from(rendered))
+}
+
+// where the samples handed to an `OutputWriter` end up before the output is complete
+enum OutputTarget {
+    /// a native encoder writing to a sibling temp file, or directly to stdout
+    Native(Box<dyn AudioEncoder>, Option<NamedTempFile>),
+    /// an ffmpeg process the samples are streamed to
+    Ffmpeg(FfmpegEncoder),
+}
+
+/// writes samples produced by an operation to an output of any format, natively where possible and through ffmpeg otherwise
+/// the output only appears once `finish` succeeds
+pub struct OutputWriter {
+    output_path: PathBuf,
+    target: OutputTarget,
+    options: TranscodeOptions,
+}
+
+impl OutputWriter {
+    /// the stream specification the samples written to `output_path` must have, given the `source` they are produced from
+    pub fn output_spec(output_path: &Path, source: &StreamSpec, options: &TranscodeOptions) -> Result<StreamSpec, TranscoderError> {
+        match transcoders::native_output_codec(output_path, options) {
+            Some(codec) => codec.output_spec(source, options),
+            None => Ok(StreamSpec {
+                sample_rate: options.sample_rate.unwrap_or(source.sample_rate),
+                channels: options.channels.unwrap_or(source.channels),
+                bits_per_sample: 32,
+                is_float: true,
+                ..*source
+            }),
+        }
+    }
+
+    pub fn create(output_path: &Path, source: &StreamSpec, tags: &Tags, options: &TranscodeOptions) -> Result<Self, TranscoderError> {
+        transcoders::check_overwrite(output_path, options)?;
+        let spec = Self::output_spec(output_path, source, options)?;
+
+        let target = match transcoders::native_output_codec(output_path, options) {
+            Some(codec) => {
+                let temp_output = transcoders::create_temp_output(output_path)?;
+                let target_path = temp_output.as_ref().map_or(output_path, |temp| temp.path());
+                OutputTarget::Native(codec.create_encoder(target_path, &spec, options, tags)?, temp_output)
+            }
+            None => {
+                info!("Encoding {:?} with FFmpeg", output_path);
+                OutputTarget::Ffmpeg(FfmpegEncoder::spawn(&spec, tags, &[(output_path, &encoding_options(options))])?)
+            }
+        };
+
+        Ok(Self { output_path: output_path.to_path_buf(), target, options: options.clone() })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        match &mut self.target {
+            OutputTarget::Native(encoder, _) => encoder.write_samples(samples),
+            OutputTarget::Ffmpeg(encoder) => encoder.write_samples(samples),
+        }
+    }
+
+    /// finalizes the encoder and moves the output into place
+    pub fn finish(self) -> Result<(), TranscoderError> {
+        match self.target {
+            OutputTarget::Native(encoder, temp_output) => {
+                encoder.finalize()?;
+                match temp_output {
+                    Some(temp_output) => transcoders::persist_output(temp_output, &self.output_path, &self.options),
+                    None => Ok(()),
+                }
+            }
+            OutputTarget::Ffmpeg(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// options for encoding already processed samples, without repeating filters and level processing
+fn encoding_options(options: &TranscodeOptions) -> TranscodeOptions {
+    TranscodeOptions {
+        equalizer: Vec::new(),
+        audio_filter: None,
+        gain_db: None,
+        normalize_peak_dbfs: None,
+        soft_limit_dbfs: None,
+        trim_silence: None,
+        progress: None,
+        ..options.clone()
+    }
+}
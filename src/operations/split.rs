@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use log::{debug, info};
+use crate::audio_processor::level::SoftLimiter;
+use crate::audio_processor::pipeline::Pipeline;
+use crate::audio_processor::silence::{SilenceDetector, SilenceSettings};
+use crate::codecs::{self, StreamSpec, Tags};
+use crate::errors::TranscoderError;
+use crate::format_detection::detect_input_format;
+use crate::operations::{self, OutputWriter};
+use crate::progress::ProgressTracker;
+use crate::transcoders::{self, native_transcoder, TranscodeOptions};
+
+/// cuts the input at every silence lasting at least the minimum duration, writing the sections in between to the outputs
+/// named by `template`, in which `{index}` is replaced by the 1-based part number; the silence itself is dropped
+/// returns the paths of the written parts
+pub fn split_on_silence(
+    input_path: &Path,
+    template: &str,
+    silence: &SilenceSettings,
+    options: &TranscodeOptions,
+) -> Result<Vec<PathBuf>, TranscoderError> {
+    operations::numbered_path(template, 1)?;
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument("--normalize-peak is not supported when splitting".to_string()));
+    }
+
+    let input_format = detect_input_format(input_path, options.input_format.as_deref())?;
+    if !codecs::supports_native_input(&input_format.format) {
+        return Err(TranscoderError::UnsupportedInputFormat(format!(
+            "Splitting requires a natively decoded input (WAV, FLAC or raw PCM), got {}", input_format.format
+        )));
+    }
+    let filters = transcoders::native_filters(options).ok_or_else(|| {
+        TranscoderError::Argument("Splitting supports only filter graphs with native equivalents".to_string())
+    })?;
+
+    let mut decoder = codecs::open_decoder(input_path, &input_format.format, options)?;
+    let input_spec = decoder.spec();
+    // every part shares the format of the template's extension
+    let spec = OutputWriter::output_spec(Path::new(template), &input_spec, options)?;
+    info!("Splitting {:?} at silences below {} dBFS lasting at least {:?}", input_path, silence.threshold_db, silence.min_duration);
+
+    let mut pipeline = Pipeline::new(&input_spec, &spec, &filters, 1.0, options.soft_limit_dbfs.map(SoftLimiter::new), None)?;
+    let mut splitter = Splitter {
+        template,
+        source: input_spec,
+        tags: decoder.tags(),
+        options,
+        detector: SilenceDetector::new(silence, spec.sample_rate, spec.channels),
+        min_samples: silence.min_samples(spec.sample_rate, spec.channels),
+        current: None,
+        held: Vec::new(),
+        parts: Vec::new(),
+    };
+
+    let mut progress = ProgressTracker::new(options.progress.clone(), input_spec.sample_rate, input_spec.total_frames);
+    let frames_read = native_transcoder::run_pipeline(decoder.as_mut(), &mut pipeline, options, |samples, frames_read| {
+        for (window, silent) in splitter.detector.push(samples) {
+            splitter.push_window(window, silent)?;
+        }
+        progress.update(frames_read);
+        Ok(())
+    })?;
+    let parts = splitter.finish()?;
+    progress.finish(frames_read);
+
+    Ok(parts)
+}
+
+// the state of a split in progress
+struct Splitter<'a> {
+    template: &'a str,
+    source: StreamSpec,
+    tags: Tags,
+    options: &'a TranscodeOptions,
+    detector: SilenceDetector,
+    min_samples: usize,
+    // the part being written, None while in a silence
+    current: Option<OutputWriter>,
+    // silent windows within the current part, written if sound follows before they add up to a split
+    held: Vec<f32>,
+    parts: Vec<PathBuf>,
+}
+
+impl Splitter<'_> {
+    fn push_window(&mut self, window: Vec<f32>, silent: bool) -> Result<(), TranscoderError> {
+        if silent {
+            if self.current.is_some() {
+                self.held.extend(window);
+                if self.held.len() >= self.min_samples {
+                    self.held.clear();
+                    self.finish_part()?;
+                }
+            }
+            return Ok(());
+        }
+
+        let writer = match &mut self.current {
+            Some(writer) => writer,
+            None => {
+                let path = operations::numbered_path(self.template, self.parts.len() + 1)?;
+                debug!("Starting part {:?}", path);
+                self.current.insert(OutputWriter::create(&path, &self.source, &self.tags, self.options)?)
+            }
+        };
+        writer.write_samples(&self.held)?;
+        writer.write_samples(&window)?;
+        self.held.clear();
+        Ok(())
+    }
+
+    fn finish_part(&mut self) -> Result<(), TranscoderError> {
+        if let Some(writer) = self.current.take() {
+            let path = operations::numbered_path(self.template, self.parts.len() + 1)?;
+            writer.finish()?;
+            info!("Wrote part {} to {:?}", self.parts.len() + 1, path);
+            self.parts.push(path);
+        }
+        Ok(())
+    }
+
+    // writes the final window and closes the last part, keeping trailing silence too short to split at
+    fn finish(mut self) -> Result<Vec<PathBuf>, TranscoderError> {
+        if let Some((window, silent)) = self.detector.finish() {
+            self.push_window(window, silent)?;
+        }
+        if let Some(writer) = &mut self.current {
+            writer.write_samples(&self.held)?;
+        }
+        self.finish_part()?;
+        Ok(self.parts)
+    }
+}
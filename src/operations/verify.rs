@@ -0,0 +1,367 @@
+use std::path::Path;
+use log::{debug, info, warn};
+use crate::audio_processor::filters::AudioFilter;
+use crate::audio_processor::pipeline::{Pipeline, CHUNK_FRAMES};
+use crate::codecs::{flac, StreamSpec};
+use crate::errors::TranscoderError;
+use crate::format_detection::detect_input_format;
+use crate::operations::{self, ConformedSource, Source};
+use crate::transcoders::{self, TranscodeOptions};
+use crate::utils;
+
+/// formats storing samples without loss, whose outputs have to match their input exactly
+const LOSSLESS_FORMATS: &[&str] = &["wav", "rf64", "w64", "flac", "raw", "aiff", "wv", "tta", "ape"];
+// frames at the start of both streams compared to find the delay a lossy encoder added
+const LAG_WINDOW_FRAMES: usize = 16384;
+// longest delay searched for, covering the priming of common lossy encoders
+const MAX_LAG_FRAMES: usize = 2048;
+
+/// thresholds an output that cannot match its input sample for sample has to meet
+#[derive(Debug, Clone, Copy)]
+pub struct VerifySettings {
+    /// lowest signal-to-noise ratio of the output against the input in dB
+    pub min_snr_db: f64,
+    /// lowest correlation between the input and the output
+    pub min_correlation: f64,
+}
+
+// running totals of a comparison
+#[derive(Debug, Default)]
+struct Comparison {
+    frames: u64,
+    differing_samples: u64,
+    first_difference: Option<u64>,
+    reference_energy: f64,
+    output_energy: f64,
+    cross_energy: f64,
+    noise_energy: f64,
+    max_difference: f32,
+    // frames left in the reference or the output once the other one ended
+    reference_excess: u64,
+    output_excess: u64,
+}
+
+impl Comparison {
+    fn add(&mut self, reference: &[f32], output: &[f32], channels: usize) {
+        for (index, (&r, &o)) in reference.iter().zip(output).enumerate() {
+            if r != o {
+                self.differing_samples += 1;
+                self.first_difference.get_or_insert(self.frames + (index / channels) as u64);
+            }
+            let (r, o) = (r as f64, o as f64);
+            self.reference_energy += r * r;
+            self.output_energy += o * o;
+            self.cross_energy += r * o;
+            self.noise_energy += (r - o) * (r - o);
+            self.max_difference = self.max_difference.max((r - o).abs() as f32);
+        }
+        self.frames += (reference.len().min(output.len()) / channels) as u64;
+    }
+
+    fn snr_db(&self) -> f64 {
+        if self.noise_energy == 0.0 {
+            return f64::INFINITY;
+        }
+        10.0 * (self.reference_energy / self.noise_energy).log10()
+    }
+
+    fn correlation(&self) -> f64 {
+        let norm = (self.reference_energy * self.output_energy).sqrt();
+        match (norm > 0.0, self.noise_energy == 0.0) {
+            (true, _) => self.cross_energy / norm,
+            // silence matching silence
+            (false, true) => 1.0,
+            (false, false) => 0.0,
+        }
+    }
+}
+
+/// rejects options that process the audio beyond converting its sample rate and channel count, which the output could
+/// not be verified against, and stdin or stdout
+pub fn check_verifiable(input_path: &Path, output_path: &Path, options: &TranscodeOptions) -> Result<(), TranscoderError> {
+    if utils::is_stdio(input_path) || utils::is_stdio(output_path) {
+        return Err(TranscoderError::Argument("Verification requires files as input and output, not stdin or stdout".to_string()));
+    }
+    let only_resampling = transcoders::native_filters(options)
+        .is_some_and(|filters| filters.iter().all(|filter| matches!(filter, AudioFilter::Aresample(_))));
+    if !only_resampling || options.normalize_peak_dbfs.is_some() || options.soft_limit_dbfs.is_some() || options.trim_silence.is_some() {
+        return Err(TranscoderError::Argument(
+            "Verification compares the output against the unprocessed input and cannot be combined with filters or level options".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// decodes the input and the output and compares them, failing with a Verification error if the output was altered
+/// outputs in lossless formats with the sample rate and channel count of the input and at least its bit depth have to
+/// match it sample for sample; others are compared with the input converted to their sample rate and channel count,
+/// aligned for the delay of lossy encoders, against the thresholds of `settings`
+/// the MD5 signatures of FLAC inputs and outputs are checked as well
+pub fn verify(input_path: &Path, output_path: &Path, settings: &VerifySettings, options: &TranscodeOptions) -> Result<(), TranscoderError> {
+    check_verifiable(input_path, output_path, options)?;
+    info!("Verifying {:?} against {:?}", output_path, input_path);
+
+    let input_format = detect_input_format(input_path, options.input_format.as_deref())?.format;
+    let reference = operations::open_source(input_path, options)?;
+    let input_spec = reference.decoder.spec();
+    let output_options = output_decode_options(&input_spec, options);
+    let output_format = detect_input_format(output_path, output_options.input_format.as_deref())?.format;
+    for (path, format) in [(input_path, &input_format), (output_path, &output_format)] {
+        if format == "flac" {
+            check_flac_signature(path)?;
+        }
+    }
+    let output = operations::open_source(output_path, &output_options)?;
+    let output_spec = output.decoder.spec();
+
+    let exact = LOSSLESS_FORMATS.contains(&output_format.as_str())
+        && output_spec.sample_rate == input_spec.sample_rate
+        && output_spec.channels == input_spec.channels
+        && (output_spec.is_float || (!input_spec.is_float && output_spec.bits_per_sample >= input_spec.bits_per_sample));
+    debug!("Verifying {:?} -> {:?}, expecting identical samples: {}", input_spec, output_spec, exact);
+
+    // the input is converted to the sample rate and channel count of the output as the transcode did
+    let conform = |reference: Source, output: Source| -> Result<(ConformedSource, ConformedSource), TranscoderError> {
+        let reference = ConformedSource::new(reference, Pipeline::new(&input_spec, &output_spec, &[], 1.0, None, None)?);
+        let output = ConformedSource::new(output, Pipeline::new(&output_spec, &output_spec, &[], 1.0, None, None)?);
+        Ok((reference, output))
+    };
+    let channels = output_spec.channels as usize;
+    let (mut reference, mut output) = conform(reference, output)?;
+    let lag = if exact {
+        0
+    } else {
+        let window = (LAG_WINDOW_FRAMES + MAX_LAG_FRAMES) * channels;
+        let lag = estimate_lag(&downmix(&reference.read(window)?, channels), &downmix(&output.read(window)?, channels));
+        (reference, output) = conform(operations::open_source(input_path, options)?, operations::open_source(output_path, &output_options)?)?;
+        lag
+    };
+    // a positive lag delays the output, a negative one the reference
+    if lag > 0 {
+        output.read(lag.unsigned_abs() as usize * channels)?;
+    } else if lag < 0 {
+        reference.read(lag.unsigned_abs() as usize * channels)?;
+    }
+
+    let mut comparison = Comparison::default();
+    let block = CHUNK_FRAMES * channels;
+    loop {
+        options.cancellation.check()?;
+        let reference_block = reference.read(block)?;
+        let output_block = output.read(block)?;
+        comparison.add(&reference_block, &output_block, channels);
+        let excess = reference_block.len().abs_diff(output_block.len()) / channels;
+        if reference_block.len() > output_block.len() {
+            comparison.reference_excess += excess as u64;
+        } else {
+            comparison.output_excess += excess as u64;
+        }
+        if reference.is_done() && output.is_done() {
+            break;
+        }
+    }
+
+    let duration = |frames: u64| frames as f64 / output_spec.sample_rate as f64;
+    if exact {
+        if comparison.differing_samples > 0 {
+            return Err(TranscoderError::Verification(format!(
+                "{:?}: {} of {} samples differ from the input, the first at {:.3}s, by up to {:.1} dBFS",
+                output_path,
+                comparison.differing_samples,
+                comparison.frames * channels as u64,
+                duration(comparison.first_difference.unwrap_or_default()),
+                20.0 * comparison.max_difference.log10()
+            )));
+        }
+        if comparison.reference_excess > 0 || comparison.output_excess > 0 {
+            return Err(TranscoderError::Verification(format!(
+                "{:?}: {} frames long, but the input has {}",
+                output_path,
+                comparison.frames + comparison.output_excess,
+                comparison.frames + comparison.reference_excess
+            )));
+        }
+        info!("Verified {:?}: all {} frames ({:.3}s) are identical to the input", output_path, comparison.frames, duration(comparison.frames));
+        return Ok(());
+    }
+
+    let (snr_db, correlation) = (comparison.snr_db(), comparison.correlation());
+    info!(
+        "Verified {:?}: SNR {:.1} dB, correlation {:.5} over {:.3}s, delayed by {} frames, {} frames longer and {} frames shorter than the input",
+        output_path, snr_db, correlation, duration(comparison.frames), lag, comparison.output_excess, comparison.reference_excess
+    );
+    if snr_db < settings.min_snr_db || correlation < settings.min_correlation {
+        return Err(TranscoderError::Verification(format!(
+            "{:?}: SNR of {:.1} dB and correlation of {:.5} against the input, below the required {} dB and {}",
+            output_path, snr_db, correlation, settings.min_snr_db, settings.min_correlation
+        )));
+    }
+    Ok(())
+}
+
+// describes how an output is decoded; raw PCM outputs are laid out as the transcode wrote them
+fn output_decode_options(input_spec: &StreamSpec, options: &TranscodeOptions) -> TranscodeOptions {
+    TranscodeOptions {
+        input_format: options.output_format.clone(),
+        raw_format: options.raw_format,
+        raw_sample_rate: Some(options.sample_rate.unwrap_or(input_spec.sample_rate)),
+        raw_channels: Some(options.channels.unwrap_or(input_spec.channels)),
+        threads: options.threads,
+        cancellation: options.cancellation.clone(),
+        ffmpeg_path: options.ffmpeg_path.clone(),
+        ..TranscodeOptions::default()
+    }
+}
+
+fn check_flac_signature(path: &Path) -> Result<(), TranscoderError> {
+    match flac::check_md5(path)? {
+        Some(true) => {
+            info!("{:?} matches the MD5 signature in its STREAMINFO", path);
+            Ok(())
+        }
+        Some(false) => Err(TranscoderError::Verification(format!("{:?} does not match the MD5 signature in its STREAMINFO", path))),
+        None => {
+            warn!("{:?} has no MD5 signature in its STREAMINFO", path);
+            Ok(())
+        }
+    }
+}
+
+// averages the channels of interleaved samples
+fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    samples.chunks_exact(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect()
+}
+
+// finds the delay of the output against the reference with the highest normalized correlation, preferring no delay
+fn estimate_lag(reference: &[f32], output: &[f32]) -> i64 {
+    let correlation = |lag: i64| {
+        let (reference, output) = if lag >= 0 {
+            (reference, output.get(lag as usize..).unwrap_or_default())
+        } else {
+            (reference.get(lag.unsigned_abs() as usize..).unwrap_or_default(), output)
+        };
+        let length = reference.len().min(output.len()).min(LAG_WINDOW_FRAMES);
+        let (mut cross, mut reference_energy, mut output_energy) = (0.0f64, 0.0f64, 0.0f64);
+        for (&r, &o) in reference[..length].iter().zip(&output[..length]) {
+            cross += r as f64 * o as f64;
+            reference_energy += r as f64 * r as f64;
+            output_energy += o as f64 * o as f64;
+        }
+        let norm = (reference_energy * output_energy).sqrt();
+        if norm > 0.0 { cross / norm } else { 0.0 }
+    };
+
+    let max_lag = MAX_LAG_FRAMES as i64;
+    let mut best = (0, correlation(0));
+    for lag in (-max_lag..=max_lag).filter(|&lag| lag != 0) {
+        let value = correlation(lag);
+        if value > best.1 + 1e-6 {
+            best = (lag, value);
+        }
+    }
+    best.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use crate::test_support;
+
+    const SETTINGS: VerifySettings = VerifySettings { min_snr_db: 10.0, min_correlation: 0.95 };
+
+    // noise without periodicity, so that only the true delay correlates
+    fn noise(length: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491_u32;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_a_known_lag() {
+        let reference = noise(LAG_WINDOW_FRAMES + MAX_LAG_FRAMES);
+        let mut delayed = vec![0.0; 150];
+        delayed.extend_from_slice(&reference);
+        assert_eq!(estimate_lag(&reference, &delayed), 150);
+        assert_eq!(estimate_lag(&delayed, &reference), -150);
+        assert_eq!(estimate_lag(&reference, &reference), 0);
+    }
+
+    #[test]
+    fn prefers_no_lag_for_silence() {
+        let silence = vec![0.0; 4096];
+        assert_eq!(estimate_lag(&silence, &silence), 0);
+    }
+
+    #[test]
+    fn compares_identical_buffers() {
+        let samples = noise(2048);
+        let mut comparison = Comparison::default();
+        comparison.add(&samples, &samples, 2);
+        assert_eq!(comparison.frames, 1024);
+        assert_eq!(comparison.differing_samples, 0);
+        assert_eq!(comparison.snr_db(), f64::INFINITY);
+        assert!((comparison.correlation() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compares_silent_buffers() {
+        let silence = vec![0.0; 512];
+        let mut comparison = Comparison::default();
+        comparison.add(&silence, &silence, 1);
+        assert_eq!(comparison.snr_db(), f64::INFINITY);
+        assert_eq!(comparison.correlation(), 1.0);
+
+        // silence against a signal correlates with nothing
+        let mut comparison = Comparison::default();
+        comparison.add(&silence, &noise(512), 1);
+        assert_eq!(comparison.correlation(), 0.0);
+        assert_eq!(comparison.first_difference, Some(0));
+    }
+
+    #[test]
+    fn measures_noise_against_the_reference() {
+        let reference = noise(4096);
+        let output: Vec<f32> = reference.iter().map(|sample| sample * 0.9).collect();
+        let mut comparison = Comparison::default();
+        comparison.add(&reference, &output, 1);
+        // the difference is a tenth of the signal, 20 dB below it
+        assert!((comparison.snr_db() - 20.0).abs() < 1e-3);
+        assert!((comparison.correlation() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn verifies_an_identical_copy() {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.path().join("in.wav"), dir.path().join("out.wav"));
+        test_support::write_sine_wav(&input, 44100, 10000);
+        std::fs::copy(&input, &output).unwrap();
+        verify(&input, &output, &SETTINGS, &TranscodeOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn rejects_an_altered_lossless_output() {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.path().join("in.wav"), dir.path().join("out.wav"));
+        test_support::write_sine_wav(&input, 44100, 10000);
+        let mut bytes = std::fs::read(&input).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x40;
+        std::fs::write(&output, bytes).unwrap();
+        let result = verify(&input, &output, &SETTINGS, &TranscodeOptions::default());
+        assert!(matches!(result, Err(TranscoderError::Verification(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn rejects_processing_options() {
+        let options = TranscodeOptions { normalize_peak_dbfs: Some(-1.0), ..TranscodeOptions::default() };
+        let result = check_verifiable(Path::new("in.wav"), Path::new("out.wav"), &options);
+        assert!(matches!(result, Err(TranscoderError::Argument(_))));
+        let result = check_verifiable(Path::new("-"), Path::new("out.wav"), &TranscodeOptions::default());
+        assert!(matches!(result, Err(TranscoderError::Argument(_))));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use crate::transcoders::{BitrateMode, TranscodeOptions};
+
+/// named quality presets translated into concrete parameters for each encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QualityPreset {
+    /// speech and podcasts, favoring small files
+    Voice,
+    /// music at a compact size
+    MusicLow,
+    /// music transparent for most listeners
+    MusicHigh,
+    /// the best quality the codec offers, regardless of size
+    Archival,
+}
+
+/// encoder families the presets provide parameters for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresetCodec {
+    Mp3,
+    Opus,
+    Vorbis,
+    /// ffmpeg's native AAC encoder
+    Aac,
+    /// the Fraunhofer FDK AAC encoder
+    FdkAac,
+    Flac,
+}
+
+impl PresetCodec {
+    /// determines the encoder family from the requested codec, falling back to the default encoder of the output format
+    pub fn detect(output_format: &str, codec: Option<&str>) -> Option<Self> {
+        match codec {
+            Some("libmp3lame" | "mp3") => Some(PresetCodec::Mp3),
+            Some("libopus" | "opus") => Some(PresetCodec::Opus),
+            Some("libvorbis" | "vorbis") => Some(PresetCodec::Vorbis),
+            Some("aac") => Some(PresetCodec::Aac),
+            Some("libfdk_aac") => Some(PresetCodec::FdkAac),
+            Some("flac") => Some(PresetCodec::Flac),
+            Some(_) => None,
+            None => match output_format {
+                "mp3" => Some(PresetCodec::Mp3),
+                "opus" => Some(PresetCodec::Opus),
+                "ogg" => Some(PresetCodec::Vorbis),
+                "aac" | "m4a" => Some(PresetCodec::Aac),
+                "flac" => Some(PresetCodec::Flac),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// MP3 parameters of a preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp3Preset {
+    pub bitrate_mode: BitrateMode,
+    /// LAME VBR quality (`-q:a`), from 0 (best) to 9 (smallest)
+    pub vbr_quality: u8,
+    /// bitrate in kbps for CBR
+    pub bitrate_kbps: u32,
+    /// LAME algorithm quality, from 0 (best, slowest) to 9 (worst, fastest)
+    pub algorithm_quality: u8,
+}
+
+/// Opus applications, tuning the encoder for speech or general audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpusApplication {
+    Voip,
+    Audio,
+}
+
+impl OpusApplication {
+    /// value of ffmpeg's `-application` option for libopus
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            OpusApplication::Voip => "voip",
+            OpusApplication::Audio => "audio",
+        }
+    }
+}
+
+/// Opus parameters of a preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusPreset {
+    pub bitrate_kbps: u32,
+    pub application: OpusApplication,
+}
+
+impl QualityPreset {
+    pub fn mp3(self) -> Mp3Preset {
+        match self {
+            QualityPreset::Voice => Mp3Preset { bitrate_mode: BitrateMode::Vbr, vbr_quality: 7, bitrate_kbps: 64, algorithm_quality: 5 },
+            QualityPreset::MusicLow => Mp3Preset { bitrate_mode: BitrateMode::Vbr, vbr_quality: 5, bitrate_kbps: 128, algorithm_quality: 3 },
+            QualityPreset::MusicHigh => Mp3Preset { bitrate_mode: BitrateMode::Vbr, vbr_quality: 2, bitrate_kbps: 192, algorithm_quality: 2 },
+            QualityPreset::Archival => Mp3Preset { bitrate_mode: BitrateMode::Cbr, vbr_quality: 0, bitrate_kbps: 320, algorithm_quality: 0 },
+        }
+    }
+
+    pub fn opus(self) -> OpusPreset {
+        match self {
+            QualityPreset::Voice => OpusPreset { bitrate_kbps: 24, application: OpusApplication::Voip },
+            QualityPreset::MusicLow => OpusPreset { bitrate_kbps: 64, application: OpusApplication::Audio },
+            QualityPreset::MusicHigh => OpusPreset { bitrate_kbps: 128, application: OpusApplication::Audio },
+            QualityPreset::Archival => OpusPreset { bitrate_kbps: 256, application: OpusApplication::Audio },
+        }
+    }
+
+    /// Vorbis VBR quality on the libvorbis scale from -1 to 10 (ffmpeg's `-q:a`)
+    pub fn vorbis_quality(self) -> f32 {
+        match self {
+            QualityPreset::Voice => 1.0,
+            QualityPreset::MusicLow => 3.0,
+            QualityPreset::MusicHigh => 6.0,
+            QualityPreset::Archival => 9.0,
+        }
+    }
+
+    /// VBR quality of ffmpeg's native AAC encoder (`-q:a`), from 0.1 to 2
+    pub fn aac_vbr_quality(self) -> f32 {
+        match self {
+            QualityPreset::Voice => 0.4,
+            QualityPreset::MusicLow => 0.8,
+            QualityPreset::MusicHigh => 1.4,
+            QualityPreset::Archival => 2.0,
+        }
+    }
+
+    /// VBR mode of the FDK AAC encoder (`-vbr`), from 1 (smallest) to 5 (best)
+    pub fn fdk_aac_vbr_mode(self) -> u8 {
+        match self {
+            QualityPreset::Voice => 1,
+            QualityPreset::MusicLow => 3,
+            QualityPreset::MusicHigh => 4,
+            QualityPreset::Archival => 5,
+        }
+    }
+
+    /// FLAC compression level, trading encoding speed for smaller files
+    pub fn flac_compression_level(self) -> u8 {
+        match self {
+            QualityPreset::Voice | QualityPreset::MusicLow => 5,
+            QualityPreset::MusicHigh => 8,
+            QualityPreset::Archival => 12,
+        }
+    }
+}
+
+/// fills in the MP3 bitrate options of the preset unless any of them was given explicitly
+pub fn with_mp3_preset(options: &TranscodeOptions) -> TranscodeOptions {
+    let mut options = options.clone();
+    let explicit = options.bitrate_mode.is_some() || options.vbr_quality.is_some() || options.bitrate_kbps.is_some();
+    if let Some(preset) = options.quality_preset.map(QualityPreset::mp3).filter(|_| !explicit) {
+        options.bitrate_mode = Some(preset.bitrate_mode);
+        match preset.bitrate_mode {
+            BitrateMode::Vbr => options.vbr_quality = Some(preset.vbr_quality),
+            BitrateMode::Cbr | BitrateMode::Abr => options.bitrate_kbps = Some(preset.bitrate_kbps),
+        }
+    }
+    options
+}
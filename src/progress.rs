@@ -0,0 +1,120 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// minimum time between two reports of the native transcoders
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// a snapshot of the progress of a running transcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// input frames processed so far; estimated from the output timestamp for ffmpeg
+    pub frames: u64,
+    /// total number of input frames, if known
+    pub total_frames: Option<u64>,
+    /// media time processed so far
+    pub position: Duration,
+    /// total media duration, if known
+    pub duration: Option<Duration>,
+    /// wall clock time since the transcode started
+    pub elapsed: Duration,
+    /// whether this is the final report of the transcode
+    pub finished: bool,
+}
+
+impl Progress {
+    /// completed fraction in percent, if the total duration is known
+    pub fn percent(&self) -> Option<f64> {
+        let duration = self.duration?.as_secs_f64();
+        if duration <= 0.0 {
+            return None;
+        }
+        Some((self.position.as_secs_f64() / duration * 100.0).min(100.0))
+    }
+
+    /// processing speed as a multiple of real time
+    pub fn speed(&self) -> Option<f64> {
+        let elapsed = self.elapsed.as_secs_f64();
+        (elapsed > 0.0).then(|| self.position.as_secs_f64() / elapsed)
+    }
+
+    /// estimated remaining wall clock time, based on the average speed so far
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.duration?.saturating_sub(self.position);
+        let speed = self.speed().filter(|speed| *speed > 0.0)?;
+        Some(Duration::from_secs_f64(remaining.as_secs_f64() / speed))
+    }
+}
+
+/// a shareable callback receiving progress reports, e.g. to drive a progress bar or forward them over a channel
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// hands a progress report to the callback
+    pub fn report(&self, progress: &Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// turns frame counts of the native pipeline into throttled progress reports
+pub struct ProgressTracker {
+    callback: Option<ProgressCallback>,
+    sample_rate: u32,
+    total_frames: Option<u64>,
+    started: Instant,
+    last_report: Option<Instant>,
+}
+
+impl ProgressTracker {
+    pub fn new(callback: Option<ProgressCallback>, sample_rate: u32, total_frames: Option<u64>) -> Self {
+        Self {
+            callback,
+            sample_rate,
+            total_frames,
+            started: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    /// reports the number of input frames processed so far, at most every `REPORT_INTERVAL`
+    pub fn update(&mut self, frames: u64) {
+        let now = Instant::now();
+        if self.last_report.is_some_and(|last| now.duration_since(last) < REPORT_INTERVAL) {
+            return;
+        }
+        self.last_report = Some(now);
+        self.report(frames, false);
+    }
+
+    /// sends the final report
+    pub fn finish(&mut self, frames: u64) {
+        self.report(frames, true);
+    }
+
+    fn report(&self, frames: u64, finished: bool) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        let to_duration = |frames: u64| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+
+        callback.report(&Progress {
+            frames,
+            total_frames: self.total_frames,
+            position: to_duration(frames),
+            duration: self.total_frames.map(to_duration),
+            elapsed: self.started.elapsed(),
+            finished,
+        });
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+use crate::audio_processor::filters;
+use crate::cancellation::CancellationToken;
+use crate::cli::ServeArgs;
+use crate::errors::TranscoderError;
+use crate::jobs::JobOptions;
+use crate::progress::ProgressCallback;
+use crate::transcoders::{self, TranscodeOptions};
+
+// how long the server waits for a request before checking for cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// the state of a submitted transcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// a submitted transcode as reported by the status endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: u64,
+    pub state: JobState,
+    /// the local input path, or `upload` for uploaded audio
+    pub input: String,
+    /// URL the output can be downloaded from once the job is done
+    pub output: String,
+    /// completed fraction in percent while running, if the duration of the input is known
+    pub progress: Option<f64>,
+    pub error: Option<String>,
+}
+
+// a submitted transcode with the files and token belonging to it
+struct Job {
+    status: JobStatus,
+    output_path: PathBuf,
+    upload: Option<PathBuf>,
+    cancellation: CancellationToken,
+}
+
+// jobs by id, shared between the request loop and the workers
+type Jobs = Arc<Mutex<BTreeMap<u64, Job>>>;
+
+// a transcode waiting in the queue for a worker
+struct QueuedJob {
+    id: u64,
+    input: PathBuf,
+    output_path: PathBuf,
+    options: TranscodeOptions,
+}
+
+// the JSON body of a submission transcoding a local file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Submission {
+    input: PathBuf,
+    format: String,
+    #[serde(default)]
+    options: JobOptions,
+}
+
+// an error reported to the client as `{"error": ...}` with an HTTP status code
+struct ApiError(u16, String);
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self(400, message.into())
+    }
+
+    fn not_found() -> Self {
+        Self(404, "not found".to_string())
+    }
+
+    fn forbidden(message: impl Into<String>) -> Self {
+        Self(403, message.into())
+    }
+}
+
+// the request handlers and what they share; requests are handled on threads of their own
+struct Service<'a> {
+    args: &'a ServeArgs,
+    work_dir: PathBuf,
+    // the canonical --input-root, without which only uploads are accepted
+    input_root: Option<PathBuf>,
+    jobs: Jobs,
+    queue: SyncSender<QueuedJob>,
+    // jobs in the queue or about to be queued, counted before an upload is read so that a full queue rejects it at once
+    queued: Arc<AtomicUsize>,
+    next_id: AtomicU64,
+}
+
+// a place in the job queue, held from the start of a submission until its job is queued
+struct QueueSlot<'a> {
+    queued: &'a AtomicUsize,
+    taken: bool,
+}
+
+impl QueueSlot<'_> {
+    // the worker that dequeues the job releases the place
+    fn take(mut self) {
+        self.taken = true;
+    }
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        if !self.taken {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// serves transcodes over HTTP until cancelled, e.g. by Ctrl+C
+/// jobs are queued in a bounded queue, run by `--workers` threads and kept with their outputs until deleted or the server stops
+pub fn serve(args: &ServeArgs, cancellation: CancellationToken) -> Result<(), TranscoderError> {
+    // a temporary work directory is removed with everything in it when the server stops
+    let temp_dir = match &args.work_dir {
+        Some(_) => None,
+        None => Some(tempfile::Builder::new().prefix("rewav-serve-").tempdir()?),
+    };
+    let work_dir = match (&args.work_dir, &temp_dir) {
+        (Some(work_dir), _) => {
+            fs::create_dir_all(work_dir)?;
+            work_dir.clone()
+        }
+        (None, temp_dir) => temp_dir.as_ref().map(|temp_dir| temp_dir.path().to_path_buf()).unwrap_or_default(),
+    };
+
+    let input_root = args.input_root.as_deref().map(fs::canonicalize).transpose()?;
+
+    let server = Server::http(&args.listen).map_err(|e| TranscoderError::Serve(format!("{}: {}", args.listen, e)))?;
+    info!("Serving on http://{} with {} workers, work directory {:?}", args.listen, args.workers, work_dir);
+    run(&server, args, work_dir, input_root, &cancellation);
+    Ok(())
+}
+
+// answers the requests received by `server` until cancelled
+fn run(server: &Server, args: &ServeArgs, work_dir: PathBuf, input_root: Option<PathBuf>, cancellation: &CancellationToken) {
+    let jobs: Jobs = Arc::default();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let (queue, receiver) = mpsc::sync_channel(args.queue_size);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let workers: Vec<_> = (0..args.workers)
+        .map(|_| {
+            let (receiver, jobs, queued, timeout) = (Arc::clone(&receiver), Arc::clone(&jobs), Arc::clone(&queued), args.transcode.timeout);
+            thread::spawn(move || work(&receiver, &jobs, &queued, timeout))
+        })
+        .collect();
+
+    let service = Service { args, work_dir, input_root, jobs: Arc::clone(&jobs), queue, queued, next_id: AtomicU64::new(1) };
+    // a slow upload must not hold up status polls and downloads
+    thread::scope(|scope| {
+        while cancellation.check().is_ok() {
+            match server.recv_timeout(POLL_INTERVAL) {
+                Ok(Some(request)) => {
+                    let service = &service;
+                    scope.spawn(move || service.respond(request));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to receive a request: {}", e),
+            }
+        }
+        info!("Stopping, cancelling unfinished jobs");
+        for job in lock(&jobs).values() {
+            job.cancellation.cancel();
+        }
+    });
+
+    // closing the queue ends the workers once their current job is cancelled
+    drop(service);
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+impl Service<'_> {
+    fn respond(&self, mut request: Request) {
+        debug!("{} {}", request.method(), request.url());
+        let response = self.handle(&mut request).unwrap_or_else(|ApiError(status, message)| {
+            json_response(status, &serde_json::json!({ "error": message }))
+        });
+        if let Err(e) = request.respond(response) {
+            warn!("Failed to send a response: {}", e);
+        }
+    }
+
+    // routes a request to its handler
+    fn handle(&self, request: &mut Request) -> Result<ResponseBox, ApiError> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        let job_id = |segment: &str| segment.parse::<u64>().map_err(|_| ApiError::not_found());
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Post, ["jobs"]) => self.submit(request, query),
+            (Method::Get, ["jobs"]) => {
+                let statuses: Vec<JobStatus> = lock(&self.jobs).values().map(|job| job.status.clone()).collect();
+                Ok(json_response(200, &statuses))
+            }
+            (Method::Get, ["jobs", id]) => Ok(json_response(200, &self.status(job_id(id)?)?)),
+            (Method::Get, ["jobs", id, "output"]) => self.download(job_id(id)?),
+            (Method::Delete, ["jobs", id]) => self.delete(job_id(id)?),
+            _ => Err(ApiError::not_found()),
+        }
+    }
+
+    // queues a transcode of a local file under --input-root named by a JSON body, or of audio uploaded as the body with
+    // the format and options in the query string (`?format=mp3&bitrate=192`)
+    fn submit(&self, request: &mut Request, query: &str) -> Result<ResponseBox, ApiError> {
+        let slot = self.reserve()?;
+
+        let is_json = request
+            .headers()
+            .iter()
+            .any(|header| header.field.equiv("Content-Type") && header.value.as_str().starts_with("application/json"));
+        let (input, format, options) = if is_json {
+            let mut body = String::new();
+            request.as_reader().take(1 << 20).read_to_string(&mut body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            let submission: Submission = serde_json::from_str(&body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            (Some(self.local_input(&submission.input)?), submission.format, submission.options)
+        } else {
+            let mut settings = query_pairs(query);
+            let format = settings
+                .iter()
+                .position(|(key, _)| key == "format")
+                .map(|index| settings.remove(index).1)
+                .ok_or_else(|| ApiError::bad_request("the output format is required, e.g. ?format=mp3"))?;
+            let options = JobOptions::from_settings(settings.iter().map(|(key, value)| (key.as_str(), value.as_str())))
+                .map_err(ApiError::bad_request)?;
+            (None, format, options)
+        };
+
+        // these would let a client run any program, exhaust the host or replace files
+        if options.ffmpeg_path.is_some() || options.threads.is_some() || options.overwrite.is_some() {
+            return Err(ApiError::forbidden("ffmpeg-path, threads and overwrite are set by the server"));
+        }
+        // ffmpeg filters such as `amovie` or `ametadata=mode=print:file=` read and write files on the host
+        if let Some(Err(reason)) = options.af.as_deref().map(filters::parse_filtergraph) {
+            return Err(ApiError::forbidden(format!("af only accepts filters that run natively: {}", reason)));
+        }
+        let format = format.trim_start_matches('.').to_ascii_lowercase();
+        if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(ApiError::bad_request(format!("invalid output format {:?}", format)));
+        }
+        let mut args = self.args.transcode.clone();
+        options.apply(&mut args).map_err(ApiError::bad_request)?;
+        args.validate().map_err(|e| ApiError::bad_request(e.to_string()))?;
+        let cancellation = CancellationToken::new();
+        // only the extension of the output selects its format, the job id naming it is assigned once the job is queued
+        let mut options = args
+            .to_options(Path::new(&format!("output.{}", format)), None, cancellation.clone())
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+        let (input, upload) = match input {
+            Some(input) => (input, None),
+            None => {
+                let upload = receive_upload(request, &self.work_dir, self.args.max_upload * 1024 * 1024)?;
+                (upload.clone(), Some(upload))
+            }
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let output_path = self.work_dir.join(format!("{}.{}", id, format));
+        let jobs = Arc::clone(&self.jobs);
+        options.progress = Some(ProgressCallback::new(move |progress| {
+            if let Some(job) = lock(&jobs).get_mut(&id) {
+                job.status.progress = progress.percent();
+            }
+        }));
+
+        let status = JobStatus {
+            id,
+            state: JobState::Queued,
+            input: if upload.is_some() { "upload".to_string() } else { input.display().to_string() },
+            output: format!("/jobs/{}/output", id),
+            progress: None,
+            error: None,
+        };
+        let job = Job { status: status.clone(), output_path: output_path.clone(), upload: upload.clone(), cancellation };
+        lock(&self.jobs).insert(id, job);
+
+        info!("Job {}: queueing {} to .{}", id, status.input, format);
+        match self.queue.try_send(QueuedJob { id, input, output_path, options }) {
+            Ok(()) => {
+                slot.take();
+                let location = format!("/jobs/{}", id);
+                Ok(with_header(json_response(202, &status), "Location", &location))
+            }
+            Err(e) => {
+                warn!("Job {}: rejected", id);
+                lock(&self.jobs).remove(&id);
+                if let Some(upload) = &upload {
+                    let _ = fs::remove_file(upload);
+                }
+                match e {
+                    TrySendError::Full(_) => Err(ApiError(503, "the job queue is full, retry later".to_string())),
+                    TrySendError::Disconnected(_) => Err(ApiError(503, "the server is stopping".to_string())),
+                }
+            }
+        }
+    }
+
+    // takes a place in the queue, rejecting the submission before its body is read if there is none
+    fn reserve(&self) -> Result<QueueSlot<'_>, ApiError> {
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| (queued < self.args.queue_size).then_some(queued + 1))
+            .map_err(|_| ApiError(503, "the job queue is full, retry later".to_string()))?;
+        Ok(QueueSlot { queued: &self.queued, taken: false })
+    }
+
+    // resolves the path of a local input against --input-root, refusing files outside of it
+    fn local_input(&self, input: &Path) -> Result<PathBuf, ApiError> {
+        let root = self.input_root.as_ref().ok_or_else(|| ApiError::forbidden("local inputs are disabled, upload the audio instead"))?;
+        let resolved = fs::canonicalize(root.join(input))
+            .ok()
+            .filter(|resolved| resolved.is_file())
+            .ok_or_else(|| ApiError::bad_request(format!("{:?} does not exist or is not a file", input)))?;
+        if !resolved.starts_with(root) {
+            return Err(ApiError::forbidden(format!("{:?} is outside the input root", input)));
+        }
+        Ok(resolved)
+    }
+
+    fn status(&self, id: u64) -> Result<JobStatus, ApiError> {
+        lock(&self.jobs).get(&id).map(|job| job.status.clone()).ok_or_else(ApiError::not_found)
+    }
+
+    // sends the output of a finished job
+    fn download(&self, id: u64) -> Result<ResponseBox, ApiError> {
+        let jobs = lock(&self.jobs);
+        let job = jobs.get(&id).ok_or_else(ApiError::not_found)?;
+        if job.status.state != JobState::Done {
+            return Err(ApiError(409, format!("job {} is {:?}, not done", id, job.status.state).to_lowercase()));
+        }
+        let file = File::open(&job.output_path).map_err(|e| ApiError(500, e.to_string()))?;
+        let name = job.output_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let response = with_header(Response::from_file(file), "Content-Type", "application/octet-stream");
+        Ok(with_header(response, "Content-Disposition", &format!("attachment; filename=\"{}\"", name)).boxed())
+    }
+
+    // cancels a job if it is unfinished and removes it with its files
+    fn delete(&self, id: u64) -> Result<ResponseBox, ApiError> {
+        let job = lock(&self.jobs).remove(&id).ok_or_else(ApiError::not_found)?;
+        job.cancellation.cancel();
+        // an output still being written is removed by its worker
+        for path in job.upload.iter().chain([&job.output_path]) {
+            let _ = fs::remove_file(path);
+        }
+        info!("Job {}: deleted", id);
+        Ok(Response::empty(204).boxed())
+    }
+}
+
+// runs queued jobs until the queue is closed
+fn work(receiver: &Mutex<Receiver<QueuedJob>>, jobs: &Jobs, queued: &AtomicUsize, timeout: Option<u64>) {
+    loop {
+        let next = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let Ok(QueuedJob { id, input, output_path, mut options }) = next else {
+            return;
+        };
+        queued.fetch_sub(1, Ordering::SeqCst);
+        match lock(jobs).get_mut(&id) {
+            Some(job) if job.status.state == JobState::Queued => job.status.state = JobState::Running,
+            // deleted while queued
+            _ => continue,
+        }
+        // --timeout limits every job rather than the whole server
+        if let Some(timeout) = timeout {
+            options.cancellation = options.cancellation.with_timeout(Duration::from_secs(timeout));
+        }
+
+        info!("Job {}: transcoding {:?}", id, input);
+        let result = transcoders::transcode_audio(&input, &output_path, &options);
+
+        let mut jobs = lock(jobs);
+        let Some(job) = jobs.get_mut(&id) else {
+            let _ = fs::remove_file(&output_path);
+            continue;
+        };
+        let status = &mut job.status;
+        match result {
+            Ok(()) => {
+                info!("Job {}: done", id);
+                (status.state, status.progress) = (JobState::Done, Some(100.0));
+            }
+            Err(TranscoderError::Cancelled(reason)) => {
+                info!("Job {}: cancelled, {}", id, reason);
+                (status.state, status.error) = (JobState::Cancelled, Some(reason));
+            }
+            Err(e) => {
+                error!("Job {}: failed: {}", id, e);
+                (status.state, status.error) = (JobState::Failed, Some(e.to_string()));
+            }
+        }
+        if let Some(upload) = job.upload.take() {
+            let _ = fs::remove_file(upload);
+        }
+    }
+}
+
+// writes the request body to a new file in `work_dir`, failing if it exceeds `limit` bytes
+fn receive_upload(request: &mut Request, work_dir: &Path, limit: u64) -> Result<PathBuf, ApiError> {
+    let too_large = || ApiError(413, format!("uploads are limited to {} MB", limit / 1024 / 1024));
+    if request.body_length().is_some_and(|length| length as u64 > limit) {
+        return Err(too_large());
+    }
+    // removed again unless the upload is complete
+    let mut file = tempfile::Builder::new()
+        .prefix("upload-")
+        .tempfile_in(work_dir)
+        .map_err(|e| ApiError(500, e.to_string()))?;
+    match io::copy(&mut request.as_reader().take(limit + 1), &mut file) {
+        Ok(0) => Err(ApiError::bad_request("the upload is empty")),
+        Ok(bytes) if bytes <= limit => file.into_temp_path().keep().map_err(|e| ApiError(500, e.to_string())),
+        Ok(_) => Err(too_large()),
+        Err(e) => Err(ApiError::bad_request(e.to_string())),
+    }
+}
+
+// splits a query string into decoded key and value pairs
+fn query_pairs(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(key), decode_component(value))
+        })
+        .collect()
+}
+
+// decodes the `%XX` escapes and `+` of a query string component
+fn decode_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| component.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[index]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                index += 2;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn json_response(status: u16, body: &impl Serialize) -> ResponseBox {
+    let json = serde_json::to_string(body).unwrap_or_default();
+    with_header(Response::from_string(json).with_status_code(status), "Content-Type", "application/json").boxed()
+}
+
+fn with_header<R: Read>(response: Response<R>, name: &str, value: &str) -> Response<R> {
+    match Header::from_bytes(name, value) {
+        Ok(header) => response.with_header(header),
+        Err(()) => response,
+    }
+}
+
+// the job table stays usable even if a worker panicked while holding it
+fn lock(jobs: &Jobs) -> MutexGuard<'_, BTreeMap<u64, Job>> {
+    jobs.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Instant;
+    use clap::Parser;
+    use tempfile::TempDir;
+    use crate::cli::{CliArgs, Command};
+    use crate::test_support;
+
+    // a server answering on a free localhost port until dropped
+    struct TestServer {
+        addr: SocketAddr,
+        work_dir: TempDir,
+        cancellation: CancellationToken,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl TestServer {
+        fn start(options: &[&str]) -> Self {
+            let work_dir = tempfile::tempdir().unwrap();
+            let work_path = work_dir.path().to_str().unwrap();
+            let cli = CliArgs::try_parse_from(["rewav", "serve", "--work-dir", work_path].iter().chain(options)).unwrap();
+            let Some(Command::Serve(args)) = cli.command else { unreachable!() };
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let addr = server.server_addr().to_ip().unwrap();
+            let cancellation = CancellationToken::new();
+            let token = cancellation.clone();
+            let work_path = work_dir.path().to_path_buf();
+            let thread = thread::spawn(move || {
+                let input_root = args.input_root.as_deref().map(|root| fs::canonicalize(root).unwrap());
+                run(&server, &args, work_path, input_root, &token)
+            });
+            Self { addr, work_dir, cancellation, thread: Some(thread) }
+        }
+
+        // sends a request and returns the status code, headers and body of the response
+        fn request(&self, method: &str, path: &str, content_type: &str, body: &[u8]) -> (u16, String, Vec<u8>) {
+            let mut stream = TcpStream::connect(self.addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+            let head = format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                method, path, content_type, body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            // the server may answer before reading the body
+            let _ = stream.write_all(body);
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8_lossy(&response[..split]).into_owned();
+            let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+            let mut body = response[split + 4..].to_vec();
+            // large responses are sent in chunks
+            if head.contains("Transfer-Encoding: chunked") {
+                body = dechunk(&body);
+            }
+            (status, head, body)
+        }
+
+        fn upload(&self, query: &str, audio: &[u8]) -> (u16, serde_json::Value) {
+            let (status, _, body) = self.request("POST", &format!("/jobs?{}", query), "audio/wav", audio);
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        fn submit_local(&self, submission: serde_json::Value) -> (u16, serde_json::Value) {
+            let (status, _, body) = self.request("POST", "/jobs", "application/json", submission.to_string().as_bytes());
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        fn status(&self, id: u64) -> (u16, serde_json::Value) {
+            let (status, _, body) = self.request("GET", &format!("/jobs/{}", id), "text/plain", b"");
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        fn wait_until_finished(&self, id: u64) -> serde_json::Value {
+            let start = Instant::now();
+            loop {
+                let (_, status) = self.status(id);
+                if !matches!(status["state"].as_str(), Some("queued" | "running")) {
+                    return status;
+                }
+                assert!(start.elapsed() < Duration::from_secs(60), "job {} did not finish", id);
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        fn files(&self) -> Vec<String> {
+            fs::read_dir(self.work_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect()
+        }
+    }
+
+    fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let line_end = chunked.windows(2).position(|window| window == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&chunked[..line_end]).unwrap().trim(), 16).unwrap();
+            if size == 0 {
+                return body;
+            }
+            body.extend_from_slice(&chunked[line_end + 2..line_end + 2 + size]);
+            chunked = &chunked[line_end + 2 + size + 2..];
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            self.cancellation.cancel();
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn transcodes_an_upload_until_deleted() {
+        let server = TestServer::start(&[]);
+        let (status, head, body) = server.request("POST", "/jobs?format=wav&sample-rate=16000", "audio/wav", &test_support::sine_wav(8000, 8000));
+        assert_eq!(status, 202);
+        assert!(head.contains("Location: /jobs/1"), "{}", head);
+        let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(job["input"], "upload");
+
+        let job = server.wait_until_finished(1);
+        assert_eq!(job["state"], "done", "{}", job);
+        assert_eq!(job["progress"], 100.0);
+        let (status, _, output) = server.request("GET", "/jobs/1/output", "text/plain", b"");
+        assert_eq!(status, 200);
+        let reader = hound::WavReader::new(io::Cursor::new(output)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16000);
+        assert_eq!(reader.duration(), 16000);
+
+        let (status, _, _) = server.request("DELETE", "/jobs/1", "text/plain", b"");
+        assert_eq!(status, 204);
+        assert_eq!(server.status(1).0, 404);
+        assert_eq!(server.request("GET", "/jobs/1/output", "text/plain", b"").0, 404);
+        assert!(server.files().is_empty(), "{:?}", server.files());
+    }
+
+    #[test]
+    fn rejects_submissions_once_the_queue_is_full() {
+        // without workers, queued jobs stay in the queue
+        let server = TestServer::start(&["--workers", "0", "--queue-size", "1"]);
+        let audio = test_support::sine_wav(8000, 800);
+        assert_eq!(server.upload("format=wav", &audio).0, 202);
+        let (status, error) = server.upload("format=wav", &audio);
+        assert_eq!(status, 503, "{}", error);
+        // the rejected upload is not kept
+        assert_eq!(server.files().len(), 1, "{:?}", server.files());
+        assert_eq!(server.status(1).1["state"], "queued");
+        assert_eq!(server.status(2).0, 404);
+        // the output of an unfinished job cannot be downloaded
+        assert_eq!(server.request("GET", "/jobs/1/output", "text/plain", b"").0, 409);
+    }
+
+    #[test]
+    fn answers_while_an_upload_is_received() {
+        let server = TestServer::start(&[]);
+        let mut upload = TcpStream::connect(server.addr).unwrap();
+        upload.write_all(b"POST /jobs?format=wav HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000000\r\n\r\n").unwrap();
+        upload.write_all(&[0; 1000]).unwrap();
+        let (status, _, body) = server.request("GET", "/jobs", "text/plain", b"");
+        assert_eq!(status, 200);
+        assert_eq!(body, b"[]");
+    }
+
+    #[test]
+    fn rejects_uploads_over_the_limit() {
+        let server = TestServer::start(&["--max-upload", "1"]);
+        let (status, error) = server.upload("format=wav", &vec![0; 1024 * 1024 + 1]);
+        assert_eq!(status, 413, "{}", error);
+        assert_eq!(server.upload("format=wav", &[]).0, 400);
+        assert!(server.files().is_empty(), "{:?}", server.files());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let server = TestServer::start(&[]);
+        let audio = test_support::sine_wav(8000, 800);
+        assert_eq!(server.upload("", &audio).0, 400);
+        assert_eq!(server.upload("format=wav&bitrate=fast", &audio).0, 400);
+        assert_eq!(server.upload("format=wav&unknown=1", &audio).0, 400);
+        assert_eq!(server.upload("format=../wav", &audio).0, 400);
+    }
+
+    #[test]
+    fn keeps_server_settings_from_clients() {
+        let server = TestServer::start(&[]);
+        let audio = test_support::sine_wav(8000, 800);
+        for setting in ["ffmpeg-path=/bin/sh", "threads=64", "overwrite=false", "af=amovie=/etc/shadow", "af=ametadata=mode=print:file=/tmp/x"] {
+            let (status, error) = server.upload(&format!("format=mp3&{}", setting), &audio);
+            assert_eq!(status, 403, "{}: {}", setting, error);
+        }
+        assert!(server.files().is_empty(), "{:?}", server.files());
+        let (status, job) = server.upload("format=wav&af=volume=0.5,lowpass=f=1000", &audio);
+        assert_eq!(status, 202, "{}", job);
+    }
+
+    #[test]
+    fn limits_local_inputs_to_the_input_root() {
+        let server = TestServer::start(&[]);
+        let (status, _) = server.submit_local(serde_json::json!({ "input": "/etc/passwd", "format": "wav" }));
+        assert_eq!(status, 403);
+
+        let root = tempfile::tempdir().unwrap();
+        test_support::write_sine_wav(&root.path().join("tone.wav"), 8000, 800);
+        let server = TestServer::start(&["--input-root", root.path().to_str().unwrap()]);
+        for input in ["/etc/passwd", "../../../../../../etc/passwd"] {
+            let (status, error) = server.submit_local(serde_json::json!({ "input": input, "format": "wav" }));
+            assert_eq!(status, 403, "{}: {}", input, error);
+        }
+        assert_eq!(server.submit_local(serde_json::json!({ "input": "missing.wav", "format": "wav" })).0, 400);
+
+        let (status, job) = server.submit_local(serde_json::json!({ "input": "tone.wav", "format": "wav", "options": { "channels": 1 } }));
+        assert_eq!(status, 202, "{}", job);
+        assert_eq!(server.wait_until_finished(job["id"].as_u64().unwrap())["state"], "done");
+    }
+}
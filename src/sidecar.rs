@@ -0,0 +1,236 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use crate::errors::TranscoderError;
+use crate::manifest::{self, InputState};
+use crate::operations::fan_out;
+use crate::transcoders::{OutputFingerprint, TranscodeOptions};
+use crate::utils;
+
+/// what an output was produced from, stored next to it in a hidden `.<output name>.rewav.json` file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// version of rewav that wrote the output, since encoders may change between versions
+    pub version: String,
+    /// absolute input path
+    pub input: PathBuf,
+    pub input_hash: String,
+    /// size of the input when it was hashed
+    pub input_size: u64,
+    /// modification time of the input when it was hashed, in nanoseconds since the Unix epoch
+    pub input_modified: Option<u64>,
+    /// the transcode options the output depends on
+    pub options: OutputFingerprint,
+}
+
+impl Sidecar {
+    fn new(input: &Path, state: &InputState, options: &TranscodeOptions) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            input: input.to_path_buf(),
+            input_hash: state.hash.clone(),
+            input_size: state.size,
+            input_modified: state.modified,
+            options: options.fingerprint(),
+        }
+    }
+
+    fn input_state(&self) -> InputState {
+        InputState { hash: self.input_hash.clone(), size: self.input_size, modified: self.input_modified }
+    }
+}
+
+/// transcodes the input to the outputs that are missing or out of date, skipping outputs whose sidecar shows they were
+/// written by this version of rewav from the same input content with the same options; sidecars are written once the
+/// outputs are complete, and outputs with a sidecar are replaced when out of date
+pub fn transcode_if_changed(input_path: &Path, outputs: &[(PathBuf, TranscodeOptions)]) -> Result<(), TranscoderError> {
+    if utils::is_stdio(input_path) || outputs.iter().any(|(path, _)| utils::is_stdio(path)) {
+        return Err(TranscoderError::Argument("--if-changed requires files as input and outputs, not stdin or stdout".to_string()));
+    }
+    let input = std::path::absolute(input_path)?;
+    let sidecars: Vec<Option<Sidecar>> = outputs.iter().map(|(path, _)| read(path)).collect();
+    // a sidecar of the same input spares hashing it if its size and modification time are unchanged
+    let recorded = sidecars.iter().flatten().find(|sidecar| sidecar.input == input).map(Sidecar::input_state);
+    let state = manifest::input_state(&input, recorded.as_ref())?;
+
+    let mut pending = Vec::new();
+    for ((path, options), sidecar) in outputs.iter().zip(&sidecars) {
+        let expected = Sidecar::new(&input, &state, options);
+        match sidecar {
+            Some(sidecar) if path.exists() && is_up_to_date(sidecar, &expected) => {
+                info!("{:?} is up to date, skipping it", path);
+                // recording the current size and modification time of an unchanged input spares hashing it again
+                if *sidecar != expected {
+                    write(path, &expected)?;
+                }
+            }
+            // outputs with a sidecar were written by rewav and may be replaced
+            Some(_) => pending.push((path.clone(), TranscodeOptions { overwrite: true, ..options.clone() })),
+            None => pending.push((path.clone(), options.clone())),
+        }
+    }
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    fan_out::transcode_to_outputs(input_path, &pending)?;
+    for (path, options) in &pending {
+        write(path, &Sidecar::new(&input, &state, options))?;
+    }
+    Ok(())
+}
+
+// the recorded input size and modification time may differ from the current ones without the content changing
+fn is_up_to_date(sidecar: &Sidecar, expected: &Sidecar) -> bool {
+    sidecar.version == expected.version
+        && sidecar.input == expected.input
+        && sidecar.input_hash == expected.input_hash
+        && sidecar.options == expected.options
+}
+
+fn sidecar_path(output_path: &Path) -> PathBuf {
+    let name = output_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    output_path.with_file_name(format!(".{}.rewav.json", name))
+}
+
+// reads the sidecar of an output, None if it is missing or unreadable
+fn read(output_path: &Path) -> Option<Sidecar> {
+    let path = sidecar_path(output_path);
+    let content = std::fs::read_to_string(&path).ok()?;
+    serde_json::from_str(&content)
+        .inspect_err(|e| debug!("Ignoring the invalid sidecar {:?}: {}", path, e))
+        .ok()
+}
+
+// replaces the sidecar of an output atomically
+fn write(output_path: &Path, sidecar: &Sidecar) -> Result<(), TranscoderError> {
+    let path = sidecar_path(output_path);
+    let mut temp = utils::create_temp_sibling(&path)?;
+    writeln!(temp, "{}", serde_json::to_string_pretty(sidecar).map_err(io::Error::from)?)?;
+    temp.persist(&path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+    use crate::test_support;
+
+    // an input and the output it is transcoded to in a fresh directory
+    struct Fixture {
+        dir: TempDir,
+        input: PathBuf,
+        output: PathBuf,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            let input = dir.path().join("in.wav");
+            test_support::write_sine_wav(&input, 44100, 4410);
+            let output = dir.path().join("out.wav");
+            Self { dir, input, output }
+        }
+
+        // transcodes the input if needed and tells whether the output was written
+        fn transcode(&self, options: &TranscodeOptions) -> bool {
+            let before = self.output.metadata().and_then(|metadata| metadata.modified()).ok();
+            // modification times may be too coarse to tell consecutive writes apart
+            if before.is_some() {
+                set_modified(&self.output, SystemTime::now() - Duration::from_secs(60));
+            }
+            let before = self.output.metadata().and_then(|metadata| metadata.modified()).ok();
+            transcode_if_changed(&self.input, &[(self.output.clone(), options.clone())]).unwrap();
+            self.output.metadata().unwrap().modified().ok() != before
+        }
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        std::fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    fn options() -> TranscodeOptions {
+        TranscodeOptions { output_format_extension: "wav".to_string(), sample_rate: Some(22050), ..TranscodeOptions::default() }
+    }
+
+    #[test]
+    fn skips_up_to_date_outputs() {
+        let fixture = Fixture::new();
+        assert!(fixture.transcode(&options()));
+        assert!(sidecar_path(&fixture.output).exists());
+        assert!(!fixture.transcode(&options()));
+        // settings that do not shape the output keep it up to date
+        assert!(!fixture.transcode(&TranscodeOptions { threads: Some(3), ffmpeg_path: Some("/opt/ffmpeg".into()), ..options() }));
+    }
+
+    #[test]
+    fn transcodes_again_when_the_options_change() {
+        let fixture = Fixture::new();
+        assert!(fixture.transcode(&options()));
+        assert!(fixture.transcode(&TranscodeOptions { sample_rate: Some(16000), ..options() }));
+        assert_eq!(read(&fixture.output).unwrap().options.sample_rate, Some(16000));
+    }
+
+    #[test]
+    fn transcodes_again_when_the_input_changes() {
+        let fixture = Fixture::new();
+        assert!(fixture.transcode(&options()));
+        test_support::write_sine_wav(&fixture.input, 44100, 8820);
+        assert!(fixture.transcode(&options()));
+    }
+
+    #[test]
+    fn transcodes_again_when_the_output_is_deleted() {
+        let fixture = Fixture::new();
+        assert!(fixture.transcode(&options()));
+        std::fs::remove_file(&fixture.output).unwrap();
+        assert!(fixture.transcode(&options()));
+        assert!(fixture.output.exists());
+    }
+
+    #[test]
+    fn keeps_outputs_it_did_not_write() {
+        let fixture = Fixture::new();
+        std::fs::write(&fixture.output, b"not written by rewav").unwrap();
+        let result = transcode_if_changed(&fixture.input, &[(fixture.output.clone(), options())]);
+        assert!(matches!(result, Err(TranscoderError::Path(_))), "{:?}", result);
+        assert_eq!(std::fs::read(&fixture.output).unwrap(), b"not written by rewav");
+    }
+
+    #[test]
+    fn records_a_touched_input_without_transcoding() {
+        let fixture = Fixture::new();
+        assert!(fixture.transcode(&options()));
+        set_modified(&fixture.input, SystemTime::now() - Duration::from_secs(3600));
+        assert!(!fixture.transcode(&options()));
+        let sidecar = read(&fixture.output).unwrap();
+        let modified = manifest::input_state(&fixture.input, None).unwrap().modified;
+        assert_eq!(sidecar.input_modified, modified);
+    }
+
+    #[test]
+    fn compares_everything_but_the_input_timestamps() {
+        let fixture = Fixture::new();
+        let state = InputState { hash: "a".to_string(), size: 1, modified: Some(2) };
+        let expected = Sidecar::new(&fixture.input, &state, &options());
+        let touched = Sidecar { input_size: 3, input_modified: None, ..expected.clone() };
+        assert!(is_up_to_date(&touched, &expected));
+        for outdated in [
+            Sidecar { version: "0.0.1".to_string(), ..expected.clone() },
+            Sidecar { input: fixture.dir.path().join("other.wav"), ..expected.clone() },
+            Sidecar { input_hash: "b".to_string(), ..expected.clone() },
+            Sidecar { options: TranscodeOptions { channels: Some(1), ..options() }.fingerprint(), ..expected.clone() },
+        ] {
+            assert!(!is_up_to_date(&outdated, &expected), "{:?}", outdated);
+        }
+    }
+
+    #[test]
+    fn rejects_stdio() {
+        let result = transcode_if_changed(Path::new("-"), &[(PathBuf::from("out.wav"), options())]);
+        assert!(matches!(result, Err(TranscoderError::Argument(_))));
+    }
+}
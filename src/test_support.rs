@@ -0,0 +1,46 @@
+//! helpers shared by the unit tests
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// a stereo 16-bit WAV file holding `frames` frames of a 440 Hz tone in the left and a 660 Hz tone in the right channel
+pub fn sine_wav(sample_rate: u32, frames: usize) -> Vec<u8> {
+    let spec = hound::WavSpec { channels: 2, sample_rate, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+    for frame in 0..frames {
+        let t = frame as f64 / sample_rate as f64;
+        for frequency in [440.0, 660.0] {
+            writer.write_sample((0.5 * (2.0 * std::f64::consts::PI * frequency * t).sin() * 32767.0) as i16).unwrap();
+        }
+    }
+    writer.finalize().unwrap();
+    cursor.into_inner()
+}
+
+/// writes `sine_wav` to `path`
+pub fn write_sine_wav(path: &Path, sample_rate: u32, frames: usize) {
+    std::fs::write(path, sine_wav(sample_rate, frames)).unwrap();
+}
+
+/// writes a stand-in for ffmpeg to `dir` that reports an aac/libmp3lame build, saves its arguments to `args` and its
+/// input to `input` next to it, and writes `out` to every output
+#[cfg(unix)]
+pub fn fake_ffmpeg(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let script = r#"#!/bin/sh
+case "$2" in
+-version) echo "ffmpeg version 6.1 Copyright (c) 2000-2023 the FFmpeg developers"; exit 0;;
+-encoders) printf ' ------\n A....D aac                  AAC\n A....D libmp3lame           MP3\n'; exit 0;;
+-formats) printf ' --\n DE f32le           PCM\n  E ipod            iPod\n  E mp3             MP3\n  E mp4             MP4\n'; exit 0;;
+esac
+dir=$(dirname "$0")
+echo "$@" > "$dir/args"
+cat > "$dir/input"
+prev=; for a; do [ "$prev" = "-y" ] && echo out > "$a"; prev=$a; done
+exit 0
+"#;
+    let path = dir.join("ffmpeg");
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
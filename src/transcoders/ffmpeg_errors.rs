@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+use crate::errors::TranscoderError;
+
+// classifiers tried in order of precedence, since follow-up errors are often printed after the root cause
+const CLASSIFIERS: [Classifier; 5] = [
+    unknown_encoder,
+    permission_denied,
+    unsupported_sample_rate,
+    unsupported_channel_layout,
+    corrupt_input,
+];
+
+// maps one stderr line onto a typed error; the second argument is the codec assumed when the line does not name one
+type Classifier = fn(&str, &str) -> Option<TranscoderError>;
+
+/// turns the stderr of a failed ffmpeg run into a specific `TranscoderError` carrying the offending parameter
+/// returns None if no known failure was recognized
+pub fn parse_ffmpeg_failure(stderr: &str, requested_codec: Option<&str>) -> Option<TranscoderError> {
+    let codec = requested_codec.unwrap_or("output");
+    CLASSIFIERS.iter().find_map(|classify| stderr.lines().find_map(|line| classify(line.trim(), codec)))
+}
+
+// `Unknown encoder 'libfdk_aac'`
+fn unknown_encoder(line: &str, _codec: &str) -> Option<TranscoderError> {
+    let name = quoted_after(line, "Unknown encoder ")?;
+    Some(TranscoderError::UnknownEncoder(name.to_string()))
+}
+
+// `/music/out.m4a: Permission denied` or `[out#0/mp4 @ 0x5581] Error opening output /music/out.m4a: Permission denied`
+fn permission_denied(line: &str, _codec: &str) -> Option<TranscoderError> {
+    let subject = strip_context(line.strip_suffix(": Permission denied")?);
+    let path = ["Error opening output file ", "Error opening output ", "Error opening input file ", "Error opening input "]
+        .iter()
+        .find_map(|prefix| subject.strip_prefix(prefix))
+        .unwrap_or(subject);
+    Some(TranscoderError::PermissionDenied(PathBuf::from(path)))
+}
+
+// `[libmp3lame @ 0x5581] Specified sample rate 96000 is not supported`
+fn unsupported_sample_rate(line: &str, codec: &str) -> Option<TranscoderError> {
+    let (_, rest) = line.split_once("Specified sample rate ")?;
+    let sample_rate = rest.split_whitespace().next()?.parse().ok()?;
+    Some(TranscoderError::UnsupportedSampleRate {
+        codec: context_name(line).unwrap_or(codec).to_string(),
+        sample_rate,
+    })
+}
+
+// `[libmp3lame @ 0x5581] Specified channel layout '5.1' is not supported by the libmp3lame encoder`
+fn unsupported_channel_layout(line: &str, codec: &str) -> Option<TranscoderError> {
+    let layout = quoted_after(line, "Specified channel layout ")?;
+    let codec = line
+        .split_once("by the ")
+        .and_then(|(_, rest)| rest.strip_suffix(" encoder"))
+        .or_else(|| context_name(line))
+        .unwrap_or(codec);
+    Some(TranscoderError::UnsupportedChannelLayout {
+        codec: codec.to_string(),
+        layout: layout.to_string(),
+    })
+}
+
+// `input.mp3: Invalid data found when processing input` or `[mov,mp4,m4a @ 0x5581] moov atom not found`
+fn corrupt_input(line: &str, _codec: &str) -> Option<TranscoderError> {
+    let is_corrupt = line.ends_with("Invalid data found when processing input") || line.ends_with("moov atom not found");
+    is_corrupt.then(|| TranscoderError::CorruptInput(line.to_string()))
+}
+
+// the text between the single quotes following `marker`
+fn quoted_after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(marker)?;
+    let rest = rest.strip_prefix('\'')?;
+    rest.split_once('\'').map(|(quoted, _)| quoted)
+}
+
+// the component name of a `[name @ 0x...]` log context
+fn context_name(line: &str) -> Option<&str> {
+    let (name, _) = line.strip_prefix('[')?.split_once(" @ ")?;
+    Some(name)
+}
+
+// removes a leading `[name @ 0x...]` log context
+fn strip_context(line: &str) -> &str {
+    match line.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+        Some((_, message)) => message,
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // the banner ffmpeg prints before any error
+    const BANNER: &str = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers
+  built with gcc 13 (Ubuntu 13.2.0-23ubuntu3)
+  libavutil      58. 29.100 / 58. 29.100
+  libavcodec     60. 31.102 / 60. 31.102
+";
+
+    fn parse(stderr: &str, requested_codec: Option<&str>) -> Option<TranscoderError> {
+        parse_ffmpeg_failure(&format!("{}{}", BANNER, stderr), requested_codec)
+    }
+
+    #[test]
+    fn recognizes_unknown_encoders() {
+        let stderr = "Input #0, wav, from 'in.wav':
+  Duration: 00:00:03.00, bitrate: 1411 kb/s
+  Stream #0:0: Audio: pcm_s16le ([1][0][0][0] / 0x0001), 44100 Hz, 2 channels, s16, 1411 kb/s
+Unknown encoder 'libfdk_aac'
+";
+        let error = parse(stderr, Some("libfdk_aac"));
+        assert!(matches!(error, Some(TranscoderError::UnknownEncoder(name)) if name == "libfdk_aac"));
+    }
+
+    #[test]
+    fn recognizes_unwritable_outputs() {
+        let stderr = "[out#0/mp4 @ 0x55d0c5a3b2c0] Error opening output /music/out.m4a: Permission denied
+Error opening output file /music/out.m4a.
+Error opening output files: Permission denied
+";
+        let error = parse(stderr, None);
+        assert!(matches!(error, Some(TranscoderError::PermissionDenied(path)) if path == Path::new("/music/out.m4a")));
+
+        // ffmpeg before 6.1 printed the bare path
+        let error = parse("/music/out.mp3: Permission denied\n", None);
+        assert!(matches!(error, Some(TranscoderError::PermissionDenied(path)) if path == Path::new("/music/out.mp3")));
+    }
+
+    #[test]
+    fn recognizes_unsupported_sample_rates() {
+        let stderr = "[libmp3lame @ 0x5581e0c3c840] Specified sample rate 96000 is not supported
+[aost#0:0/libmp3lame @ 0x5581e0c3b780] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.
+Error while filtering: Invalid argument
+[out#0/mp3 @ 0x5581e0c3a900] Nothing was written into output file, because at least one of its streams received no packets.
+";
+        let error = parse(stderr, Some("mp3"));
+        assert!(matches!(
+            error,
+            Some(TranscoderError::UnsupportedSampleRate { codec, sample_rate: 96000 }) if codec == "libmp3lame"
+        ));
+    }
+
+    #[test]
+    fn recognizes_unsupported_channel_layouts() {
+        let stderr = "[libmp3lame @ 0x55c4c8a1e700] Specified channel layout '5.1' is not supported by the libmp3lame encoder
+[libmp3lame @ 0x55c4c8a1e700] Supported channel layouts:
+[libmp3lame @ 0x55c4c8a1e700]   mono
+[libmp3lame @ 0x55c4c8a1e700]   stereo
+[aost#0:0/libmp3lame @ 0x55c4c8a1d640] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.
+";
+        let error = parse(stderr, Some("mp3"));
+        assert!(matches!(
+            error,
+            Some(TranscoderError::UnsupportedChannelLayout { codec, layout }) if codec == "libmp3lame" && layout == "5.1"
+        ));
+    }
+
+    #[test]
+    fn recognizes_corrupt_inputs() {
+        let stderr = "[mp3 @ 0x5617a4e6b9c0] Failed to read frame size: Could not seek to 1026.
+[in#0 @ 0x5617a4e6b800] Error opening input: Invalid data found when processing input
+Error opening input file broken.mp3.
+Error opening input files: Invalid data found when processing input
+";
+        assert!(matches!(parse(stderr, None), Some(TranscoderError::CorruptInput(_))));
+
+        let stderr = "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x55f0f3f0a9c0] moov atom not found
+truncated.m4a: Invalid data found when processing input
+";
+        assert!(matches!(parse(stderr, None), Some(TranscoderError::CorruptInput(line)) if line.ends_with("moov atom not found")));
+    }
+
+    #[test]
+    fn prefers_the_root_cause() {
+        // the encoder error is printed before a follow-up corrupt input message and still wins
+        let stderr = "broken.wav: Invalid data found when processing input
+Unknown encoder 'libopus'
+";
+        assert!(matches!(parse(stderr, Some("libopus")), Some(TranscoderError::UnknownEncoder(_))));
+    }
+
+    #[test]
+    fn falls_back_to_the_requested_codec() {
+        let error = parse("Specified sample rate 8000 is not supported\n", Some("aac"));
+        assert!(matches!(error, Some(TranscoderError::UnsupportedSampleRate { codec, .. }) if codec == "aac"));
+        let error = parse("Specified sample rate 8000 is not supported\n", None);
+        assert!(matches!(error, Some(TranscoderError::UnsupportedSampleRate { codec, .. }) if codec == "output"));
+    }
+
+    #[test]
+    fn ignores_unknown_failures() {
+        let stderr = "[aost#0:0/libopus @ 0x55e5] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.
+Conversion failed!
+";
+        assert!(parse(stderr, Some("libopus")).is_none());
+        assert!(parse_ffmpeg_failure("", None).is_none());
+    }
+}
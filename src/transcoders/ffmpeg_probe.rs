@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use log::{debug, info, warn};
+use crate::errors::TranscoderError;
+
+/// environment variable pointing to the ffmpeg binary when `--ffmpeg-path` is not given
+pub const FFMPEG_PATH_ENV: &str = "REWAV_FFMPEG";
+
+// oldest release providing the `-progress` keys and options the transcoder relies on
+const MIN_MAJOR_VERSION: u32 = 4;
+
+/// version and available components of an ffmpeg binary
+#[derive(Debug)]
+pub struct FfmpegCapabilities {
+    /// path the binary is invoked with
+    pub path: PathBuf,
+    /// version string as printed by `ffmpeg -version` (e.g. `6.1.1` or `N-113245-g1234abcd` for git builds)
+    pub version: String,
+    audio_encoders: HashSet<String>,
+    muxers: HashSet<String>,
+    demuxers: HashSet<String>,
+}
+
+impl FfmpegCapabilities {
+    /// probes the ffmpeg binary selected by `--ffmpeg-path`, `REWAV_FFMPEG` or `PATH`, in this order
+    /// results are cached per path, so that the binary is only probed once per process
+    pub fn probe(explicit_path: Option<&Path>) -> Result<Arc<Self>, TranscoderError> {
+        static PROBED: OnceLock<Mutex<HashMap<PathBuf, Arc<FfmpegCapabilities>>>> = OnceLock::new();
+
+        let path = resolve_ffmpeg_path(explicit_path);
+        let mut probed = PROBED.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(capabilities) = probed.get(&path) {
+            return Ok(Arc::clone(capabilities));
+        }
+
+        let capabilities = Arc::new(Self::probe_uncached(path.clone())?);
+        probed.insert(path, Arc::clone(&capabilities));
+        Ok(capabilities)
+    }
+
+    fn probe_uncached(path: PathBuf) -> Result<Self, TranscoderError> {
+        let version_output = run_ffmpeg(&path, "-version")?;
+        let version = version_output
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("ffmpeg version "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .ok_or_else(|| TranscoderError::FfmpegCli(format!("{} does not look like an ffmpeg binary", path.display())))?
+            .to_string();
+
+        match parse_major_version(&version) {
+            Some(major) if major < MIN_MAJOR_VERSION => {
+                return Err(TranscoderError::FfmpegCli(format!(
+                    "FFmpeg {} at {} is too old, version {}.0 or newer is required", version, path.display(), MIN_MAJOR_VERSION
+                )));
+            }
+            Some(_) => {}
+            // git builds carry no release number
+            None => warn!("Could not determine the release of FFmpeg {}, assuming it is recent enough", version),
+        }
+
+        let audio_encoders = parse_audio_encoders(&run_ffmpeg(&path, "-encoders")?);
+        let (demuxers, muxers) = parse_formats(&run_ffmpeg(&path, "-formats")?);
+
+        info!("Using FFmpeg {} at {}", version, path.display());
+        debug!(
+            "FFmpeg provides {} audio encoders, {} muxers and {} demuxers",
+            audio_encoders.len(), muxers.len(), demuxers.len()
+        );
+
+        Ok(Self { path, version, audio_encoders, muxers, demuxers })
+    }
+
+    /// checks whether ffmpeg can encode audio with the named encoder (e.g. `aac`, `libmp3lame`)
+    pub fn has_audio_encoder(&self, name: &str) -> bool {
+        self.audio_encoders.contains(name)
+    }
+
+    /// checks whether ffmpeg can write the named container format (e.g. `mp4`, `adts`)
+    pub fn has_muxer(&self, name: &str) -> bool {
+        self.muxers.contains(name)
+    }
+
+    /// checks whether ffmpeg can read the named container format
+    pub fn has_demuxer(&self, name: &str) -> bool {
+        self.demuxers.contains(name)
+    }
+}
+
+/// selects the ffmpeg binary: an explicit path wins over `REWAV_FFMPEG`, which wins over looking up `ffmpeg` in `PATH`
+pub fn resolve_ffmpeg_path(explicit_path: Option<&Path>) -> PathBuf {
+    explicit_path
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os(FFMPEG_PATH_ENV).filter(|path| !path.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("ffmpeg"))
+}
+
+// runs `ffmpeg -hide_banner <flag>` and returns its stdout
+fn run_ffmpeg(path: &Path, flag: &str) -> Result<String, TranscoderError> {
+    let output = Command::new(path)
+        .arg("-hide_banner")
+        .arg(flag)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| TranscoderError::FfmpegCli(format!(
+            "Failed to run FFmpeg at {}: {}. Please install ffmpeg, add it to your PATH, or point --ffmpeg-path or {} to it",
+            path.display(), e, FFMPEG_PATH_ENV
+        )))?;
+
+    if !output.status.success() {
+        return Err(TranscoderError::FfmpegCli(format!(
+            "`{} {}` exited with status {:?}", path.display(), flag, output.status.code()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// extracts the major release from versions like `6.1.1`, `n5.1.4` or `4.4.2-0ubuntu0.22.04.1`
+fn parse_major_version(version: &str) -> Option<u32> {
+    let digits: String = version
+        .trim_start_matches('n')
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+// parses the listing of `ffmpeg -encoders`, e.g. ` A....D aac    AAC (Advanced Audio Coding)`
+fn parse_audio_encoders(listing: &str) -> HashSet<String> {
+    listing
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            let name = fields.next()?;
+            flags.starts_with('A').then(|| name.to_string())
+        })
+        .collect()
+}
+
+// parses the listing of `ffmpeg -formats` into (demuxers, muxers), e.g. ` DE mp3    MP3 (MPEG audio layer 3)`
+// flags form a fixed-width column after a single leading space, names may be comma-separated aliases
+fn parse_formats(listing: &str) -> (HashSet<String>, HashSet<String>) {
+    let mut demuxers = HashSet::new();
+    let mut muxers = HashSet::new();
+
+    for line in listing.lines().skip_while(|line| !line.trim_start().starts_with("--")).skip(1) {
+        let Some(columns) = line.strip_prefix(' ') else {
+            continue;
+        };
+        let flags: Vec<char> = columns.chars().take(2).collect();
+        // newer releases add a third flag marking devices
+        let rest = columns.get(2..).unwrap_or_default().trim_start_matches('d');
+        let Some(names) = rest.split_whitespace().next() else {
+            continue;
+        };
+
+        for name in names.split(',') {
+            if flags.first() == Some(&'D') {
+                demuxers.insert(name.to_string());
+            }
+            if flags.get(1) == Some(&'E') {
+                muxers.insert(name.to_string());
+            }
+        }
+    }
+
+    (demuxers, muxers)
+}
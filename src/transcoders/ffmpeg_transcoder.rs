@@ -1,20 +1,288 @@
-use std::path::Path;
-use std::process::Command;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 use log::{info, debug, warn, error};
+use crate::audio_processor::{biquad::BiquadSpec, level};
 use crate::errors::TranscoderError;
-use crate::transcoders::TranscodeOptions;
+use crate::codecs::{self, raw::RawPcmFormat, StreamSpec, Tags};
+use crate::format_detection::{ffmpeg_format_name, DetectedFormat, DetectionSource};
+use crate::presets::{self, PresetCodec};
+use crate::progress::{Progress, ProgressCallback};
+use crate::transcoders::{self, BitrateMode, TranscodeOptions};
+use crate::transcoders::ffmpeg_errors::parse_ffmpeg_failure;
+use crate::transcoders::ffmpeg_probe::FfmpegCapabilities;
+use crate::utils;
+use tempfile::NamedTempFile;
+
+// how often the cancellation token is checked while ffmpeg runs silently
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 /// transcodes an audio file from any ffmpeg-supported audio format to any other ffmpeg-supported audio format using `ffmpeg-next` library
 pub fn transcode_with_ffmpeg(
     input_path: &Path,
+    input_format: &DetectedFormat,
     output_path: &Path,
     options: &TranscodeOptions,
 ) -> Result<(), TranscoderError> {
     info!("FFmpeg transcoder: Converting {:?} to {:?} with options: {:?}", input_path, output_path, options);
 
-    let mut command = Command::new("ffmpeg");
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument(
+            "--normalize-peak is only supported when both the input and output formats are handled natively".to_string(),
+        ));
+    }
+    if options.trim_silence.is_some() {
+        return Err(TranscoderError::Argument("--trim-silence is applied natively and cannot be run by FFmpeg".to_string()));
+    }
+
+    let options = &with_preset_options(options);
+
+    // failing early with a precise error instead of ffmpeg's stderr when a component is missing
+    let ffmpeg = FfmpegCapabilities::probe(options.ffmpeg_path.as_deref())?;
+    validate_capabilities(&ffmpeg, input_format, options)?;
+
+    let mut command = Command::new(&ffmpeg.path);
+
+    // progress is reported as key=value lines on stdout, or on stderr when the audio itself is streamed to stdout
+    let streams_to_stdout = utils::is_stdio(output_path);
+    if options.progress.is_some() {
+        command.arg("-progress").arg(if streams_to_stdout { "pipe:2" } else { "pipe:1" }).arg("-nostats");
+    }
+
+    // explicit formats are forced, otherwise ffmpeg probes the input and picks the muxer from the extension
+    // raw PCM cannot be probed, so its layout is passed as demuxer options
+    if let Some(demuxer) = input_demuxer(input_format, options) {
+        command.arg("-f").arg(demuxer);
+    }
+    if input_format.format == "raw" {
+        let (sample_rate, channels) = codecs::raw_input_layout(options)?;
+        command.arg("-ar").arg(sample_rate.to_string());
+        command.arg("-ac").arg(channels.to_string());
+    }
+
+    // `-` is handed to ffmpeg as its own stdin/stdout pipes
+    if utils::is_stdio(input_path) {
+        command.arg("-i").arg("pipe:0").stdin(Stdio::inherit());
+    } else {
+        command.arg("-i").arg(input_path).stdin(Stdio::null());
+    }
+
+    add_output_args(&mut command, options);
+
+    // the output is a temp file created beforehand, the overwrite policy is applied when it is moved into place
+    command.arg("-y");
+
+    if utils::is_stdio(output_path) {
+        command.arg("pipe:1").stdout(Stdio::inherit());
+    } else {
+        command.arg(output_path);
+    }
+
+    debug!("Executing FFmpeg: {:?}", command);
+
+    command.stderr(Stdio::piped());
+    if !streams_to_stdout {
+        command.stdout(Stdio::piped());
+    }
+
+    let mut child = command.spawn().map_err(|e| {
+        TranscoderError::Io(std::io::Error::other(
+            format!("Failed to execute FFmpeg at {}: {}", ffmpeg.path.display(), e),
+        ))
+    })?;
+
+    // draining both pipes on their own threads so that ffmpeg never blocks on a full pipe
+    let (sender, receiver) = mpsc::channel();
+    let stdout_reader = child.stdout.take().map(|stdout| forward_lines(stdout, sender.clone(), false));
+    let stderr_reader = child.stderr.take().map(|stderr| forward_lines(stderr, sender, true));
 
-    command.arg("-i").arg(input_path);
+    let mut progress = FfmpegProgress::new(options.progress.clone());
+    loop {
+        match receiver.recv_timeout(CANCELLATION_POLL_INTERVAL) {
+            Ok(line) => progress.parse_line(&line),
+            Err(RecvTimeoutError::Timeout) => {}
+            // both pipes were closed, ffmpeg is exiting
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if let Err(e) = options.cancellation.check() {
+            warn!("Stopping FFmpeg: {}", e);
+            child.kill()?;
+            // reaping the child so that the output file is released before it gets removed
+            child.wait()?;
+            return Err(e);
+        }
+    }
+
+    let status = child.wait()?;
+    if let Some(reader) = stdout_reader {
+        let _ = reader.join();
+    }
+    let stderr = stderr_reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+    if status.success() {
+        info!("FFmpeg successfully transcoded {:?} to {:?}", input_path, output_path);
+        debug!("FFmpeg stderr:\n{}", stderr);
+    } else {
+        error!("FFmpeg CLI failed to transcode {:?} to {:?}", input_path, output_path);
+        return Err(failure_error(status, &stderr, options.output_codec.as_deref()));
+    }
+
+    Ok(())
+}
+
+/// one ffmpeg process encoding the interleaved float samples written to it into one or more outputs, each with its own
+/// options, so that a single decode feeds all of them without intermediate files
+/// the samples are streamed through a pipe; the outputs only appear once `finish` succeeds
+pub struct FfmpegEncoder {
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    stderr_reader: Option<thread::JoinHandle<String>>,
+    outputs: Vec<(PathBuf, Option<NamedTempFile>, TranscodeOptions)>,
+    // codec named in typed errors when ffmpeg fails
+    requested_codec: Option<String>,
+}
+
+impl FfmpegEncoder {
+    /// starts ffmpeg reading samples of `spec` and writing `outputs`, whose options must not need a pass of their own
+    /// the ffmpeg binary of the first output is used
+    pub fn spawn(spec: &StreamSpec, tags: &Tags, outputs: &[(&Path, &TranscodeOptions)]) -> Result<Self, TranscoderError> {
+        let outputs: Vec<(&Path, TranscodeOptions)> = outputs.iter().map(|(path, options)| (*path, with_preset_options(options))).collect();
+        let Some((_, first)) = outputs.first() else {
+            return Err(TranscoderError::Argument("FFmpeg needs at least one output to encode".to_string()));
+        };
+        if outputs.iter().any(|(_, options)| options.normalize_peak_dbfs.is_some()) {
+            return Err(TranscoderError::Argument(
+                "--normalize-peak needs a pass of its own and cannot be streamed to FFmpeg".to_string(),
+            ));
+        }
+        if outputs.iter().any(|(_, options)| options.trim_silence.is_some()) {
+            return Err(TranscoderError::Argument("--trim-silence is applied natively and cannot be streamed to FFmpeg".to_string()));
+        }
+
+        let ffmpeg = FfmpegCapabilities::probe(first.ffmpeg_path.as_deref())?;
+        let requested_codec = first.output_codec.clone();
+        let input_format = RawPcmFormat::F32le.ffmpeg_name();
+        if !ffmpeg.has_demuxer(input_format) {
+            return Err(TranscoderError::UnsupportedInputFormat(format!(
+                "FFmpeg {} cannot read '{}' input", ffmpeg.version, input_format
+            )));
+        }
+        for (output_path, options) in &outputs {
+            transcoders::check_overwrite(output_path, options)?;
+            validate_output_capabilities(&ffmpeg, options)?;
+        }
+
+        let mut command = Command::new(&ffmpeg.path);
+        command.arg("-f").arg(input_format);
+        command.arg("-ar").arg(spec.sample_rate.to_string());
+        command.arg("-ac").arg(spec.channels.to_string());
+        command.arg("-i").arg("pipe:0");
+
+        let mut pending = Vec::new();
+        for (output_path, options) in outputs {
+            add_output_args(&mut command, &options);
+            for (key, value) in tags {
+                command.arg("-metadata").arg(format!("{}={}", key.to_ascii_lowercase(), value));
+            }
+            // the temp files are moved into place once ffmpeg succeeded
+            let temp_output = transcoders::create_temp_output(output_path)?;
+            match &temp_output {
+                Some(temp) => command.arg("-y").arg(temp.path()),
+                None => command.arg("-y").arg("pipe:1"),
+            };
+            pending.push((output_path.to_path_buf(), temp_output, options));
+        }
+        let streams_to_stdout = pending.iter().any(|(_, temp_output, _)| temp_output.is_none());
+        command.stdin(Stdio::piped());
+        command.stdout(if streams_to_stdout { Stdio::inherit() } else { Stdio::null() });
+        command.stderr(Stdio::piped());
+        debug!("Executing FFmpeg: {:?}", command);
+
+        let mut child = command.spawn().map_err(|e| {
+            TranscoderError::Io(std::io::Error::other(
+                format!("Failed to execute FFmpeg at {}: {}", ffmpeg.path.display(), e),
+            ))
+        })?;
+        // draining stderr on its own thread so that ffmpeg never blocks on a full pipe
+        let stderr_reader = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut text = String::new();
+                let _ = stderr.read_to_string(&mut text);
+                text
+            })
+        });
+        let stdin = child.stdin.take().map(BufWriter::new);
+        Ok(Self { child, stdin, stderr_reader, outputs: pending, requested_codec })
+    }
+
+    /// streams interleaved samples to ffmpeg
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), TranscoderError> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Err(TranscoderError::FfmpegCli("FFmpeg has already finished".to_string()));
+        };
+        let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        match stdin.write_all(&bytes) {
+            Ok(()) => Ok(()),
+            // ffmpeg closes its input when it fails, the reason is in its stderr
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Err(self.wait().err().unwrap_or(TranscoderError::Io(e))),
+            Err(e) => Err(TranscoderError::Io(e)),
+        }
+    }
+
+    /// ends the input, waits for ffmpeg and moves the outputs into place
+    pub fn finish(mut self) -> Result<(), TranscoderError> {
+        self.wait()?;
+        for (output_path, temp_output, options) in std::mem::take(&mut self.outputs) {
+            if let Some(temp_output) = temp_output {
+                transcoders::persist_output(temp_output, &output_path, &options)?;
+            }
+            info!("FFmpeg successfully encoded {:?}", output_path);
+        }
+        Ok(())
+    }
+
+    // closes stdin and reaps ffmpeg, failing with its error if it did not succeed
+    fn wait(&mut self) -> Result<(), TranscoderError> {
+        if let Some(mut stdin) = self.stdin.take() {
+            // a failed flush shows in the exit status
+            let _ = stdin.flush();
+        }
+        let status = self.child.wait()?;
+        let stderr = self.stderr_reader.take().and_then(|reader| reader.join().ok()).unwrap_or_default();
+        if !status.success() {
+            error!("FFmpeg CLI failed to encode {:?}", self.outputs.iter().map(|(path, _, _)| path).collect::<Vec<_>>());
+            return Err(failure_error(status, &stderr, self.requested_codec.as_deref()));
+        }
+        debug!("FFmpeg stderr:\n{}", stderr);
+        Ok(())
+    }
+}
+
+impl Drop for FfmpegEncoder {
+    // an encoder dropped before finishing, e.g. on cancellation, stops ffmpeg before its temp files are removed
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+// the MP3 preset is expressed through the regular bitrate options handled by `add_output_args`
+fn with_preset_options(options: &TranscodeOptions) -> TranscodeOptions {
+    match PresetCodec::detect(&options.output_format_extension, options.output_codec.as_deref()) {
+        Some(PresetCodec::Mp3) => presets::with_mp3_preset(options),
+        _ => options.clone(),
+    }
+}
+
+// adds the encoder, filter and muxer options of an output, which precede its path
+fn add_output_args(command: &mut Command, options: &TranscodeOptions) {
+    let preset_codec = PresetCodec::detect(&options.output_format_extension, options.output_codec.as_deref());
 
     if let Some(codec) = &options.output_codec {
         command.arg("-c:a").arg(codec);
@@ -24,6 +292,32 @@ pub fn transcode_with_ffmpeg(
         command.arg("-b:a").arg(format!("{}k", bitrate_kbps));
     }
 
+    // LAME specific options are only understood by libmp3lame
+    let is_mp3 = options.output_codec.as_deref().map_or(options.output_format_extension == "mp3", |codec| codec == "libmp3lame");
+    if is_mp3 {
+        match options.bitrate_mode {
+            Some(BitrateMode::Vbr) => {
+                command.arg("-q:a").arg(options.vbr_quality.unwrap_or(4).to_string());
+            }
+            Some(BitrateMode::Abr) => {
+                command.arg("-abr").arg("1");
+            }
+            Some(BitrateMode::Cbr) => {}
+            None => {
+                if let Some(vbr_quality) = options.vbr_quality {
+                    command.arg("-q:a").arg(vbr_quality.to_string());
+                }
+            }
+        }
+        if let Some(joint_stereo) = options.joint_stereo {
+            command.arg("-joint_stereo").arg(if joint_stereo { "1" } else { "0" });
+        }
+    }
+
+    if let Some(filter_graph) = ffmpeg_filter_graph(options) {
+        command.arg("-af").arg(filter_graph);
+    }
+
     if let Some(sample_rate) = options.sample_rate {
         command.arg("-ar").arg(sample_rate.to_string());
     }
@@ -32,40 +326,276 @@ pub fn transcode_with_ffmpeg(
         command.arg("-ac").arg(channels.to_string());
     }
 
+    if let Some(channel_mask) = options.channel_mask {
+        command.arg("-channel_layout").arg(format!("{:#x}", channel_mask));
+    }
+
     if let Some(threads) = options.threads {
         command.arg("-threads").arg(threads.to_string());
     }
 
-    if let Some(quality_preset) = &options.quality_preset {
-        warn!("'quality-preset' is a highly codec-specific option and may not directly apply too all audio codecs via generic flags for the FFmpeg CLI");
-        command.arg("-preset").arg(quality_preset);
+    // presets are translated into the options of the selected encoder, explicit bitrates keep precedence
+    if let Some(preset) = options.quality_preset {
+        match preset_codec {
+            Some(PresetCodec::Mp3) => {
+                // libmp3lame maps the compression level onto LAME's algorithm quality
+                command.arg("-compression_level").arg(preset.mp3().algorithm_quality.to_string());
+            }
+            Some(PresetCodec::Opus) => {
+                let opus = preset.opus();
+                if options.bitrate_kbps.is_none() {
+                    command.arg("-b:a").arg(format!("{}k", opus.bitrate_kbps));
+                }
+                command.arg("-application").arg(opus.application.ffmpeg_name());
+            }
+            Some(PresetCodec::Vorbis) if options.bitrate_kbps.is_none() => {
+                command.arg("-q:a").arg(preset.vorbis_quality().to_string());
+            }
+            Some(PresetCodec::Aac) if options.bitrate_kbps.is_none() => {
+                command.arg("-q:a").arg(preset.aac_vbr_quality().to_string());
+            }
+            Some(PresetCodec::FdkAac) if options.bitrate_kbps.is_none() => {
+                command.arg("-vbr").arg(preset.fdk_aac_vbr_mode().to_string());
+            }
+            Some(PresetCodec::Flac) => {
+                command.arg("-compression_level").arg(preset.flac_compression_level().to_string());
+            }
+            Some(PresetCodec::Vorbis | PresetCodec::Aac | PresetCodec::FdkAac) => {
+                debug!("Quality preset {:?} is overridden by the explicit bitrate", preset);
+            }
+            None => warn!("Quality preset {:?} has no parameters for this output codec, ignoring it", preset),
+        }
     }
 
-    command.arg("-y");
+    if let Some(muxer) = output_muxer(options) {
+        command.arg("-f").arg(muxer);
+    }
+}
 
-    command.arg(output_path);
+// describes a failed ffmpeg run, as the typed error of a known failure if it was recognized
+fn failure_error(status: ExitStatus, stderr: &str, requested_codec: Option<&str>) -> TranscoderError {
+    debug!("FFmpeg stderr:\n{}", stderr);
+    // known failures are reported with the offending parameter so that callers can adjust their options
+    if let Some(failure) = parse_ffmpeg_failure(stderr, requested_codec) {
+        return failure;
+    }
+    error!("FFmpeg stderr:\n{}", stderr);
+    TranscoderError::FfmpegCli(format!("FFmpeg exited with non-zero status: {:?}\nStderr:{}", status.code(), stderr))
+}
 
-    debug!("Executing FFmpeg: {:?}", command);
+// the `-af` graph in the order the native pipeline applies it: EQ options, `--af`, `--gain`, then the limiter
+// silence is always trimmed natively, see `native_transcoder::transcode_with_native_trimming`
+fn ffmpeg_filter_graph(options: &TranscodeOptions) -> Option<String> {
+    let mut filter_graph: Vec<String> = options.equalizer.iter().map(BiquadSpec::ffmpeg_filter).collect();
+    filter_graph.extend(options.audio_filter.clone());
+    if let Some(gain_db) = options.gain_db {
+        filter_graph.push(format!("volume={}dB", gain_db));
+    }
+    if let Some(ceiling_dbfs) = options.soft_limit_dbfs {
+        // alimiter accepts limits from -24 to 0 dBFS; automatic leveling would raise the output to the limit
+        let limit = level::db_to_linear(ceiling_dbfs).clamp(0.0625, 1.0);
+        filter_graph.push(format!("alimiter=limit={:.4}:level=0", limit));
+    }
+    (!filter_graph.is_empty()).then(|| filter_graph.join(","))
+}
 
-    let output = command.output().map_err(|e| {
-        TranscoderError::Io(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to execute ffmpeg command. Please check if ffmpeg is installed and in your PATH. Error: {}", e),
-        ))
-    })?;
+// demuxer forced with `-f` before the input, if any
+fn input_demuxer<'a>(input_format: &'a DetectedFormat, options: &TranscodeOptions) -> Option<&'a str> {
+    if input_format.format == "raw" {
+        Some(options.raw_format.unwrap_or(RawPcmFormat::S16le).ffmpeg_name())
+    } else if input_format.source == DetectionSource::Override {
+        Some(ffmpeg_format_name(&input_format.format))
+    } else {
+        None
+    }
+}
 
-    if output.status.success() {
-        info!("FFmpeg successfully transcoded {:?} to {:?}", input_path, output_path);
-        debug!("FFmpeg stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+// muxer forced with `-f` before the output, if any
+fn output_muxer(options: &TranscodeOptions) -> Option<&str> {
+    if options.output_format_extension == "raw" {
+        Some(options.raw_format.unwrap_or(RawPcmFormat::S16le).ffmpeg_name())
     } else {
-        error!("FFmpeg CLI failed to transcode {:?} to {:?}", input_path, output_path);
-        error!("FFmpeg stderr:\n{}", String::from_utf8_lossy(&output.stderr));
-        return Err(TranscoderError::FfmpegCli(format!(
-            "FFmpeg exited with non-zero status: {:?}\nStderr:{}",
-            output.status.code(),
-            String::from_utf8_lossy(&output.stderr)
+        options.output_format.as_deref().map(ffmpeg_format_name)
+    }
+}
+
+// checks that the probed ffmpeg provides the demuxer, muxer and encoder the job asks for
+fn validate_capabilities(
+    ffmpeg: &FfmpegCapabilities,
+    input_format: &DetectedFormat,
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    if let Some(demuxer) = input_demuxer(input_format, options).filter(|demuxer| !ffmpeg.has_demuxer(demuxer)) {
+        return Err(TranscoderError::UnsupportedInputFormat(format!(
+            "FFmpeg {} cannot read '{}' input", ffmpeg.version, demuxer
         )));
     }
+    validate_output_capabilities(ffmpeg, options)
+}
+
+// checks that the probed ffmpeg provides the muxer and encoder an output asks for
+fn validate_output_capabilities(ffmpeg: &FfmpegCapabilities, options: &TranscodeOptions) -> Result<(), TranscoderError> {
+    match output_muxer(options) {
+        Some(muxer) if !ffmpeg.has_muxer(muxer) => {
+            return Err(TranscoderError::UnsupportedOutputFormat(format!(
+                "FFmpeg {} cannot write '{}' output", ffmpeg.version, muxer
+            )));
+        }
+        Some(_) => {}
+        // ffmpeg maps extensions onto muxers itself, an unlisted name is not necessarily an error
+        None => {
+            let muxer = ffmpeg_format_name(&options.output_format_extension);
+            if !ffmpeg.has_muxer(muxer) {
+                warn!("FFmpeg {} lists no '{}' muxer, relying on its own extension mapping", ffmpeg.version, muxer);
+            }
+        }
+    }
+
+    if let Some(codec) = options.output_codec.as_deref().filter(|codec| !ffmpeg.has_audio_encoder(codec)) {
+        debug!("FFmpeg {} has no '{}' audio encoder", ffmpeg.version, codec);
+        return Err(TranscoderError::UnknownEncoder(codec.to_string()));
+    }
 
     Ok(())
 }
+
+// forwards the lines of an ffmpeg pipe to the progress parser
+// the returned thread yields the text of the pipe without progress lines when `collect` is set
+fn forward_lines(pipe: impl Read + Send + 'static, sender: mpsc::Sender<String>, collect: bool) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut text = String::new();
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            if collect && parse_progress_line(&line).is_none() {
+                text.push_str(&line);
+                text.push('\n');
+            }
+            let _ = sender.send(line);
+        }
+        text
+    })
+}
+
+// splits a `-progress` line into its key and value
+fn parse_progress_line(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once('=')?;
+    let is_key = !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    is_key.then_some((key, value.trim()))
+}
+
+// parses a `HH:MM:SS.xx` timestamp as printed by ffmpeg
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.trim().splitn(3, ':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+// assembles progress reports from the input description on stderr and the `-progress` key=value blocks
+struct FfmpegProgress {
+    callback: Option<ProgressCallback>,
+    started: Instant,
+    duration: Option<Duration>,
+    sample_rate: Option<u32>,
+    position: Duration,
+}
+
+impl FfmpegProgress {
+    fn new(callback: Option<ProgressCallback>) -> Self {
+        Self {
+            callback,
+            started: Instant::now(),
+            duration: None,
+            sample_rate: None,
+            position: Duration::ZERO,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+
+        // the first `Duration:` and audio stream lines describe the input
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix("Duration: ") {
+            if self.duration.is_none() {
+                self.duration = rest.split(',').next().and_then(parse_timestamp);
+            }
+            return;
+        }
+        if trimmed.starts_with("Stream #") && trimmed.contains("Audio: ") {
+            if self.sample_rate.is_none() {
+                self.sample_rate = trimmed.split(',')
+                    .find_map(|part| part.trim().strip_suffix(" Hz"))
+                    .and_then(|rate| rate.parse().ok());
+            }
+            return;
+        }
+
+        match parse_progress_line(line) {
+            Some(("out_time_us", value)) => {
+                if let Ok(micros) = value.parse::<u64>() {
+                    self.position = Duration::from_micros(micros);
+                }
+            }
+            // every block of progress lines ends with `progress=continue` or `progress=end`
+            Some(("progress", value)) => {
+                let to_frames = |duration: Duration| {
+                    self.sample_rate.map_or(0, |rate| (duration.as_secs_f64() * rate as f64) as u64)
+                };
+                callback.report(&Progress {
+                    frames: to_frames(self.position),
+                    total_frames: self.duration.filter(|_| self.sample_rate.is_some()).map(to_frames),
+                    position: self.position,
+                    duration: self.duration,
+                    elapsed: self.started.elapsed(),
+                    finished: value == "end",
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[cfg(unix)]
+    #[test]
+    fn streams_samples_to_one_process_for_several_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let ffmpeg_path = test_support::fake_ffmpeg(dir.path());
+        let aac = TranscodeOptions {
+            output_format_extension: "m4a".to_string(),
+            bitrate_kbps: Some(128),
+            ffmpeg_path: Some(ffmpeg_path.clone()),
+            ..TranscodeOptions::default()
+        };
+        let mp3 = TranscodeOptions {
+            output_format_extension: "mp3".to_string(),
+            sample_rate: Some(22050),
+            ffmpeg_path: Some(ffmpeg_path),
+            ..TranscodeOptions::default()
+        };
+        let spec = StreamSpec { sample_rate: 44100, channels: 2, bits_per_sample: 32, is_float: true, total_frames: None };
+        let tags = vec![("TITLE".to_string(), "Song".to_string())];
+        let (aac_path, mp3_path) = (dir.path().join("out.m4a"), dir.path().join("out.mp3"));
+
+        let mut encoder = FfmpegEncoder::spawn(&spec, &tags, &[(&aac_path, &aac), (&mp3_path, &mp3)]).unwrap();
+        encoder.write_samples(&[0.5; 2048]).unwrap();
+        encoder.finish().unwrap();
+
+        let args = std::fs::read_to_string(dir.path().join("args")).unwrap();
+        let (input, outputs) = args.split_once("-i pipe:0").unwrap();
+        assert_eq!(input.trim(), "-f f32le -ar 44100 -ac 2");
+        let outputs: Vec<&str> = outputs.split(" -y ").collect();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].trim(), "-b:a 128k -metadata title=Song");
+        assert_eq!(outputs[1].split_once(' ').unwrap().1, "-ar 22050 -metadata title=Song");
+        assert_eq!(std::fs::read(dir.path().join("input")).unwrap().len(), 8192);
+        assert!(aac_path.exists() && mp3_path.exists());
+    }
+}
@@ -1,29 +1,158 @@
-pub mod native_wav;
-pub mod native_flac_to_wav;
+pub mod native_transcoder;
 pub mod ffmpeg_transcoder;
+pub mod ffmpeg_probe;
+pub mod ffmpeg_errors;
 
-use std::path::Path;
-use log::{info, error, debug};
+use std::path::{Path, PathBuf};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use crate::audio_processor::biquad::BiquadSpec;
+use crate::audio_processor::filters::{self, AudioFilter};
+use crate::audio_processor::level;
+use crate::audio_processor::silence::SilenceSettings;
+use crate::cancellation::CancellationToken;
+use crate::codecs::{self, NativeCodec};
+use crate::codecs::raw::RawPcmFormat;
 use crate::errors::TranscoderError;
-use crate::utils::{infer_file_type, get_file_extension};
+use crate::format_detection::detect_input_format;
+use crate::presets::QualityPreset;
+use crate::progress::ProgressCallback;
+use crate::utils;
+use tempfile::NamedTempFile;
 
 /// options for audio transcoding
 #[derive(Debug, Default, Clone)]
 pub struct TranscodeOptions {
     /// output file format extension
     pub output_format_extension: String,
+    /// explicit input format overriding content detection (`--input-format`)
+    pub input_format: Option<String>,
+    /// explicit output format overriding the output extension (`--output-format`)
+    pub output_format: Option<String>,
     /// output audio codec; if None, ffmpeg behaves a fallback and chooses a default for the output format
     pub output_codec: Option<String>,
     /// desired output bitrate in kbps; if None, ffmpeg chooses a default
+    /// the native Vorbis (ABR) and Opus encoders honor it as well
     pub bitrate_kbps: Option<u32>,
     /// desired output sample rate in Hz; if None, ffmpeg will choose the input audio's sample rate or a codec default
     pub sample_rate: Option<u32>,
     /// desired number of output audio channels; if None, ffmpeg will use the input audio's channel count or a codec default
     pub channels: Option<u8>, 
-    /// defienes the quality preset for ffmpeg; codec-specific and may influence encoding speed vs compression efficiency
-    pub quality_preset: Option<String>,
+    /// speaker assignment of the output channels as a WAVE_FORMAT_EXTENSIBLE mask; if None, the default layout for the channel count is used
+    pub channel_mask: Option<u32>,
+    /// quality preset translated into codec-specific parameters by both the native and ffmpeg transcoders
+    /// explicitly given bitrate options take precedence over the preset
+    pub quality_preset: Option<QualityPreset>,
+    /// EQ filters (`--highpass`, `--eq`, ...) applied natively, or translated into ffmpeg filters, before `audio_filter`
+    pub equalizer: Vec<BiquadSpec>,
+    /// ffmpeg filter graph (`--af`); simple chains of volume, highpass, lowpass and aresample are applied natively
+    pub audio_filter: Option<String>,
+    /// gain in dB applied after all filters
+    pub gain_db: Option<f32>,
+    /// peak level in dBFS the output is normalized to, measured in a first pass over the input (native transcoder only)
+    pub normalize_peak_dbfs: Option<f32>,
+    /// ceiling in dBFS of the soft limiter applied last; if None, overs are clipped by the sample conversion
+    pub soft_limit_dbfs: Option<f32>,
+    /// strips leading and trailing silence as described by the settings
+    pub trim_silence: Option<SilenceSettings>,
     /// number fo threads to used for encoding; if None, ffmpeg will default to all available cores
     pub threads: Option<usize>,
+    /// bitrate management mode for MP3 output; if None, it is derived from `vbr_quality` and `bitrate_kbps`
+    pub bitrate_mode: Option<BitrateMode>,
+    /// VBR quality level for MP3 output, from 0 (best) to 9 (smallest)
+    pub vbr_quality: Option<u8>,
+    /// whether MP3 output uses joint stereo; if None, the encoder default (joint stereo) is kept
+    pub joint_stereo: Option<bool>,
+    /// sample layout of raw PCM input and output; if None, signed 16-bit little-endian is assumed
+    pub raw_format: Option<RawPcmFormat>,
+    /// sample rate of raw PCM input in Hz, required since raw data has no header
+    pub raw_sample_rate: Option<u32>,
+    /// number of interleaved channels of raw PCM input, required since raw data has no header
+    pub raw_channels: Option<u8>,
+    /// receives progress reports while transcoding; if None, progress is not tracked
+    pub progress: Option<ProgressCallback>,
+    /// aborts the transcode with `TranscoderError::Cancelled` when cancelled or timed out
+    pub cancellation: CancellationToken,
+    /// whether an existing output file is replaced; if false, the transcode fails instead
+    pub overwrite: bool,
+    /// ffmpeg binary used by the fallback; if None, `REWAV_FFMPEG` or `ffmpeg` from the PATH is used
+    pub ffmpeg_path: Option<PathBuf>,
+}
+
+impl TranscodeOptions {
+    /// the options shaping the output, leaving out those that only affect how it is produced
+    /// outputs written with equal fingerprints from the same input are identical
+    pub fn fingerprint(&self) -> OutputFingerprint {
+        OutputFingerprint {
+            output_format_extension: self.output_format_extension.clone(),
+            input_format: self.input_format.clone(),
+            output_format: self.output_format.clone(),
+            output_codec: self.output_codec.clone(),
+            bitrate_kbps: self.bitrate_kbps,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            channel_mask: self.channel_mask,
+            quality_preset: self.quality_preset,
+            equalizer: self.equalizer.clone(),
+            audio_filter: self.audio_filter.clone(),
+            gain_db: self.gain_db,
+            normalize_peak_dbfs: self.normalize_peak_dbfs,
+            soft_limit_dbfs: self.soft_limit_dbfs,
+            trim_silence: self.trim_silence,
+            bitrate_mode: self.bitrate_mode,
+            vbr_quality: self.vbr_quality,
+            joint_stereo: self.joint_stereo,
+            raw_format: self.raw_format,
+            raw_sample_rate: self.raw_sample_rate,
+            raw_channels: self.raw_channels,
+        }
+    }
+}
+
+/// the transcode options an output depends on, recorded with outputs to tell whether a rerun would change them
+/// threads, the ffmpeg binary, progress, cancellation and overwriting are left out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputFingerprint {
+    pub output_format_extension: String,
+    pub input_format: Option<String>,
+    pub output_format: Option<String>,
+    pub output_codec: Option<String>,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub channel_mask: Option<u32>,
+    pub quality_preset: Option<QualityPreset>,
+    pub equalizer: Vec<BiquadSpec>,
+    pub audio_filter: Option<String>,
+    pub gain_db: Option<f32>,
+    pub normalize_peak_dbfs: Option<f32>,
+    pub soft_limit_dbfs: Option<f32>,
+    pub trim_silence: Option<SilenceSettings>,
+    pub bitrate_mode: Option<BitrateMode>,
+    pub vbr_quality: Option<u8>,
+    pub joint_stereo: Option<bool>,
+    pub raw_format: Option<RawPcmFormat>,
+    pub raw_sample_rate: Option<u32>,
+    pub raw_channels: Option<u8>,
+}
+
+impl OutputFingerprint {
+    /// MD5 of the JSON form, whose field order is fixed by the struct
+    pub fn hash(&self) -> String {
+        utils::md5_hex(serde_json::to_string(self).unwrap_or_default().as_bytes())
+    }
+}
+
+/// bitrate management modes for lossy encoders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BitrateMode {
+    /// constant bitrate
+    Cbr,
+    /// variable bitrate targeting a quality level
+    Vbr,
+    /// average bitrate
+    Abr,
 }
 
 /// selects between the native Rust implementations and ffmpeg as the fallback based on the detected file type
@@ -34,33 +163,111 @@ pub fn transcode_audio(
 ) -> Result<(), TranscoderError> {
     info!("Attempting to transcode audio from {:?} to {:?} with options: {:?}", input_path, output_path, options);
 
-    // inferring input file type
-    let input_file_type = infer_file_type(input_path)?;
-    let input_extension = get_file_extension(input_path)?;
+    check_overwrite(output_path, options)?;
+
+    // detecting input format from its content, falling back to the extension
+    let input_format = detect_input_format(input_path, options.input_format.as_deref())?;
 
-    info!("Detected input file type {:?} extension: '{}'", input_file_type, input_extension);
+    info!("Detected input format: {}", input_format);
     info!("Requested output format extension: '{}'", options.output_format_extension);
 
     // dispatching processing to the appropriate transcoder
-    // prioritizing native transcoding and relying on ffmpeg if either of the input or output extensions are not supported
-    let use_native_wav = input_file_type.as_ref().map_or(false, |t| t.extension() == "wav") 
-        && options.output_format_extension == "wav"
-        && options.output_codec.is_none();
-
-    let use_native_flac_to_wav = input_file_type.as_ref().map_or(false, |t| t.extension() == "flac") 
-        && options.output_format_extension == "wav"
-        && options.output_codec.is_none();
-
-    if use_native_wav {
-        info!("Dispatching to native WAV transcoder...");
-        native_wav::transcode_wav_with_options(input_path, output_path, options)
+    // prioritizing native transcoding and relying on ffmpeg if either of the input or output formats are not supported
+    let native_filters = native_filters(options);
+    let native_input = Some(input_format.format.as_str())
+        .filter(|format| codecs::supports_native_input(format))
+        .filter(|_| native_filters.is_some());
+    let native_output = native_output_codec(output_path, options);
+
+    let temp_output = create_temp_output(output_path)?;
+    let target_path = temp_output.as_ref().map_or(output_path, |temp| temp.path());
+
+    match (native_input, native_output, native_filters) {
+        (Some(input_format), Some(codec), Some(filters)) => {
+            info!("Dispatching to native transcoder ({} to {:?})...", input_format, codec);
+            native_transcoder::transcode_natively(input_path, input_format, target_path, codec, &filters, options)?;
+        }
+        _ if options.trim_silence.is_some() => {
+            info!("Dispatching to FFmpeg with native silence trimming...");
+            native_transcoder::transcode_with_native_trimming(input_path, &input_format, target_path, options)?;
+        }
+        _ => {
+            info!("Dispatching to FFmpeg's transcoder (fallback)...");
+            ffmpeg_transcoder::transcode_with_ffmpeg(input_path, &input_format, target_path, options)?;
+        }
     }
-    else if use_native_flac_to_wav {
-        info!("Dispatching to native FLAC to WAV transcoder...");
-        native_flac_to_wav::transcode_flac_to_wav_with_options(input_path, output_path, options)
+
+    if let Some(temp_output) = temp_output {
+        persist_output(temp_output, output_path, options)?;
     }
-    else {
-        info!("Dispatching to FFmpeg's transcoder (fallback)...");
-        ffmpeg_transcoder::transcode_with_ffmpeg(input_path, output_path, options)
+
+    Ok(())
+}
+
+/// selects the native encoder for the output, None if it has to be written by ffmpeg
+pub fn native_output_codec(output_path: &Path, options: &TranscodeOptions) -> Option<NativeCodec> {
+    NativeCodec::for_output(&options.output_format_extension, options.output_codec.as_deref())
+        // encoders that seek back to patch their headers cannot write to stdout
+        .filter(|codec| !utils::is_stdio(output_path) || codec.supports_streaming())
+}
+
+/// combines the EQ options, `--af` and `--gain` into native filter stages, returning None if the graph has to be run by ffmpeg
+pub fn native_filters(options: &TranscodeOptions) -> Option<Vec<AudioFilter>> {
+    let mut native: Vec<AudioFilter> = options.equalizer.iter().copied().map(AudioFilter::Biquad).collect();
+    let gain = options.gain_db.map(|gain_db| AudioFilter::Volume(level::db_to_linear(gain_db)));
+    let Some(graph) = options.audio_filter.as_deref() else {
+        native.extend(gain);
+        return Some(native);
+    };
+    match filters::parse_filtergraph(graph) {
+        Ok(filters) => {
+            native.extend(filters);
+            native.extend(gain);
+            Some(native)
+        }
+        Err(reason) => {
+            info!("Filter graph '{}' requires FFmpeg: {}", graph, reason);
+            None
+        }
+    }
+}
+
+/// fails early if the output exists and may not be replaced
+pub fn check_overwrite(output_path: &Path, options: &TranscodeOptions) -> Result<(), TranscoderError> {
+    if !utils::is_stdio(output_path) && !options.overwrite && output_path.exists() {
+        return Err(output_exists_error(output_path));
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// creates the hidden sibling temp file a transcoder writes to instead of the output, None for stdout
+/// the temp file is renamed over the output on success and deleted on failure or cancellation,
+/// so that downstream jobs never see a partial output
+pub fn create_temp_output(output_path: &Path) -> Result<Option<NamedTempFile>, TranscoderError> {
+    if utils::is_stdio(output_path) {
+        return Ok(None);
+    }
+    Ok(Some(utils::create_temp_sibling(output_path)?))
+}
+
+/// moves a finished temp file into place, honoring `options.overwrite`
+pub fn persist_output(temp_output: NamedTempFile, output_path: &Path, options: &TranscodeOptions) -> Result<(), TranscoderError> {
+    debug!("Moving {:?} to {:?}", temp_output.path(), output_path);
+    let persisted = if options.overwrite {
+        temp_output.persist(output_path)
+    } else {
+        // the output may have been created by someone else in the meantime
+        temp_output.persist_noclobber(output_path)
+    };
+    persisted.map_err(|e| match e.error.kind() {
+        std::io::ErrorKind::AlreadyExists => output_exists_error(output_path),
+        _ => TranscoderError::Io(e.error),
+    })?;
+    Ok(())
+}
+
+fn output_exists_error(output_path: &Path) -> TranscoderError {
+    TranscoderError::Path(format!(
+        "Output file already exists: {} (use --overwrite to replace it)", output_path.display()
+    ))
+}
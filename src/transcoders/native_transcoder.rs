@@ -0,0 +1,265 @@
+use std::path::Path;
+use log::{info, debug, warn};
+use crate::errors::TranscoderError;
+use crate::format_detection::DetectedFormat;
+use crate::operations::OutputWriter;
+use crate::transcoders::{self, ffmpeg_transcoder, TranscodeOptions};
+use crate::audio_processor::{filters::{self, AudioFilter}, level::{self, SoftLimiter}, pipeline::{Pipeline, CHUNK_FRAMES}};
+use crate::codecs::{self, AudioDecoder, NativeCodec};
+use crate::progress::ProgressTracker;
+use crate::utils;
+
+/// transcodes between natively supported formats, decoding to f32, resampling and mixing channels as requested, and encoding with `codec`
+/// `input_format_extension` selects the decoder, `filters` are applied after resampling and mixing, followed by
+/// peak normalization, the soft limiter and silence trimming
+pub fn transcode_natively(
+    input_path: &Path,
+    input_format_extension: &str,
+    output_path: &Path,
+    codec: NativeCodec,
+    filters: &[AudioFilter],
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    info!("Native transcoder: Reading {:?} as '{}'", input_path, input_format_extension);
+
+    let resampled_options = resampled_options(filters, options);
+    let options = resampled_options.as_ref().unwrap_or(options);
+
+    let mut decoder = codecs::open_decoder(input_path, input_format_extension, options)?;
+    let input_spec = decoder.spec();
+    let output_spec = codec.output_spec(&input_spec, options)?;
+
+    info!("Native transcoder: {:?} -> {:?} ({:?})", input_spec, output_spec, codec);
+
+    // the first pass runs the whole pipeline only to measure the peak the second pass is normalized against
+    let normalization_gain = match options.normalize_peak_dbfs {
+        Some(target_dbfs) => {
+            if utils::is_stdio(input_path) {
+                return Err(TranscoderError::Argument(
+                    "--normalize-peak reads the input twice and cannot be used with stdin".to_string(),
+                ));
+            }
+            info!("Native transcoder: measuring the peak level for normalization to {} dBFS", target_dbfs);
+            let mut analysis_decoder = codecs::open_decoder(input_path, input_format_extension, options)?;
+            let mut pipeline = Pipeline::new(&input_spec, &output_spec, filters, 1.0, None, None)?;
+            let mut peak: f32 = 0.0;
+            run_pipeline(analysis_decoder.as_mut(), &mut pipeline, options, |samples, _| {
+                peak = peak.max(level::peak(samples));
+                Ok(())
+            })?;
+
+            if peak > 0.0 {
+                info!("Native transcoder: peak level is {:.2} dBFS", level::linear_to_db(peak));
+                level::db_to_linear(target_dbfs) / peak
+            } else {
+                warn!("Input is silent, skipping peak normalization");
+                1.0
+            }
+        }
+        None => 1.0,
+    };
+
+    let limiter = options.soft_limit_dbfs.map(SoftLimiter::new);
+    let mut pipeline = Pipeline::new(&input_spec, &output_spec, filters, normalization_gain, limiter, options.trim_silence.as_ref())?;
+    let mut encoder = codec.create_encoder(output_path, &output_spec, options, &decoder.tags())?;
+
+    // reading samples, processing, and writing to output
+    let mut progress = ProgressTracker::new(options.progress.clone(), input_spec.sample_rate, input_spec.total_frames);
+    let mut clipped_samples: u64 = 0;
+    let mut total_samples: u64 = 0;
+    let frames_read = run_pipeline(decoder.as_mut(), &mut pipeline, options, |samples, frames_read| {
+        if !output_spec.is_float {
+            clipped_samples += level::count_clipped(samples) as u64;
+        }
+        total_samples += samples.len() as u64;
+        encoder.write_samples(samples)?;
+        progress.update(frames_read);
+        Ok(())
+    })?;
+
+    encoder.finalize()?;
+    progress.finish(frames_read);
+
+    if clipped_samples > 0 {
+        warn!(
+            "{} of {} samples ({:.3}%) exceeded full scale and were clipped in the conversion to {}-bit; consider --gain, --normalize-peak or --soft-limit",
+            clipped_samples, total_samples, clipped_samples as f64 * 100.0 / total_samples as f64, output_spec.bits_per_sample
+        );
+    }
+
+    debug!("Native transcoder: processed {} input frames", frames_read);
+    info!("Native transcoder: Successfully wrote to {:?}", output_path);
+    Ok(())
+}
+
+/// transcodes with ffmpeg on either side while trimming silence natively, as ffmpeg's `silenceremove` either keeps the
+/// trailing silence or, with a negative stop period, also removes pauses inside the recording
+/// ffmpeg first decodes inputs the native pipeline cannot read or filter to an intermediate float WAV file, applying the
+/// filters, level processing, sample rate and channels; outputs without a native encoder are streamed to ffmpeg
+pub fn transcode_with_native_trimming(
+    input_path: &Path,
+    input_format: &DetectedFormat,
+    output_path: &Path,
+    options: &TranscodeOptions,
+) -> Result<(), TranscoderError> {
+    if options.normalize_peak_dbfs.is_some() {
+        return Err(TranscoderError::Argument(
+            "--normalize-peak is only supported when both the input and output formats are handled natively".to_string(),
+        ));
+    }
+
+    let native_filters = transcoders::native_filters(options)
+        .filter(|_| codecs::supports_native_input(&input_format.format));
+    let _intermediate;
+    let (mut decoder, filters, options) = match native_filters {
+        Some(filters) => {
+            let resampled_options = resampled_options(&filters, options);
+            let options = resampled_options.unwrap_or_else(|| options.clone());
+            (codecs::open_decoder(input_path, &input_format.format, &options)?, filters, options)
+        }
+        None => {
+            info!("Decoding and filtering {:?} with FFmpeg before trimming silence", input_path);
+            let intermediate = tempfile::Builder::new().prefix(".rewav-").suffix(".wav").tempfile()?;
+            let decode_options = TranscodeOptions {
+                output_format_extension: "wav".to_string(),
+                output_format: None,
+                output_codec: Some("pcm_f32le".to_string()),
+                bitrate_kbps: None,
+                quality_preset: None,
+                bitrate_mode: None,
+                vbr_quality: None,
+                joint_stereo: None,
+                trim_silence: None,
+                progress: None,
+                overwrite: true,
+                ..options.clone()
+            };
+            ffmpeg_transcoder::transcode_with_ffmpeg(input_path, input_format, intermediate.path(), &decode_options)?;
+            let decoder = codecs::open_decoder(intermediate.path(), "wav", &decode_options)?;
+            _intermediate = intermediate;
+            // only the trimming is left to do
+            let options = TranscodeOptions {
+                equalizer: Vec::new(),
+                audio_filter: None,
+                gain_db: None,
+                soft_limit_dbfs: None,
+                ..options.clone()
+            };
+            (decoder, Vec::new(), options)
+        }
+    };
+
+    let input_spec = decoder.spec();
+    let output_spec = OutputWriter::output_spec(output_path, &input_spec, &options)?;
+    let limiter = options.soft_limit_dbfs.map(SoftLimiter::new);
+    let mut pipeline = Pipeline::new(&input_spec, &output_spec, &filters, 1.0, limiter, options.trim_silence.as_ref())?;
+    // the output path is the temp file of `transcode_audio` already
+    let writer_options = TranscodeOptions { overwrite: true, ..options.clone() };
+    let mut writer = OutputWriter::create(output_path, &input_spec, &decoder.tags(), &writer_options)?;
+
+    let mut progress = ProgressTracker::new(options.progress.clone(), input_spec.sample_rate, input_spec.total_frames);
+    let frames_read = run_pipeline(decoder.as_mut(), &mut pipeline, &options, |samples, frames_read| {
+        writer.write_samples(samples)?;
+        progress.update(frames_read);
+        Ok(())
+    })?;
+    writer.finish()?;
+    progress.finish(frames_read);
+
+    info!("Native transcoder: Successfully wrote to {:?} with silence trimmed", output_path);
+    Ok(())
+}
+
+/// `aresample` in the filter graph sets the output rate unless --sample-rate is given, which ffmpeg would apply last
+/// returns the options with that rate, or None if they apply as they are
+pub fn resampled_options(filters: &[AudioFilter], options: &TranscodeOptions) -> Option<TranscodeOptions> {
+    let rate = filters::resample_rate(filters)?;
+    match options.sample_rate {
+        Some(sample_rate) if sample_rate != rate => {
+            warn!("aresample={} is overridden by the requested sample rate of {} Hz", rate, sample_rate);
+            None
+        }
+        _ => Some(TranscodeOptions { sample_rate: Some(rate), ..options.clone() }),
+    }
+}
+
+/// decodes the whole input through `pipeline`, handing every processed chunk and the input frames read so far to `sink`
+/// returns the number of input frames read
+pub fn run_pipeline(
+    decoder: &mut dyn AudioDecoder,
+    pipeline: &mut Pipeline,
+    options: &TranscodeOptions,
+    mut sink: impl FnMut(&[f32], u64) -> Result<(), TranscoderError>,
+) -> Result<u64, TranscoderError> {
+    let channels = decoder.spec().channels as usize;
+    let mut frames_read: u64 = 0;
+    loop {
+        options.cancellation.check()?;
+
+        let chunk = decoder.read_frames(CHUNK_FRAMES)?;
+        if chunk.is_empty() { // EOF
+            break;
+        }
+        frames_read += (chunk.len() / channels) as u64;
+        sink(&pipeline.process(chunk)?, frames_read)?;
+    }
+
+    let flushed = pipeline.flush()?;
+    if !flushed.is_empty() {
+        sink(&flushed, frames_read)?;
+    }
+    Ok(frames_read)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::audio_processor::silence::SilenceSettings;
+    use crate::test_support;
+    use crate::transcoders::{self, TranscodeOptions};
+
+    // a mono 16-bit WAV file of the given stretches of silence (false) and tone (true), in tenths of a second
+    fn bursts_wav(path: &std::path::Path, stretches: &[(bool, usize)]) {
+        let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        let mut frame = 0;
+        for &(tone, tenths) in stretches {
+            for _ in 0..tenths * 800 {
+                let t = frame as f64 / 8000.0;
+                let sample = if tone { 0.5 * (2.0 * std::f64::consts::PI * 440.0 * t).sin() } else { 0.0 };
+                writer.write_sample((sample * 32767.0) as i16).unwrap();
+                frame += 1;
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trims_only_the_edges_of_outputs_encoded_by_ffmpeg() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.wav");
+        bursts_wav(&input, &[(false, 10), (true, 10), (false, 10), (true, 10), (false, 10)]);
+        let options = TranscodeOptions {
+            output_format_extension: "m4a".to_string(),
+            trim_silence: Some(SilenceSettings { threshold_db: -50.0, min_duration: Duration::from_millis(500) }),
+            ffmpeg_path: Some(test_support::fake_ffmpeg(dir.path())),
+            ..TranscodeOptions::default()
+        };
+        transcoders::transcode_audio(&input, &dir.path().join("output.m4a"), &options).unwrap();
+
+        // ffmpeg received the trimmed samples, the pause between the tones included
+        let samples: Vec<f32> = std::fs::read(dir.path().join("input"))
+            .unwrap()
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        let silent = |sample: &f32| sample.abs() < 1e-4;
+        let leading = samples.iter().take_while(|sample| silent(sample)).count();
+        let trailing = samples.iter().rev().take_while(|sample| silent(sample)).count();
+        assert!(leading < 80 && trailing < 80, "{} leading and {} trailing silent samples", leading, trailing);
+        assert!((23_000..25_000).contains(&samples.len()), "{} samples", samples.len());
+        let longest_pause = samples.split(|sample| !silent(sample)).map(<[f32]>::len).max().unwrap();
+        assert!(longest_pause >= 7_900, "longest pause of {} samples", longest_pause);
+    }
+}
@@ -1,25 +1,77 @@
-use infer::Type;
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::Path;
+use md5::{Digest, Md5};
 
 use crate::errors::TranscoderError;
 
-/// reads beginning of file to determine type
-pub fn infer_file_type(path: &Path) -> Result<Option<Type>, TranscoderError> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = Vec::new();
-
-    reader.take(4096).read_to_end(&mut buffer)?;
-
-    Ok(infer::get(&buffer))
-}
-
 /// extracts the file extension from a path as a lowercase string
 pub fn get_file_extension(path: &Path) -> Result<String, TranscoderError> {
     path.extension()
         .and_then(|ext| ext.to_str())
         .map(|s| s.to_ascii_lowercase())
         .ok_or_else(|| TranscoderError::Path(format!("File path has no extension: {:?}", path))) 
-}
\ No newline at end of file
+}
+
+/// checks whether a path is `-`, standing for stdin as input and stdout as output
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// opens a buffered reader over the input file, or stdin for `-`
+pub fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+/// creates a buffered writer over the output file, or stdout for `-`
+pub fn create_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        return Ok(Box::new(BufWriter::new(io::stdout().lock())));
+    }
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+/// creates a hidden temp file next to `path` that keeps its extension, so that ffmpeg still picks the right muxer
+/// the file is deleted when dropped unless it is persisted over `path`
+pub fn create_temp_sibling(path: &Path) -> io::Result<tempfile::NamedTempFile> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(".{}.", path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default());
+    let suffix = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(&prefix).suffix(&suffix);
+    // the default 0600 mode of temp files would otherwise carry over to the final output
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    builder.tempfile_in(directory)
+}
+
+/// MD5 digest of a file's content as lowercase hex, used to detect changed inputs
+pub fn md5_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// MD5 digest of `data` as lowercase hex
+pub fn md5_hex(data: &[u8]) -> String {
+    hex(&Md5::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
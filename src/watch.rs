@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use crate::cancellation::CancellationToken;
+use crate::cli::{TranscodeArgs, WatchArgs};
+use crate::errors::TranscoderError;
+use crate::jobs;
+use crate::progress::ProgressCallback;
+use crate::transcoders;
+
+// how often files waiting to settle are checked when no events arrive
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// a file seen in the watched folder, transcoded once its size and modification time stop changing
+struct Pending {
+    size: u64,
+    modified: Option<SystemTime>,
+    unchanged_since: Instant,
+}
+
+// the folders and options every dropped file is handled with
+struct Profile<'a> {
+    args: &'a WatchArgs,
+    transcode: TranscodeArgs,
+    done_dir: PathBuf,
+    failed_dir: PathBuf,
+}
+
+/// watches a folder for new files, transcodes every file once it is stable to `--output-dir` with the configured
+/// profile and moves the original to the done or failed folder; runs until cancelled, e.g. by Ctrl+C
+/// a file interrupted by cancellation stays in place and is picked up again on the next start
+pub fn watch_folder(
+    args: &WatchArgs,
+    progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+) -> Result<(), TranscoderError> {
+    if !args.dir.is_dir() {
+        return Err(TranscoderError::Path(format!("Watched folder does not exist or is not a directory: {}", args.dir.display())));
+    }
+    let mut transcode = args.transcode.clone();
+    if let Some(profile) = &args.profile {
+        jobs::load_profile(profile)?
+            .apply(&mut transcode)
+            .map_err(|e| TranscoderError::Job(format!("{}: {}", profile.display(), e)))?;
+        transcode.validate()?;
+    }
+    let profile = Profile {
+        args,
+        transcode,
+        done_dir: args.done_dir.clone().unwrap_or_else(|| args.dir.join("done")),
+        failed_dir: args.failed_dir.clone().unwrap_or_else(|| args.dir.join("failed")),
+    };
+    for dir in [&args.output_dir, &profile.done_dir, &profile.failed_dir] {
+        fs::create_dir_all(dir)?;
+        // files moved or written there would be picked up again
+        if fs::canonicalize(dir)? == fs::canonicalize(&args.dir)? {
+            return Err(TranscoderError::Argument(format!("{} must not be the watched folder itself", dir.display())));
+        }
+    }
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| TranscoderError::Watch(e.to_string()))?;
+    watcher
+        .watch(&args.dir, RecursiveMode::NonRecursive)
+        .map_err(|e| TranscoderError::Watch(format!("{}: {}", args.dir.display(), e)))?;
+    info!("Watching {:?}, transcoding to {:?} as .{}", args.dir, args.output_dir, args.extension);
+
+    // files dropped before the watch started are picked up as well
+    let mut pending = HashMap::new();
+    for entry in fs::read_dir(&args.dir)? {
+        track(&mut pending, entry?.path());
+    }
+
+    let settle = Duration::from_secs_f64(args.settle);
+    loop {
+        if cancellation.check().is_err() {
+            info!("Stopped watching {:?}", args.dir);
+            return Ok(());
+        }
+
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    track(&mut pending, path);
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => warn!("Watching {:?}: {}", args.dir, e),
+            Err(RecvTimeoutError::Disconnected) => return Err(TranscoderError::Watch("the file watcher stopped".to_string())),
+        }
+
+        for path in settled(&mut pending, settle) {
+            if !transcode_file(&profile, &path, progress.clone(), &cancellation) {
+                info!("Stopped watching {:?}", args.dir);
+                return Ok(());
+            }
+        }
+    }
+}
+
+// starts tracking a regular file of the watched folder; hidden files (e.g. temp files of copies in progress) are skipped
+fn track(pending: &mut HashMap<PathBuf, Pending>, path: PathBuf) {
+    let hidden = path.file_name().is_none_or(|name| name.to_string_lossy().starts_with('.'));
+    if hidden || pending.contains_key(&path) {
+        return;
+    }
+    if let Some(metadata) = fs::metadata(&path).ok().filter(|metadata| metadata.is_file()) {
+        debug!("Waiting for {:?} to settle", path);
+        let file = Pending { size: metadata.len(), modified: metadata.modified().ok(), unchanged_since: Instant::now() };
+        pending.insert(path, file);
+    }
+}
+
+// removes and returns the files that have not changed for `settle`, forgetting files that disappeared
+fn settled(pending: &mut HashMap<PathBuf, Pending>, settle: Duration) -> Vec<PathBuf> {
+    let mut settled = Vec::new();
+    pending.retain(|path, file| {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        let modified = metadata.modified().ok();
+        if metadata.len() != file.size || modified != file.modified {
+            *file = Pending { size: metadata.len(), modified, unchanged_since: Instant::now() };
+            return true;
+        }
+        if file.unchanged_since.elapsed() < settle {
+            return true;
+        }
+        settled.push(path.clone());
+        false
+    });
+    settled.sort();
+    settled
+}
+
+// transcodes a settled file and files the original under done or failed, logging the result
+// returns false if the watch was cancelled meanwhile, leaving the file in place
+fn transcode_file(profile: &Profile, path: &Path, progress: Option<ProgressCallback>, cancellation: &CancellationToken) -> bool {
+    let Some(name) = path.file_name() else {
+        return true;
+    };
+    let output = profile.args.output_dir.join(Path::new(name).with_extension(&profile.args.extension));
+    // --timeout limits every file rather than the whole watch
+    let file_cancellation = match profile.transcode.timeout {
+        Some(timeout) => cancellation.with_timeout(Duration::from_secs(timeout)),
+        None => cancellation.clone(),
+    };
+
+    info!("Transcoding {:?} to {:?}", path, output);
+    let started = Instant::now();
+    let result = profile
+        .transcode
+        .to_options(&output, progress, file_cancellation)
+        .and_then(|options| transcoders::transcode_audio(path, &output, &options));
+    if cancellation.check().is_err() {
+        return false;
+    }
+
+    let seconds = started.elapsed().as_secs_f64();
+    let target_dir = match result {
+        Ok(()) => {
+            info!("{:?}: transcoded to {:?} in {:.1}s", path, output, seconds);
+            &profile.done_dir
+        }
+        Err(e) => {
+            error!("{:?}: failed after {:.1}s: {}", path, seconds, e);
+            &profile.failed_dir
+        }
+    };
+    if let Err(e) = move_file(path, &target_dir.join(name)) {
+        warn!("Failed to move {:?} to {:?}: {}", path, target_dir, e);
+    }
+    true
+}
+
+// renames a file, copying it if the target is on another file system
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}